@@ -0,0 +1,114 @@
+use {Buffer, BufResult, Line, Point};
+use selection::SelectionSet;
+
+impl Buffer {
+    /// Returns where the abbreviation ending at `pt` starts and what it
+    /// expands to. Abbreviations have to be whole words.
+    fn abbreviation_before(&self, pt: &Point) -> Option<(Point, String)> {
+        if pt.r >= self.lines.len() || self.abbreviations.is_empty() {
+            return None;
+        }
+        let line = &self.lines[pt.r];
+        let mut c = pt.c;
+        while c > 0 && self.is_word_char(line[c - 1]) {
+            c -= 1;
+        }
+        if c == pt.c {
+            return None;
+        }
+        let word = line[c..pt.c].iter().cloned().collect::<String>();
+        self.abbreviations.get(&word).map(|expansion| (Point::new(pt.r, c), expansion.clone()))
+    }
+
+    /// Returns whether typing `string` ends a word that may need expanding.
+    fn is_separator(&self, string: &str) -> bool {
+        let mut chars = string.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => !self.is_word_char(ch),
+            _ => false
+        }
+    }
+
+    /// Expands the abbreviation ending at `pt`, if there is one, as an undo
+    /// step of its own so that undoing it brings back the unexpanded word.
+    /// Returns where the end of the word ends up.
+    fn expand_abbreviation(&mut self, pt: &Point) -> BufResult<Option<Point>> {
+        match self.abbreviation_before(pt) {
+            Some((start, expansion)) => {
+                self.replace_region(&start, pt, &expansion)?;
+                Ok(Some(start.advanced_by(&expansion)))
+            }
+            None => Ok(None)
+        }
+    }
+
+    /// Like `insert_typed`, but typing a separator after an abbreviation
+    /// also expands it. The cursor belongs after the typed text, which moves
+    /// by however much the expansion is longer than the abbreviation.
+    pub fn insert_typed_expanding(&mut self, string: &str, pt: &Point) -> BufResult<Vec<Line>> {
+        let abbreviation = if self.is_separator(string) {
+            self.abbreviation_before(pt)
+        } else {
+            None
+        };
+        let lines = self.insert_typed(string, pt)?;
+        let start = match abbreviation {
+            Some((start, _)) => start,
+            None => return Ok(lines)
+        };
+        let end = match self.expand_abbreviation(pt)? {
+            Some(end) => end,
+            None => return Ok(lines)
+        };
+        let last = end.advanced_by(string).r;
+        Ok(self.get_lines(start.r, last + 1))
+    }
+
+    /// Like `insert_at_selections`, but typing a separator after
+    /// abbreviations also expands them, as one more undo step.
+    pub fn insert_at_selections_expanding(&mut self, string: &str, sels: &mut SelectionSet)
+        -> BufResult<Vec<Line>> {
+        let lines = self.insert_at_selections(string, sels)?;
+        let expanded = self.expand_abbreviations_at(string, sels)?;
+        let rows = lines.iter().chain(expanded.iter()).map(|line| line.number);
+        match (rows.clone().min(), rows.max()) {
+            (Some(first), Some(last)) => Ok(self.get_lines(first, last + 1)),
+            _ => Ok(lines)
+        }
+    }
+
+    /// Expands abbreviations before every cursor in `sels` that `string` was
+    /// just typed after, as a single undo step.
+    fn expand_abbreviations_at(&mut self, string: &str, sels: &mut SelectionSet)
+        -> BufResult<Vec<Line>> {
+        if !self.is_separator(string) {
+            return Ok(vec![]);
+        }
+        let first_change = self.changes.len();
+        self.begin_transaction();
+        let mut result = Ok(());
+        // Work backwards so that expansions don't move the cursors still to
+        // be looked at
+        for sel in sels.selections.iter().rev() {
+            if !sel.is_empty() {
+                continue;
+            }
+            // The cursor is just after the typed character, even if it was
+            // paired or typed over a closer
+            let word_end = self.point_before(&sel.head);
+            result = self.expand_abbreviation(&word_end).map(|_| ());
+            if result.is_err() {
+                break;
+            }
+        }
+        self.end_transaction_with(result)?;
+        let edits = self.changes[first_change..].to_vec();
+        let first_row = match edits.iter().map(|edit| edit.start.r).min() {
+            Some(r) => r,
+            None => return Ok(vec![])
+        };
+        sels.shift(&edits);
+        let last_row = sels.selections.iter().map(|sel| sel.head.r).max().unwrap_or(first_row);
+        Ok(self.get_lines(first_row, last_row + 1))
+    }
+}
@@ -0,0 +1,124 @@
+use std::cmp;
+use std::iter;
+
+use {Buffer, BufErr, BufResult, Line, Point};
+use column::char_col;
+
+/// The rectangle with corners at two points. Columns are visual, so tabs and
+/// wide characters count as many columns as they take up on screen.
+#[derive(Debug, PartialEq)]
+struct Block {
+    top: usize,
+    bottom: usize,
+    left: usize,
+    right: usize
+}
+
+impl Buffer {
+    fn block(&self, start: &Point, end: &Point) -> BufResult<Block> {
+        if !self.is_valid_point(start) {
+            return Err(BufErr::InvalidStartPoint);
+        }
+        if !self.is_valid_point(end) {
+            return Err(BufErr::InvalidEndPoint);
+        }
+        let start_col = self.visual_col_at(start);
+        let end_col = self.visual_col_at(end);
+        Ok(Block {
+            top: cmp::min(start.r, end.r),
+            bottom: cmp::min(cmp::max(start.r, end.r), self.lines.len().saturating_sub(1)),
+            left: cmp::min(start_col, end_col),
+            right: cmp::max(start_col, end_col)
+        })
+    }
+
+    /// Returns the character range of row `r` that lies inside `block`.
+    fn block_range(&self, block: &Block, r: usize) -> (usize, usize) {
+        let line = &self.lines[r];
+        let tab_width = self.options.tab_width;
+        (char_col(line, block.left, tab_width).0, char_col(line, block.right, tab_width).0)
+    }
+
+    /// Returns the text of each row in the rectangle between `start` and `end`.
+    /// Rows that end before the rectangle give empty strings.
+    pub fn block_to_strs(&self, start: &Point, end: &Point) -> BufResult<Vec<String>> {
+        let block = self.block(start, end)?;
+        if block.top >= self.lines.len() {
+            return Ok(vec![]);
+        }
+        Ok((block.top..block.bottom + 1).map(|r| {
+            let (from, to) = self.block_range(&block, r);
+            self.lines[r][from..to].iter().cloned().collect()
+        }).collect())
+    }
+
+    /// Deletes the rectangle between `start` and `end` as a single undo step.
+    pub fn delete_block(&mut self, start: &Point, end: &Point) -> BufResult<Vec<Line>> {
+        let block = self.block(start, end)?;
+        if block.top >= self.lines.len() {
+            return Ok(vec![]);
+        }
+        self.begin_transaction();
+        let mut result = Ok(());
+        for r in block.top..block.bottom + 1 {
+            let (from, to) = self.block_range(&block, r);
+            if let Err(err) = self.delete_region(&Point::new(r, from), &Point::new(r, to)) {
+                result = Err(err);
+                break;
+            }
+        }
+        self.end_transaction_with(result)?;
+        Ok(self.get_lines(block.top, block.bottom + 1))
+    }
+
+    /// Inserts `string` at the left edge of the rectangle between `start` and
+    /// `end` on every row it covers, padding short rows with spaces.
+    pub fn insert_at_block(&mut self, string: &str, start: &Point, end: &Point)
+        -> BufResult<Vec<Line>> {
+        let block = self.block(start, end)?;
+        let strings = iter::repeat(string.to_string())
+            .take(block.bottom + 1 - block.top)
+            .collect::<Vec<_>>();
+        self.paste_block(&strings, block.top, block.left)
+    }
+
+    /// Pastes `strings` as a rectangle whose top left corner is at `pt`,
+    /// padding short rows with spaces and adding rows past the end of the
+    /// buffer as needed. The paste is a single undo step.
+    pub fn insert_block(&mut self, strings: &[String], pt: &Point) -> BufResult<Vec<Line>> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        let col = self.visual_col_at(pt);
+        self.paste_block(strings, pt.r, col)
+    }
+
+    fn paste_block(&mut self, strings: &[String], top: usize, col: usize)
+        -> BufResult<Vec<Line>> {
+        if strings.iter().any(|s| s.contains('\n')) {
+            return Err(BufErr::InvalidBlock);
+        }
+        self.begin_transaction();
+        let mut result = Ok(());
+        for (i, string) in strings.iter().enumerate() {
+            let r = top + i;
+            let (c, pad) = if r < self.lines.len() {
+                char_col(&self.lines[r], col, self.options.tab_width)
+            } else {
+                (0, col)
+            };
+            let mut text = iter::repeat(' ').take(pad).collect::<String>();
+            text += string;
+            if r >= self.lines.len() {
+                text.push('\n');
+            }
+            let pt = Point::new(cmp::min(r, self.lines.len()), c);
+            if let Err(err) = self.insert_at_pt(&text, &pt) {
+                result = Err(err);
+                break;
+            }
+        }
+        self.end_transaction_with(result)?;
+        Ok(self.get_lines(top, top + strings.len()))
+    }
+}
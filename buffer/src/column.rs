@@ -0,0 +1,97 @@
+use std::cmp;
+
+use {Buffer, Point};
+
+// Combining marks and other characters drawn on top of the one before them
+const ZERO_WIDTH: [(u32, u32); 22] = [
+    (0x0300, 0x036F), (0x0483, 0x0489), (0x0591, 0x05BD), (0x05BF, 0x05C7),
+    (0x0610, 0x061A), (0x064B, 0x065F), (0x0670, 0x0670), (0x06D6, 0x06ED),
+    (0x0900, 0x0903), (0x093A, 0x094F), (0x0E31, 0x0E3A), (0x0E47, 0x0E4E),
+    (0x1AB0, 0x1AFF), (0x1DC0, 0x1DFF), (0x200B, 0x200F), (0x202A, 0x202E),
+    (0x2060, 0x2064), (0x20D0, 0x20FF), (0x302A, 0x302F), (0x3099, 0x309A),
+    (0xFE00, 0xFE0F), (0xFE20, 0xFE2F)
+];
+
+// East Asian wide and fullwidth characters, and emoji
+const DOUBLE_WIDTH: [(u32, u32); 16] = [
+    (0x1100, 0x115F), (0x2E80, 0x303E), (0x3041, 0x33FF), (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF), (0xA000, 0xA4CF), (0xA960, 0xA97F), (0xAC00, 0xD7A3),
+    (0xF900, 0xFAFF), (0xFE30, 0xFE4F), (0xFF00, 0xFF60), (0xFFE0, 0xFFE6),
+    (0x1F300, 0x1F64F), (0x1F900, 0x1F9FF), (0x20000, 0x2FFFD), (0x30000, 0x3FFFD)
+];
+
+fn in_ranges(ch: char, ranges: &[(u32, u32)]) -> bool {
+    let ch = ch as u32;
+    ranges.iter().any(|&(first, last)| first <= ch && ch <= last)
+}
+
+/// Returns the number of columns `ch` takes up on screen, other than for tabs,
+/// whose width depends on where they are.
+pub fn char_width(ch: char) -> usize {
+    if in_ranges(ch, &ZERO_WIDTH) {
+        0
+    } else if in_ranges(ch, &DOUBLE_WIDTH) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Returns the visual column after `ch` if it starts at visual column `col`.
+pub fn advance(col: usize, ch: char, tab_width: usize) -> usize {
+    if ch == '\t' {
+        col + tab_width - col % tab_width
+    } else {
+        col + char_width(ch)
+    }
+}
+
+/// Returns the visual column that character `c` of `line` starts at.
+pub fn visual_col(line: &[char], c: usize, tab_width: usize) -> usize {
+    line[..c].iter().fold(0, |col, &ch| advance(col, ch, tab_width))
+}
+
+/// Returns the index of the first character in `line` that starts at or after
+/// visual column `col`, along with the number of spaces needed to reach `col`
+/// if the line is too short.
+pub fn char_col(line: &[char], col: usize, tab_width: usize) -> (usize, usize) {
+    let mut vcol = 0;
+    for (i, &ch) in line.iter().enumerate() {
+        if vcol >= col {
+            return (i, 0);
+        }
+        vcol = advance(vcol, ch, tab_width);
+    }
+    (line.len(), col.saturating_sub(vcol))
+}
+
+impl Buffer {
+    /// Returns the visual column `pt` is at, with tabs taking up to
+    /// `options.tab_width` columns and wide characters two.
+    pub fn visual_col_at(&self, pt: &Point) -> usize {
+        if pt.r < self.lines.len() {
+            let c = cmp::min(pt.c, self.lines[pt.r].len());
+            visual_col(&self.lines[pt.r], c, self.options.tab_width)
+        } else {
+            0
+        }
+    }
+
+    /// Returns the point on row `r` at the character drawn over visual column
+    /// `col`, or the end of the row if it's too short.
+    pub fn point_at_visual_col(&self, r: usize, col: usize) -> Point {
+        if r >= self.lines.len() {
+            return Point::new(r, 0);
+        }
+        let line = &self.lines[r];
+        let mut vcol = 0;
+        for (i, &ch) in line.iter().enumerate() {
+            let next = advance(vcol, ch, self.options.tab_width);
+            if next > col {
+                return Point::new(r, i);
+            }
+            vcol = next;
+        }
+        Point::new(r, line.len())
+    }
+}
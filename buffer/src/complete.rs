@@ -0,0 +1,246 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use {Buffer, BufErr, BufResult, Edit, Point};
+use observe::{ChangeEvent, Follow};
+
+/// Candidates for completing the word before a point, best first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Completions {
+    /// Where the word being completed starts. Candidates replace the text
+    /// from here up to the point.
+    pub start: Point,
+    pub candidates: Vec<String>
+}
+
+/// Where a word was last seen, for ranking candidates.
+#[derive(Clone, Copy)]
+struct WordStats {
+    count: usize,
+    // The latest tick of any row containing the word
+    last_edited: u64
+}
+
+/// The words on every row of a buffer, recomputed only for rows that have
+/// changed.
+pub struct WordIndex {
+    word_chars: String,
+    rows: Vec<Option<Vec<String>>>,
+    // When each row was last edited, counting edits since the index was made
+    row_ticks: Vec<u64>,
+    tick: u64,
+    words: HashMap<String, WordStats>
+}
+
+impl WordIndex {
+    pub fn new() -> WordIndex {
+        WordIndex {
+            word_chars: String::new(),
+            rows: vec![],
+            row_ticks: vec![],
+            tick: 0,
+            words: HashMap::new()
+        }
+    }
+
+    fn forget_row(&mut self, r: usize) {
+        if let Some(words) = self.rows[r].take() {
+            for word in words {
+                let remove = match self.words.get_mut(&word) {
+                    Some(stats) => {
+                        stats.count -= 1;
+                        stats.count == 0
+                    }
+                    None => false
+                };
+                if remove {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Throws away the words on the rows `edit` changes, which may not have
+    /// been applied to the buffer yet.
+    pub fn edit(&mut self, edit: &Edit) {
+        self.tick += 1;
+        let start = edit.start.r;
+        if start >= self.rows.len() {
+            return;
+        }
+        let last = cmp::min(edit.old_end().r, self.rows.len() - 1);
+        for r in start..last + 1 {
+            self.forget_row(r);
+        }
+        let added = edit.new_end().r - start;
+        self.rows.drain(start + 1..last + 1);
+        self.row_ticks.drain(start + 1..last + 1);
+        for _ in 0..added {
+            self.rows.insert(start + 1, None);
+            self.row_ticks.insert(start + 1, 0);
+        }
+        for r in start..cmp::min(start + added + 1, self.rows.len()) {
+            self.row_ticks[r] = self.tick;
+        }
+    }
+
+    fn update(&mut self, buffer: &Buffer) {
+        if self.word_chars != buffer.options.word_chars {
+            *self = WordIndex::new();
+            self.word_chars = buffer.options.word_chars.clone();
+        }
+        let n_rows = buffer.lines.len();
+        if self.rows.len() > n_rows {
+            for r in n_rows..self.rows.len() {
+                self.forget_row(r);
+            }
+        }
+        self.rows.resize(n_rows, None);
+        self.row_ticks.resize(n_rows, 0);
+        for r in 0..n_rows {
+            if self.rows[r].is_some() {
+                continue;
+            }
+            let words = buffer.words_in_row(r);
+            for word in &words {
+                let tick = self.row_ticks[r];
+                let stats = self.words.entry(word.clone()).or_insert(WordStats {
+                    count: 0,
+                    last_edited: tick
+                });
+                stats.count += 1;
+                stats.last_edited = cmp::max(stats.last_edited, tick);
+            }
+            self.rows[r] = Some(words);
+        }
+    }
+}
+
+impl Follow for WordIndex {
+    fn follow(&mut self, event: &ChangeEvent) {
+        self.edit(&event.edit());
+    }
+}
+
+/// How well `candidate` matches `prefix`: an exact prefix, a prefix
+/// ignoring case, or the letters of `prefix` in order with the number of
+/// characters skipped to find them. Returns None if it doesn't match.
+fn match_quality(candidate: &str, prefix: &str) -> Option<(usize, usize)> {
+    if candidate.starts_with(prefix) {
+        return Some((0, 0));
+    }
+    let candidate = candidate.to_lowercase().chars().collect::<Vec<_>>();
+    let prefix = prefix.to_lowercase().chars().collect::<Vec<_>>();
+    if candidate.starts_with(&prefix) {
+        return Some((1, 0));
+    }
+    // The first letter has to match so that fuzzy matches stay relevant
+    if candidate.first() != prefix.first() {
+        return None;
+    }
+    let mut skipped = 0;
+    let mut i = 0;
+    for ch in prefix {
+        while i < candidate.len() && candidate[i] != ch {
+            i += 1;
+            skipped += 1;
+        }
+        if i == candidate.len() {
+            return None;
+        }
+        i += 1;
+    }
+    Some((2, skipped))
+}
+
+impl Buffer {
+    fn words_in_row(&self, r: usize) -> Vec<String> {
+        let line = &self.lines[r];
+        let mut words = vec![];
+        let mut c = 0;
+        while c < line.len() {
+            if !self.is_word_char(line[c]) {
+                c += 1;
+                continue;
+            }
+            let start = c;
+            while c < line.len() && self.is_word_char(line[c]) {
+                c += 1;
+            }
+            words.push(line[start..c].iter().cloned().collect());
+        }
+        words
+    }
+
+    /// Returns the start of the word ending at `pt`.
+    fn word_start(&self, pt: &Point) -> Point {
+        let mut c = pt.c;
+        while c > 0 && self.is_word_char(self.lines[pt.r][c - 1]) {
+            c -= 1;
+        }
+        Point::new(pt.r, c)
+    }
+
+    /// Returns up to `limit` words from this buffer and `others` that could
+    /// complete the word before `pt`. Words starting with it come first,
+    /// then those that do ignoring case, then those containing its letters
+    /// in order. Within each group, words on recently edited rows and then
+    /// common words rank higher.
+    pub fn completions(&self, pt: &Point, others: &[&Buffer], limit: usize)
+        -> BufResult<Completions> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        let start = if pt.r < self.lines.len() { self.word_start(pt) } else { *pt };
+        let prefix = self.region_to_str(&start, pt)?;
+        let mut completions = Completions {
+            start: start,
+            candidates: vec![]
+        };
+        if prefix.is_empty() {
+            return Ok(completions);
+        }
+        // The word the cursor is in shouldn't suggest itself
+        let mut end = *pt;
+        while self.char_at(&end).map_or(false, |ch| self.is_word_char(ch)) {
+            end.c += 1;
+        }
+        let current = self.region_to_str(&start, &end)?;
+
+        let mut ranked: HashMap<String, ((usize, usize), WordStats)> = HashMap::new();
+        for (i, buffer) in Some(self).into_iter().chain(others.iter().cloned()).enumerate() {
+            let mut index = buffer.word_index.borrow_mut();
+            let index = &mut *index;
+            index.update(buffer);
+            for (word, stats) in &index.words {
+                if *word == prefix || (i == 0 && *word == current && stats.count == 1) {
+                    continue;
+                }
+                let quality = match match_quality(word, &prefix) {
+                    Some(quality) => quality,
+                    None => continue
+                };
+                let entry = ranked.entry(word.clone()).or_insert((quality, WordStats {
+                    count: 0,
+                    last_edited: 0
+                }));
+                entry.1.count += stats.count;
+                // Recency only means anything within this buffer
+                if i == 0 {
+                    entry.1.last_edited = stats.last_edited;
+                }
+            }
+        }
+        let mut ranked = ranked.into_iter().collect::<Vec<_>>();
+        ranked.sort_by(|a, b| {
+            let (&(ref word_a, (quality_a, stats_a)), &(ref word_b, (quality_b, stats_b))) = (a, b);
+            quality_a.cmp(&quality_b)
+                .then(stats_b.last_edited.cmp(&stats_a.last_edited))
+                .then(stats_b.count.cmp(&stats_a.count))
+                .then(word_a.len().cmp(&word_b.len()))
+                .then(word_a.cmp(word_b))
+        });
+        completions.candidates = ranked.into_iter().take(limit).map(|(word, _)| word).collect();
+        Ok(completions)
+    }
+}
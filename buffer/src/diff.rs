@@ -0,0 +1,544 @@
+use std::cmp;
+
+use {Buffer, BufErr, BufResult, Line, Point};
+
+const CONFLICT_START: &'static str = "<<<<<<< buffer";
+const CONFLICT_MIDDLE: &'static str = "=======";
+const CONFLICT_END: &'static str = ">>>>>>> file";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    #[serde(rename = "equal")]
+    Equal,
+    #[serde(rename = "delete")]
+    Delete,
+    #[serde(rename = "insert")]
+    Insert
+}
+
+/// A run of text that's in both buffers, or only in the old or new one.
+/// `old_start` and `new_start` are where it starts, or would start, in each.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    #[serde(rename = "oldStart")]
+    pub old_start: Point,
+    #[serde(rename = "newStart")]
+    pub new_start: Point,
+    pub text: String
+}
+
+/// Returns the index of diagonal `k` in Myers' `v`, which covers diagonals
+/// -`max` - 1 to `max` + 1.
+fn diagonal(k: isize, max: usize) -> usize {
+    (k + max as isize + 1) as usize
+}
+
+/// Returns whether the furthest path on diagonal `k` after `d` steps comes
+/// from diagonal `k` + 1, going down, rather than `k` - 1, going right.
+fn from_above(v: &[isize], k: isize, d: isize, max: usize) -> bool {
+    k == -d || (k != d && v[diagonal(k - 1, max)] < v[diagonal(k + 1, max)])
+}
+
+/// Finds the shortest edit script turning `a` into `b` with Myers'
+/// algorithm, one step per element.
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<ChangeKind> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = a.len() + b.len();
+    let mut v = vec![0isize; 2 * max + 3];
+    // The furthest point reached on each diagonal before every round
+    let mut trace = vec![];
+    'rounds: for d in 0..(max as isize + 1) {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let mut x = if from_above(&v, k, d, max) {
+                v[diagonal(k + 1, max)]
+            } else {
+                v[diagonal(k - 1, max)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[diagonal(k, max)] = x;
+            if x >= n && y >= m {
+                break 'rounds;
+            }
+            k += 2;
+        }
+    }
+
+    let mut script = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if from_above(v, k, d, max) { k + 1 } else { k - 1 };
+        let prev_x = v[diagonal(prev_k, max)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            script.push(ChangeKind::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            script.push(if x == prev_x { ChangeKind::Insert } else { ChangeKind::Delete });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    script.reverse();
+    script
+}
+
+/// Returns the steps turning `a` into `b`, with deletions before insertions
+/// wherever they're next to each other.
+fn edit_script<T: PartialEq>(a: &[T], b: &[T]) -> Vec<ChangeKind> {
+    // Most diffs are small, so don't make Myers look at what's the same
+    let prefix = a.iter().zip(b).take_while(|&(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev())
+        .take_while(|&(x, y)| x == y)
+        .count();
+    let mut script = vec![ChangeKind::Equal; prefix];
+    script.extend(myers(&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]));
+    script.extend(vec![ChangeKind::Equal; suffix]);
+    let mut i = 0;
+    while i < script.len() {
+        let len = script[i..].iter().take_while(|&&kind| kind != ChangeKind::Equal).count();
+        script[i..i + len].sort_by_key(|&kind| kind != ChangeKind::Delete);
+        i += cmp::max(len, 1);
+    }
+    script
+}
+
+fn row_text(rows: &[Vec<char>]) -> String {
+    rows.iter().flat_map(|row| row.iter().cloned().chain(Some('\n'))).collect()
+}
+
+/// Adds `change` to `changes`, merging it into the last one if they're the
+/// same kind.
+fn push_change(changes: &mut Vec<Change>, change: Change) {
+    if change.text.is_empty() {
+        return;
+    }
+    if let Some(last) = changes.last_mut() {
+        if last.kind == change.kind {
+            last.text += &change.text;
+            return;
+        }
+    }
+    changes.push(change);
+}
+
+/// A hunk of a unified diff. Rows are where it applies in the old text.
+struct Hunk {
+    row: usize,
+    old: Vec<Vec<char>>,
+    new: Vec<Vec<char>>
+}
+
+/// Returns the hunks turning rows `old` into `new`.
+fn hunks_between(old: &[Vec<char>], new: &[Vec<char>]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = vec![];
+    let (mut r, mut s) = (0, 0);
+    let mut in_hunk = false;
+    for kind in edit_script(old, new) {
+        if kind == ChangeKind::Equal {
+            r += 1;
+            s += 1;
+            in_hunk = false;
+            continue;
+        }
+        if !in_hunk {
+            hunks.push(Hunk { row: r, old: vec![], new: vec![] });
+            in_hunk = true;
+        }
+        let hunk = hunks.last_mut().unwrap();
+        if kind == ChangeKind::Delete {
+            hunk.old.push(old[r].clone());
+            r += 1;
+        } else {
+            hunk.new.push(new[s].clone());
+            s += 1;
+        }
+    }
+    hunks
+}
+
+/// Returns rows `start` to `end` of `base` with `hunks`, which all fall
+/// within them, applied.
+fn apply_within(base: &[Vec<char>], start: usize, end: usize, hunks: &[Hunk]) -> Vec<Vec<char>> {
+    let mut rows = vec![];
+    let mut pos = start;
+    for hunk in hunks {
+        rows.extend_from_slice(&base[pos..hunk.row]);
+        rows.extend_from_slice(&hunk.new);
+        pos = hunk.row + hunk.old.len();
+    }
+    rows.extend_from_slice(&base[pos..end]);
+    rows
+}
+
+/// Parses a hunk header's range, e.g. `-12,3`, into a start and a count.
+fn parse_range(range: &str, sign: char) -> BufResult<(usize, usize)> {
+    if !range.starts_with(sign) {
+        return Err(BufErr::InvalidPatch);
+    }
+    let mut parts = range[1..].splitn(2, ',');
+    let start = parts.next().and_then(|start| start.parse().ok());
+    let count = match parts.next() {
+        Some(count) => count.parse().ok(),
+        None => Some(1)
+    };
+    match (start, count) {
+        (Some(start), Some(count)) => Ok((start, count)),
+        _ => Err(BufErr::InvalidPatch)
+    }
+}
+
+fn parse_patch(patch: &str) -> BufResult<Vec<Hunk>> {
+    let mut hunks: Vec<Hunk> = vec![];
+    let mut lines = patch.lines().peekable();
+    while let Some(line) = lines.next() {
+        // Anything between hunks, like file names, is ignored
+        if !line.starts_with("@@ ") {
+            continue;
+        }
+        let mut fields = line.split(' ').skip(1);
+        let (old_start, old_count) = parse_range(fields.next().unwrap_or(""), '-')?;
+        let (_, new_count) = parse_range(fields.next().unwrap_or(""), '+')?;
+        if fields.next() != Some("@@") {
+            return Err(BufErr::InvalidPatch);
+        }
+        let mut hunk = Hunk {
+            row: if old_count == 0 { old_start } else { old_start.saturating_sub(1) },
+            old: vec![],
+            new: vec![]
+        };
+        while hunk.old.len() < old_count || hunk.new.len() < new_count {
+            let line = match lines.next() {
+                Some(line) => line,
+                None => return Err(BufErr::InvalidPatch)
+            };
+            let mut chars = line.chars();
+            let (old, new) = match chars.next() {
+                // Some tools strip the space from empty context lines
+                Some(' ') | None => (true, true),
+                Some('-') => (true, false),
+                Some('+') => (false, true),
+                Some('\\') => continue,
+                Some(_) => return Err(BufErr::InvalidPatch)
+            };
+            let row = chars.collect::<Vec<_>>();
+            if old {
+                hunk.old.push(row.clone());
+            }
+            if new {
+                hunk.new.push(row);
+            }
+        }
+        if hunk.old.len() != old_count || hunk.new.len() != new_count {
+            return Err(BufErr::InvalidPatch);
+        }
+        if let Some(last) = hunks.last() {
+            if hunk.row < last.row + last.old.len() {
+                return Err(BufErr::InvalidPatch);
+            }
+        }
+        // A missing newline at the end of the file is what the buffer adds
+        while lines.peek().map_or(false, |line| line.starts_with('\\')) {
+            lines.next();
+        }
+        hunks.push(hunk);
+    }
+    if hunks.is_empty() {
+        return Err(BufErr::InvalidPatch);
+    }
+    Ok(hunks)
+}
+
+/// Formats a hunk header range the way diff does, leaving out a count of 1.
+fn format_range(start: usize, count: usize) -> String {
+    if count == 1 { start.to_string() } else { format!("{},{}", start, count) }
+}
+
+impl Buffer {
+    /// Replaces `n_old` rows starting at `row` with `rows`. The buffer's last
+    /// row ends in a newline that can't be deleted or added to, so rows
+    /// going at the end are joined on to the one before instead.
+    fn splice_rows(&mut self, row: usize, n_old: usize, rows: &[Vec<char>]) -> BufResult<()> {
+        let joined = rows.iter()
+            .map(|row| row.iter().cloned().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let end_of = |buf: &Buffer, r: usize| Point::new(r, buf.lines[r].len());
+        match (n_old, rows.len()) {
+            (0, 0) => Ok(()),
+            (_, 0) if row + n_old < self.lines.len() || row == 0 => {
+                let end = if row + n_old < self.lines.len() {
+                    Point::new(row + n_old, 0)
+                } else {
+                    end_of(self, row + n_old - 1)
+                };
+                self.delete_region(&Point::new(row, 0), &end).map(|_| ())
+            }
+            (_, 0) => {
+                let (start, end) = (end_of(self, row - 1), end_of(self, row + n_old - 1));
+                self.delete_region(&start, &end).map(|_| ())
+            }
+            (0, _) if row < self.lines.len() => {
+                self.insert_at_pt(&(joined + "\n"), &Point::new(row, 0)).map(|_| ())
+            }
+            (0, _) if row > 0 => {
+                let end = end_of(self, row - 1);
+                self.insert_at_pt(&("\n".to_string() + &joined), &end).map(|_| ())
+            }
+            (0, _) => self.insert_at_pt(&joined, &Point::new(0, 0)).map(|_| ()),
+            _ => {
+                let end = end_of(self, row + n_old - 1);
+                self.replace_region(&Point::new(row, 0), &end, &joined).map(|_| ())
+            }
+        }
+    }
+
+    /// Returns the rows that are the same in this buffer and `other`, and
+    /// the rows that have to be deleted from this one and inserted from
+    /// `other` to turn it into `other`.
+    pub fn diff_lines(&self, other: &Buffer) -> Vec<Change> {
+        let script = edit_script(&self.lines, &other.lines);
+        let mut changes = vec![];
+        let (mut r, mut s) = (0, 0);
+        for kind in script {
+            let (old_start, new_start) = (Point::new(r, 0), Point::new(s, 0));
+            let text = match kind {
+                ChangeKind::Equal => {
+                    r += 1;
+                    s += 1;
+                    row_text(&self.lines[r - 1..r])
+                }
+                ChangeKind::Delete => {
+                    r += 1;
+                    row_text(&self.lines[r - 1..r])
+                }
+                ChangeKind::Insert => {
+                    s += 1;
+                    row_text(&other.lines[s - 1..s])
+                }
+            };
+            push_change(&mut changes, Change {
+                kind: kind,
+                old_start: old_start,
+                new_start: new_start,
+                text: text
+            });
+        }
+        changes
+    }
+
+    /// Like diff_lines(), but rows that were changed rather than deleted or
+    /// inserted outright are compared character by character.
+    pub fn diff_chars(&self, other: &Buffer) -> Vec<Change> {
+        let mut changes = vec![];
+        let lines = self.diff_lines(other);
+        let mut i = 0;
+        while i < lines.len() {
+            let (deleted, inserted) = match (&lines[i], lines.get(i + 1)) {
+                (deleted, Some(inserted)) if deleted.kind == ChangeKind::Delete &&
+                    inserted.kind == ChangeKind::Insert => (deleted, inserted),
+                (change, _) => {
+                    push_change(&mut changes, change.clone());
+                    i += 1;
+                    continue;
+                }
+            };
+            let old = deleted.text.chars().collect::<Vec<_>>();
+            let new = inserted.text.chars().collect::<Vec<_>>();
+            let (mut old_pt, mut new_pt) = (deleted.old_start, deleted.new_start);
+            let (mut x, mut y) = (0, 0);
+            for kind in edit_script(&old, &new) {
+                let ch = if kind == ChangeKind::Insert { new[y] } else { old[x] };
+                push_change(&mut changes, Change {
+                    kind: kind,
+                    old_start: old_pt,
+                    new_start: new_pt,
+                    text: ch.to_string()
+                });
+                let advance = |pt: &mut Point| {
+                    *pt = if ch == '\n' {
+                        Point::new(pt.r + 1, 0)
+                    } else {
+                        Point::new(pt.r, pt.c + 1)
+                    };
+                };
+                if kind != ChangeKind::Insert {
+                    x += 1;
+                    advance(&mut old_pt);
+                }
+                if kind != ChangeKind::Delete {
+                    y += 1;
+                    advance(&mut new_pt);
+                }
+            }
+            i += 2;
+        }
+        changes
+    }
+
+    /// Returns a unified diff turning this buffer into `other`, with
+    /// `context` unchanged rows around each change, or an empty string if
+    /// they're the same.
+    pub fn unified_diff(&self, other: &Buffer, old_name: &str, new_name: &str, context: usize)
+        -> String {
+        let script = edit_script(&self.lines, &other.lines);
+        // Where each step starts in the old and new rows
+        let mut rows = vec![(0, 0)];
+        for &kind in &script {
+            let (r, s) = rows[rows.len() - 1];
+            rows.push(match kind {
+                ChangeKind::Equal => (r + 1, s + 1),
+                ChangeKind::Delete => (r + 1, s),
+                ChangeKind::Insert => (r, s + 1)
+            });
+        }
+        let changed = (0..script.len())
+            .filter(|&i| script[i] != ChangeKind::Equal)
+            .collect::<Vec<_>>();
+        if changed.is_empty() {
+            return String::new();
+        }
+
+        let mut diff = format!("--- {}\n+++ {}\n", old_name, new_name);
+        let mut i = 0;
+        while i < changed.len() {
+            // Changes close enough for their context to touch share a hunk
+            let mut j = i;
+            while j + 1 < changed.len() && changed[j + 1] - changed[j] - 1 <= 2 * context {
+                j += 1;
+            }
+            let start = changed[i].saturating_sub(context);
+            let end = cmp::min(changed[j] + context + 1, script.len());
+            let (r, s) = rows[start];
+            let (old_count, new_count) = (rows[end].0 - r, rows[end].1 - s);
+            diff += &format!("@@ -{} +{} @@\n",
+                             format_range(if old_count == 0 { r } else { r + 1 }, old_count),
+                             format_range(if new_count == 0 { s } else { s + 1 }, new_count));
+            for step in start..end {
+                let (r, s) = rows[step];
+                let (sign, row) = match script[step] {
+                    ChangeKind::Equal => (' ', &self.lines[r]),
+                    ChangeKind::Delete => ('-', &self.lines[r]),
+                    ChangeKind::Insert => ('+', &other.lines[s])
+                };
+                diff.push(sign);
+                diff.extend(row.iter());
+                diff.push('\n');
+            }
+            i = j + 1;
+        }
+        diff
+    }
+
+    /// Applies a unified diff to the buffer as a single undo step. Nothing
+    /// is changed if any hunk's old rows aren't where it says they are.
+    /// Returns the rows from the first change to the end of the last.
+    pub fn apply_patch(&mut self, patch: &str) -> BufResult<Vec<Line>> {
+        let hunks = parse_patch(patch)?;
+        for (i, hunk) in hunks.iter().enumerate() {
+            let end = hunk.row + hunk.old.len();
+            if end > self.lines.len() || self.lines[hunk.row..end] != hunk.old[..] {
+                return Err(BufErr::PatchConflict(i + 1));
+            }
+        }
+        self.apply_hunks(&hunks)
+    }
+
+    /// Makes the buffer's contents the same as `other`'s as a single undo
+    /// step, only touching the rows that differ. Returns the rows from the
+    /// first change to the end of the last.
+    pub fn replace_contents(&mut self, other: &Buffer) -> BufResult<Vec<Line>> {
+        let hunks = hunks_between(&self.lines, &other.lines);
+        self.apply_hunks(&hunks)
+    }
+
+    /// Merges the changes made to `base` on disk, which turned it into
+    /// `theirs`, with the changes made to it in this buffer, as a single undo
+    /// step. Where both changed the same rows differently, both versions are
+    /// kept between conflict markers. Returns the rows from the first change
+    /// to the end of the last, and the rows each conflict starts on.
+    pub fn merge(&mut self, base: &Buffer, theirs: &Buffer) -> BufResult<(Vec<Line>, Vec<usize>)> {
+        let ours = hunks_between(&base.lines, &self.lines);
+        let theirs = hunks_between(&base.lines, &theirs.lines);
+        let base = &base.lines;
+        let mut merged = vec![];
+        let mut conflicts = vec![];
+        let mut pos = 0;
+        let (mut i, mut j) = (0, 0);
+        while i < ours.len() || j < theirs.len() {
+            // Changes that overlap or touch are merged together
+            let (first_i, first_j) = (i, j);
+            let mut end = 0;
+            let start = match (ours.get(i), theirs.get(j)) {
+                (Some(a), Some(b)) => cmp::min(a.row, b.row),
+                (Some(a), None) => a.row,
+                (None, Some(b)) => b.row,
+                (None, None) => unreachable!()
+            };
+            loop {
+                if i < ours.len() && ours[i].row <= cmp::max(end, start) {
+                    end = cmp::max(end, ours[i].row + ours[i].old.len());
+                    i += 1;
+                } else if j < theirs.len() && theirs[j].row <= cmp::max(end, start) {
+                    end = cmp::max(end, theirs[j].row + theirs[j].old.len());
+                    j += 1;
+                } else {
+                    break;
+                }
+            }
+            merged.extend_from_slice(&base[pos..start]);
+            let our_rows = apply_within(base, start, end, &ours[first_i..i]);
+            let their_rows = apply_within(base, start, end, &theirs[first_j..j]);
+            if first_j == j || our_rows == their_rows {
+                merged.extend(our_rows);
+            } else if first_i == i {
+                merged.extend(their_rows);
+            } else {
+                conflicts.push(merged.len());
+                merged.push(CONFLICT_START.chars().collect());
+                merged.extend(our_rows);
+                merged.push(CONFLICT_MIDDLE.chars().collect());
+                merged.extend(their_rows);
+                merged.push(CONFLICT_END.chars().collect());
+            }
+            pos = end;
+        }
+        merged.extend_from_slice(&base[pos..]);
+        let hunks = hunks_between(&self.lines, &merged);
+        Ok((self.apply_hunks(&hunks)?, conflicts))
+    }
+
+    /// Applies `hunks`, which must be in order and not overlap, as a single
+    /// undo step.
+    fn apply_hunks(&mut self, hunks: &[Hunk]) -> BufResult<Vec<Line>> {
+        if hunks.is_empty() {
+            return Ok(vec![]);
+        }
+        let first = hunks[0].row;
+        let last = hunks[hunks.len() - 1].row;
+        let added = hunks.iter().map(|hunk| hunk.new.len() as isize - hunk.old.len() as isize)
+            .sum::<isize>();
+        let end = (last as isize + hunks[hunks.len() - 1].old.len() as isize + added) as usize;
+        self.begin_transaction();
+        let mut result = Ok(());
+        // Work backwards so that earlier hunks' rows don't move
+        for hunk in hunks.iter().rev() {
+            result = self.splice_rows(hunk.row, hunk.old.len(), &hunk.new);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.end_transaction_with(result)?;
+        Ok(self.get_lines(first, cmp::max(end, first + 1)))
+    }
+}
@@ -0,0 +1,133 @@
+use Buffer;
+use syntax::{SyntaxTree, NodeKind};
+
+// Same as Vim's default foldmarker
+const FOLD_START_MARKER: &'static str = "{{{";
+const FOLD_END_MARKER: &'static str = "}}}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FoldKind {
+    #[serde(rename = "indent")]
+    Indent,
+    /// A multi-line bracketed expression or comment
+    #[serde(rename = "syntax")]
+    Syntax,
+    /// Between lines containing `{{{` and `}}}`
+    #[serde(rename = "marker")]
+    Marker
+}
+
+/// Rows `start` through `end` that can be folded, leaving `start` visible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FoldingRange {
+    pub start: usize,
+    pub end: usize,
+    pub kind: FoldKind
+}
+
+fn fold(start: usize, end: usize, kind: FoldKind) -> FoldingRange {
+    FoldingRange {
+        start: start,
+        end: end,
+        kind: kind
+    }
+}
+
+impl Buffer {
+    /// Returns every foldable range, ordered by start row and then with the
+    /// outermost first. Where folds of different kinds cover the same rows,
+    /// only the first of marker, syntax and indent is kept.
+    pub fn folding_ranges(&self) -> Vec<FoldingRange> {
+        let mut folds = self.marker_folds();
+        self.syntax_folds(&self.syntax_tree(), &mut folds);
+        folds.extend(self.indent_folds());
+        let mut result: Vec<FoldingRange> = vec![];
+        for f in folds {
+            if !result.iter().any(|other| other.start == f.start && other.end == f.end) {
+                result.push(f);
+            }
+        }
+        result.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+        result
+    }
+
+    fn line_contains(&self, r: usize, s: &str) -> bool {
+        let n = s.chars().count();
+        self.lines[r].windows(n).any(|chars| chars.iter().cloned().eq(s.chars()))
+    }
+
+    fn is_marker_line(&self, r: usize) -> bool {
+        self.line_contains(r, FOLD_START_MARKER) || self.line_contains(r, FOLD_END_MARKER)
+    }
+
+    fn marker_folds(&self) -> Vec<FoldingRange> {
+        let mut folds = vec![];
+        let mut open = vec![];
+        for r in 0..self.lines.len() {
+            if self.line_contains(r, FOLD_START_MARKER) {
+                open.push(r);
+            } else if self.line_contains(r, FOLD_END_MARKER) {
+                if let Some(start) = open.pop() {
+                    folds.push(fold(start, r, FoldKind::Marker));
+                }
+            }
+        }
+        folds
+    }
+
+    fn syntax_folds(&self, tree: &SyntaxTree, folds: &mut Vec<FoldingRange>) {
+        for child in &tree.children {
+            let node = &child.node;
+            let end = match node.kind {
+                // Fold markers aren't code even though they're made of braces
+                NodeKind::Braces if self.is_marker_line(node.start.r) => None,
+                // Leave the closing bracket visible
+                NodeKind::Parens | NodeKind::Brackets | NodeKind::Braces => {
+                    node.end.r.checked_sub(1)
+                }
+                NodeKind::Comment | NodeKind::String => Some(node.end.r),
+                _ => None
+            };
+            if let Some(end) = end {
+                if end > node.start.r && end < self.lines.len() {
+                    folds.push(fold(node.start.r, end, FoldKind::Syntax));
+                }
+            }
+            self.syntax_folds(child, folds);
+        }
+    }
+
+    fn indent_of(&self, r: usize) -> usize {
+        self.visual_col_at(&self.first_non_blank(r))
+    }
+
+    /// Folds each row followed by more indented ones, up to the last of them
+    /// that isn't blank.
+    fn indent_folds(&self) -> Vec<FoldingRange> {
+        let mut folds = vec![];
+        // Rows whose folds haven't ended yet, with their indents, which only
+        // increase from the bottom
+        let mut open: Vec<(usize, usize)> = vec![];
+        let mut last = 0;
+        for r in 0..self.lines.len() {
+            if self.is_blank_line(r) {
+                continue;
+            }
+            let indent = self.indent_of(r);
+            while open.last().map_or(false, |&(_, other)| other >= indent) {
+                let (start, _) = open.pop().unwrap();
+                if last > start {
+                    folds.push(fold(start, last, FoldKind::Indent));
+                }
+            }
+            open.push((r, indent));
+            last = r;
+        }
+        while let Some((start, _)) = open.pop() {
+            if last > start {
+                folds.push(fold(start, last, FoldKind::Indent));
+            }
+        }
+        folds
+    }
+}
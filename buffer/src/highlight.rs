@@ -0,0 +1,387 @@
+use std::cmp;
+
+use Edit;
+use observe::{ChangeEvent, Follow};
+use textmate::{Frame, Rules, TextMateGrammar, Token};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Style {
+    #[serde(rename = "keyword")]
+    Keyword,
+    #[serde(rename = "type")]
+    Type,
+    #[serde(rename = "comment")]
+    Comment,
+    #[serde(rename = "string")]
+    String,
+    #[serde(rename = "number")]
+    Number
+}
+
+/// A styled range of characters within a line.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub style: Style
+}
+
+/// Describes the tokens of a language. Anything not covered is left
+/// unstyled. A grammar loaded from TextMate's format is highlighted with its
+/// own rules instead of the keywords, comments and strings here.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Grammar {
+    pub name: String,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub types: Vec<String>,
+    #[serde(rename = "lineComment")]
+    pub line_comment: Option<String>,
+    #[serde(rename = "blockComment")]
+    pub block_comment: Option<(String, String)>,
+    /// Characters that open and close strings. Strings may span lines.
+    #[serde(rename = "stringDelimiters", default)]
+    pub string_delimiters: Vec<char>,
+    #[serde(default)]
+    pub textmate: Option<TextMateGrammar>
+}
+
+fn words(s: &str) -> Vec<String> {
+    s.split_whitespace().map(|w| w.to_string()).collect()
+}
+
+impl Grammar {
+    /// Returns one of the grammars that come with the editor.
+    pub fn builtin(name: &str) -> Option<Grammar> {
+        let (keywords, types, line_comment, block_comment, string_delimiters) = match name {
+            "rust" => (
+                "as break const continue crate else enum extern false fn for if impl in let \
+                 loop match mod move mut pub ref return self Self static struct super trait \
+                 true type unsafe use where while",
+                "bool char f32 f64 i8 i16 i32 i64 isize str u8 u16 u32 u64 usize String Vec \
+                 Option Result Box",
+                Some("//"), Some(("/*", "*/")), vec!['"']
+            ),
+            "c" => (
+                "break case const continue default do else enum extern for goto if return \
+                 sizeof static struct switch typedef union volatile while",
+                "char double float int long short signed unsigned void",
+                Some("//"), Some(("/*", "*/")), vec!['"', '\'']
+            ),
+            "python" => (
+                "and as assert break class continue def del elif else except False finally \
+                 for from global if import in is lambda None nonlocal not or pass raise return \
+                 True try while with yield",
+                "bool bytes dict float int list object set str tuple",
+                Some("#"), None, vec!['"', '\'']
+            ),
+            "javascript" => (
+                "break case catch class const continue default delete do else export extends \
+                 false finally for function if import in instanceof let new null return super \
+                 switch this throw true try typeof undefined var void while yield",
+                "Array Boolean Date Map Number Object Promise Set String",
+                Some("//"), Some(("/*", "*/")), vec!['"', '\'', '`']
+            ),
+            _ => return None
+        };
+        Some(Grammar {
+            name: name.to_string(),
+            keywords: words(keywords),
+            types: words(types),
+            line_comment: line_comment.map(|s| s.to_string()),
+            block_comment: block_comment.map(|(open, close)| (open.to_string(), close.to_string())),
+            string_delimiters: string_delimiters,
+            textmate: None
+        })
+    }
+
+    /// Makes a grammar from one in TextMate's format, named after it.
+    pub fn from_textmate(textmate: TextMateGrammar) -> Grammar {
+        Grammar {
+            name: textmate.name.clone().unwrap_or(textmate.scope_name.clone()),
+            keywords: vec![],
+            types: vec![],
+            line_comment: None,
+            block_comment: None,
+            string_delimiters: vec!['"', '\''],
+            textmate: Some(textmate)
+        }
+    }
+
+    /// Returns the builtin grammar for files with extension `ext`.
+    pub fn for_extension(ext: &str) -> Option<Grammar> {
+        match ext {
+            "rs" => Grammar::builtin("rust"),
+            "c" | "h" | "cc" | "cpp" | "hpp" => Grammar::builtin("c"),
+            "py" => Grammar::builtin("python"),
+            "js" => Grammar::builtin("javascript"),
+            _ => None
+        }
+    }
+
+    /// Highlights `line`, which starts in `state`, and returns its spans
+    /// along with the state the next line starts in.
+    pub fn highlight_line(&self, line: &[char], state: LineState) -> (Vec<Span>, LineState) {
+        let mut spans: Vec<Span> = vec![];
+        let mut state = state;
+        let mut i = 0;
+        {
+            let mut push = |start: usize, end: usize, style: Style| {
+                // Merge with the previous span if they touch
+                if let Some(last) = spans.last_mut() {
+                    if last.end == start && last.style == style {
+                        last.end = end;
+                        return;
+                    }
+                }
+                spans.push(Span {
+                    start: start,
+                    end: end,
+                    style: style
+                });
+            };
+            while i < line.len() {
+                match state {
+                    LineState::BlockComment => {
+                        let close = &self.block_comment.as_ref().unwrap().1;
+                        let end = match find(line, i, close) {
+                            Some(j) => {
+                                state = LineState::Normal;
+                                j + close.chars().count()
+                            }
+                            None => line.len()
+                        };
+                        push(i, end, Style::Comment);
+                        i = end;
+                    }
+                    LineState::String(delim) => {
+                        let mut j = i;
+                        while j < line.len() && line[j] != delim {
+                            j += if line[j] == '\\' { 2 } else { 1 };
+                        }
+                        let end = cmp::min(j + 1, line.len());
+                        if j < line.len() {
+                            state = LineState::Normal;
+                        }
+                        push(i, end, Style::String);
+                        i = end;
+                    }
+                    // Only TextMate rules leave lines inside their regions
+                    LineState::Normal | LineState::TextMate(_) => {
+                        if self.line_comment.as_ref().map_or(false, |c| starts_with(line, i, c)) {
+                            push(i, line.len(), Style::Comment);
+                            i = line.len();
+                        } else if let Some((ref open, _)) = self.block_comment {
+                            if starts_with(line, i, open) {
+                                let end = i + open.chars().count();
+                                push(i, end, Style::Comment);
+                                state = LineState::BlockComment;
+                                i = end;
+                                continue;
+                            }
+                            i = self.highlight_token(line, i, &mut state, &mut push);
+                        } else {
+                            i = self.highlight_token(line, i, &mut state, &mut push);
+                        }
+                    }
+                }
+            }
+        }
+        (spans, state)
+    }
+
+    /// Highlights the string, number or word starting at `i`, returning where
+    /// it ends.
+    fn highlight_token<F>(&self, line: &[char], i: usize, state: &mut LineState, push: &mut F)
+        -> usize where F: FnMut(usize, usize, Style) {
+        let ch = line[i];
+        let is_word = |ch: char| ch.is_alphanumeric() || ch == '_';
+        if self.string_delimiters.contains(&ch) {
+            push(i, i + 1, Style::String);
+            *state = LineState::String(ch);
+            i + 1
+        } else if is_word(ch) && (i == 0 || !is_word(line[i - 1])) {
+            let mut end = i;
+            while end < line.len() && (is_word(line[end]) || (ch.is_digit(10) && line[end] == '.')) {
+                end += 1;
+            }
+            let word = line[i..end].iter().cloned().collect::<String>();
+            if ch.is_digit(10) {
+                push(i, end, Style::Number);
+            } else if self.keywords.contains(&word) {
+                push(i, end, Style::Keyword);
+            } else if self.types.contains(&word) {
+                push(i, end, Style::Type);
+            }
+            end
+        } else {
+            i + 1
+        }
+    }
+}
+
+fn starts_with(line: &[char], i: usize, s: &str) -> bool {
+    let s = s.chars().collect::<Vec<_>>();
+    s.len() > 0 && line.len() >= i + s.len() && line[i..i + s.len()] == s[..]
+}
+
+fn find(line: &[char], from: usize, s: &str) -> Option<usize> {
+    (from..line.len()).find(|&i| starts_with(line, i, s))
+}
+
+/// What a line starts inside of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LineState {
+    Normal,
+    BlockComment,
+    String(char),
+    // The regions of a TextMate grammar, outermost first. Never empty.
+    TextMate(Vec<Frame>)
+}
+
+fn frames(state: LineState) -> Vec<Frame> {
+    match state {
+        LineState::TextMate(stack) => stack,
+        _ => vec![]
+    }
+}
+
+/// Highlights a buffer lazily, caching the state each line starts in so that
+/// after an edit only the changed lines and any lines whose starting state
+/// changed need highlighting again.
+pub struct Highlighter {
+    pub grammar: Grammar,
+    rules: Option<Rules>,
+    states: Vec<LineState>,
+    spans: Vec<Vec<Span>>,
+    // Rows before dirty_from are up to date. Rows from there up to dirty_to
+    // have changed, and rows from dirty_to up to clean_until are still right
+    // as long as the state they start in hasn't changed.
+    dirty_from: usize,
+    dirty_to: usize,
+    clean_until: usize
+}
+
+impl Highlighter {
+    pub fn new(grammar: Grammar) -> Highlighter {
+        Highlighter {
+            rules: grammar.textmate.as_ref().map(Rules::new),
+            grammar: grammar,
+            states: vec![],
+            spans: vec![],
+            dirty_from: 0,
+            dirty_to: 0,
+            clean_until: 0
+        }
+    }
+
+    /// Adjusts the cache for `edit`, which may not have been applied to the
+    /// buffer yet.
+    pub fn edit(&mut self, edit: &Edit) {
+        let start = edit.start.r;
+        let old_end = edit.old_end().r;
+        let new_end = edit.new_end().r;
+        if start >= self.states.len() {
+            return;
+        }
+        let removed = cmp::min(old_end, self.states.len() - 1) - start;
+        let added = new_end - start;
+        let shift = |row: usize| {
+            if row <= start { row } else if row <= start + removed { start + 1 } else { row + added - removed }
+        };
+        self.states.drain(start + 1..start + 1 + removed);
+        self.spans.drain(start + 1..start + 1 + removed);
+        for _ in 0..added {
+            self.states.insert(start + 1, LineState::Normal);
+            self.spans.insert(start + 1, vec![]);
+        }
+
+        if start >= self.clean_until {
+            return;
+        }
+        self.clean_until = shift(self.clean_until);
+        self.dirty_to = cmp::max(shift(self.dirty_to), new_end + 1);
+        self.dirty_from = cmp::min(self.dirty_from, start);
+        self.clean_until = cmp::max(self.clean_until, self.dirty_to);
+    }
+
+    fn resize(&mut self, n_lines: usize) {
+        if self.states.len() == n_lines {
+            return;
+        }
+        if n_lines > self.states.len() && self.states.len() > 0 {
+            // The state the new rows start in comes from the last old row,
+            // which wasn't saved while it was the last
+            self.dirty_from = cmp::min(self.dirty_from, self.states.len() - 1);
+        }
+        self.states.resize(n_lines, LineState::Normal);
+        self.spans.resize(n_lines, vec![]);
+        self.clean_until = cmp::min(self.clean_until, n_lines);
+        self.dirty_to = cmp::min(self.dirty_to, n_lines);
+        self.dirty_from = cmp::min(self.dirty_from, n_lines);
+    }
+
+    /// Highlights whatever is out of date in `lines` before row `end`.
+    pub fn update(&mut self, lines: &[Vec<char>], end: usize) {
+        self.resize(lines.len());
+        if lines.len() > 0 {
+            self.states[0] = LineState::Normal;
+        }
+        let end = cmp::min(end, lines.len());
+        while self.dirty_from < end {
+            let r = self.dirty_from;
+            let (spans, next_state) = self.highlight_line(&lines[r], self.states[r].clone());
+            self.spans[r] = spans;
+            self.dirty_from = r + 1;
+            if r + 1 < lines.len() {
+                let unchanged = self.states[r + 1] == next_state;
+                self.states[r + 1] = next_state;
+                if unchanged && r + 1 >= self.dirty_to && r + 1 < self.clean_until {
+                    // Everything after this was highlighted starting from the
+                    // same state, so it's still right
+                    self.dirty_from = self.clean_until;
+                }
+            }
+            self.dirty_to = cmp::max(self.dirty_to, self.dirty_from);
+            self.clean_until = cmp::max(self.clean_until, self.dirty_from);
+        }
+    }
+
+    fn highlight_line(&self, line: &[char], state: LineState) -> (Vec<Span>, LineState) {
+        let rules = match self.rules {
+            Some(ref rules) => rules,
+            None => return self.grammar.highlight_line(line, state)
+        };
+        let (spans, stack) = rules.highlight_line(line, frames(state));
+        (spans, if stack.is_empty() { LineState::Normal } else { LineState::TextMate(stack) })
+    }
+
+    /// Returns where the rules of a TextMate grammar matched on `line`,
+    /// which starts in `state`, or None without one.
+    pub fn parse_line(&self, line: &[char], state: LineState) -> Option<Vec<Token>> {
+        self.rules.as_ref().map(|rules| rules.parse_line(line, frames(state)).0)
+    }
+
+    /// Returns the state row `r` starts in, which must be up to date.
+    pub fn state(&self, r: usize) -> LineState {
+        self.states.get(r).cloned().unwrap_or(LineState::Normal)
+    }
+
+    /// Returns the spans for row `r`, which must be up to date.
+    pub fn spans(&self, r: usize) -> Vec<Span> {
+        self.spans.get(r).cloned().unwrap_or(vec![])
+    }
+
+    /// Returns the number of rows that have been highlighted and are up to
+    /// date.
+    pub fn highlighted_rows(&self) -> usize {
+        self.dirty_from
+    }
+}
+
+impl Follow for Highlighter {
+    fn follow(&mut self, event: &ChangeEvent) {
+        self.edit(&event.edit());
+    }
+}
@@ -0,0 +1,149 @@
+use std::cmp;
+
+use {Buffer, BufErr, BufResult, Line, Point};
+use syntax::{SyntaxTree, NodeKind};
+
+/// Which rows open a bracket that's closed on a later row, and which rows
+/// start with the closing bracket of one opened on an earlier row.
+struct Brackets {
+    opens: Vec<bool>,
+    closes: Vec<Option<usize>>
+}
+
+impl Buffer {
+    fn leading_whitespace(&self, r: usize) -> String {
+        self.lines[r][..self.first_non_blank(r).c].iter().cloned().collect()
+    }
+
+    fn brackets(&self) -> Brackets {
+        let mut brackets = Brackets {
+            opens: vec![false; self.lines.len()],
+            closes: vec![None; self.lines.len()]
+        };
+        self.find_brackets(&self.syntax_tree(), &mut brackets);
+        brackets
+    }
+
+    fn find_brackets(&self, tree: &SyntaxTree, brackets: &mut Brackets) {
+        for child in &tree.children {
+            let node = &child.node;
+            match node.kind {
+                NodeKind::Parens | NodeKind::Brackets | NodeKind::Braces => {}
+                NodeKind::Region => {
+                    self.find_brackets(child, brackets);
+                    continue;
+                }
+                _ => continue
+            }
+            if node.end.r > node.start.r {
+                brackets.opens[node.start.r] = true;
+                // Unclosed brackets run to the end of the buffer
+                if node.end.r < self.lines.len() &&
+                    self.first_non_blank(node.end.r).c + 1 == node.end.c {
+                    brackets.closes[node.end.r] = Some(node.start.r);
+                }
+            }
+            self.find_brackets(child, brackets);
+        }
+    }
+
+    /// Returns the indentation for row `r`, given that row `p` is indented
+    /// with `indent_of(p)`. Rows starting with a closing bracket line up with
+    /// the row that opened it, and other rows are indented one level more
+    /// than the row above if that opened a bracket.
+    fn indent_with<F>(&self, r: usize, brackets: &Brackets, indent_of: F) -> String
+        where F: Fn(usize) -> String {
+        if let Some(&Some(open)) = brackets.closes.get(r) {
+            return indent_of(open);
+        }
+        match (0..cmp::min(r, self.lines.len())).rev().find(|&p| !self.is_blank_line(p)) {
+            Some(p) if brackets.opens[p] => indent_of(p) + &self.options.indent_unit(),
+            Some(p) => indent_of(p),
+            None => String::new()
+        }
+    }
+
+    /// Returns the indentation row `r` should have.
+    pub fn indent_for_row(&self, r: usize) -> String {
+        self.indent_with(r, &self.brackets(), |p| self.leading_whitespace(p))
+    }
+
+    /// Replaces the leading whitespace of row `r` with `indent`.
+    fn set_indent(&mut self, r: usize, indent: &str) -> BufResult<()> {
+        if r >= self.lines.len() || self.leading_whitespace(r) == indent {
+            return Ok(());
+        }
+        let end = self.first_non_blank(r);
+        self.replace_region(&Point::new(r, 0), &end, indent).map(|_| ())
+    }
+
+    /// Reindents rows `first` through `last`. Blank lines are emptied.
+    pub fn reindent_rows(&mut self, first: usize, last: usize) -> BufResult<Vec<Line>> {
+        if first >= self.lines.len() || last < first {
+            return Err(BufErr::InvalidPoint);
+        }
+        let last = cmp::min(last, self.lines.len() - 1);
+        // Work out every row's indentation before changing any, since
+        // changing whitespace doesn't change the brackets
+        let brackets = self.brackets();
+        let mut indents: Vec<String> = vec![];
+        for r in first..last + 1 {
+            let indent = if self.is_blank_line(r) {
+                String::new()
+            } else {
+                self.indent_with(r, &brackets, |p| {
+                    if p >= first { indents[p - first].clone() } else { self.leading_whitespace(p) }
+                })
+            };
+            indents.push(indent);
+        }
+        self.begin_transaction();
+        let mut result = Ok(());
+        for (i, indent) in indents.iter().enumerate() {
+            result = self.set_indent(first + i, indent);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.end_transaction_with(result)?;
+        Ok(self.get_lines(first, last + 1))
+    }
+
+    /// Breaks the line at `pt` and indents the new line, dropping whitespace
+    /// around the break. Breaking between a pair of brackets puts the closing
+    /// one on a line of its own. Returns the changed lines and where the
+    /// cursor goes.
+    pub fn insert_newline(&mut self, pt: &Point) -> BufResult<(Vec<Line>, Point)> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        let r = pt.r;
+        let (mut start, mut end) = (pt.c, pt.c);
+        if r < self.lines.len() {
+            let line = &self.lines[r];
+            while start > 0 && (line[start - 1] == ' ' || line[start - 1] == '\t') {
+                start -= 1;
+            }
+            while end < line.len() && (line[end] == ' ' || line[end] == '\t') {
+                end += 1;
+            }
+        }
+        self.begin_transaction();
+        let result = self.replace_region(&Point::new(r, start), &Point::new(r, end), "\n")
+            .and_then(|_| {
+                let brackets = self.brackets();
+                let mut last = r + 1;
+                if brackets.closes.get(r + 1) == Some(&Some(r)) {
+                    self.insert_at_pt("\n", &Point::new(r + 1, 0))?;
+                    let indent = self.indent_for_row(r + 2);
+                    self.set_indent(r + 2, &indent)?;
+                    last = r + 2;
+                }
+                let indent = self.indent_for_row(r + 1);
+                self.set_indent(r + 1, &indent)?;
+                Ok((last, self.first_non_blank(r + 1)))
+            });
+        let (last, point) = self.end_transaction_with(result)?;
+        Ok((self.get_lines(r, last + 1), point))
+    }
+}
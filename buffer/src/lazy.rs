@@ -0,0 +1,250 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use observe::{self, ChangeEvent};
+use {Buffer, BufErr, BufResult, Edit, Grammar, IntoLine, Line, Options, Point};
+
+/// Somewhere the rows of a file can be read from one at a time, such as an
+/// indexed file on disk.
+pub trait LineSource {
+    fn len(&self) -> usize;
+
+    /// Returns row `r`, without its newline.
+    fn line(&self, r: usize) -> Vec<char>;
+
+    /// Returns whether the rows may no longer be the ones there were when
+    /// the source was opened.
+    fn changed(&self) -> bool {
+        false
+    }
+}
+
+/// A run of rows, either still in the source or made by edits.
+enum Piece {
+    Source(usize, usize),
+    Edited(Vec<Vec<char>>)
+}
+
+impl Piece {
+    fn len(&self) -> usize {
+        match self {
+            &Piece::Source(start, end) => end - start,
+            &Piece::Edited(ref rows) => rows.len()
+        }
+    }
+}
+
+fn text_len(rows: &[Vec<char>]) -> usize {
+    rows.iter().map(|row| row.iter().map(|ch| ch.len_utf8()).sum::<usize>() + 1).sum()
+}
+
+/// A buffer whose rows are only read from its source when they're asked
+/// for, with edits kept on top of them. Only insertions and deletions are
+/// supported; everything else needs a whole `Buffer` from `to_buffer()`.
+pub struct LazyBuffer<S: LineSource> {
+    /// Settings the edits are made with, such as whether they're refused
+    pub options: Options,
+    /// Words that expand to their values when typed, as in a `Buffer`
+    pub abbreviations: HashMap<String, String>,
+    /// Grammar whose quotes typed text is paired with
+    pub grammar: Option<Grammar>,
+    source: S,
+    pieces: Vec<Piece>,
+    n_rows: usize,
+    // The edits made so far, grouped into the undo steps they made
+    edits: Vec<Vec<Edit>>,
+    version: usize,
+    observers: Vec<Sender<ChangeEvent>>
+}
+
+impl<S: LineSource> LazyBuffer<S> {
+    pub fn new(source: S) -> LazyBuffer<S> {
+        let n_rows = source.len();
+        LazyBuffer {
+            options: Options::default(),
+            abbreviations: HashMap::new(),
+            grammar: None,
+            source: source,
+            pieces: vec![Piece::Source(0, n_rows)],
+            n_rows: n_rows,
+            edits: vec![],
+            version: 0,
+            observers: vec![]
+        }
+    }
+
+    /// Returns a stream of every change made from now on, like
+    /// `Buffer::observe()`. The versions carry on in the buffer made by
+    /// `to_buffer()`.
+    pub fn observe(&mut self) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.observers.push(sender);
+        receiver
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn len(&self) -> usize {
+        self.n_rows
+    }
+
+    pub fn line(&self, r: usize) -> Vec<char> {
+        let mut start = 0;
+        for piece in &self.pieces {
+            if r < start + piece.len() {
+                return match piece {
+                    &Piece::Source(first, _) => self.source.line(first + r - start),
+                    &Piece::Edited(ref rows) => rows[r - start].clone()
+                };
+            }
+            start += piece.len();
+        }
+        panic!("row {} out of range", r);
+    }
+
+    /// Returns rows `start` up to but not including `end`, stopping early at
+    /// the end of the buffer.
+    pub fn get_lines(&self, start: usize, end: usize) -> Vec<Line> {
+        (start..cmp::min(end, self.n_rows))
+            .map(|r| self.line(r).into_iter().collect::<String>().into_line(r))
+            .collect()
+    }
+
+    /// Replaces `n_old` rows starting at `row` with `rows`.
+    fn splice(&mut self, row: usize, n_old: usize, rows: Vec<Vec<char>>) {
+        let end = row + n_old;
+        let mut pieces = vec![];
+        let mut edited = Some(Piece::Edited(rows));
+        let mut start = 0;
+        for piece in self.pieces.drain(..) {
+            let len = piece.len();
+            // The parts of the piece before and after the replaced rows
+            let before = cmp::min(len, row.saturating_sub(start));
+            let after = cmp::min(len, (start + len).saturating_sub(end));
+            let (head, tail) = match piece {
+                Piece::Source(first, last) => {
+                    (Piece::Source(first, first + before), Piece::Source(last - after, last))
+                }
+                Piece::Edited(rows) => {
+                    (Piece::Edited(rows[..before].to_vec()),
+                     Piece::Edited(rows[len - after..].to_vec()))
+                }
+            };
+            pieces.push(head);
+            if start + len >= row {
+                pieces.extend(edited.take());
+            }
+            pieces.push(tail);
+            start += len;
+        }
+        pieces.extend(edited.take());
+        self.pieces = pieces.into_iter().filter(|piece| piece.len() > 0).collect();
+        self.n_rows = self.pieces.iter().map(|piece| piece.len()).sum();
+    }
+
+    /// Makes a buffer of the rows `first` up to `last` so that an edit
+    /// within them can be made the same way a whole buffer would make it.
+    fn scratch(&self, first: usize, last: usize) -> Buffer {
+        let mut scratch = Buffer::new();
+        scratch.options = self.options.clone();
+        scratch.abbreviations = self.abbreviations.clone();
+        scratch.set_grammar(self.grammar.clone());
+        scratch.lines = (first..last).map(|r| self.line(r)).collect();
+        scratch.text_len = text_len(&scratch.lines);
+        scratch
+    }
+
+    /// Puts the rows of `scratch`, made from rows `first` up to `last`, in
+    /// their place, keeps its edits and returns how many rows it had.
+    fn apply_scratch(&mut self, mut scratch: Buffer, first: usize, last: usize) -> usize {
+        for mut edits in mem::replace(&mut scratch.undo_stack, vec![]) {
+            for edit in edits.iter_mut() {
+                edit.start.r += first;
+                self.version += 1;
+                observe::notify(&mut self.observers, &ChangeEvent::new(edit, self.version));
+            }
+            self.edits.push(edits);
+        }
+        let n_rows = scratch.lines.len();
+        self.splice(first, last - first, scratch.lines);
+        n_rows
+    }
+
+    fn is_valid_point(&self, pt: &Point) -> bool {
+        pt.r < self.n_rows && pt.c <= self.line(pt.r).len() || *pt == Point::new(self.n_rows, 0)
+    }
+
+    /// Makes an insertion at `pt` with `insert` on a scratch buffer of its
+    /// row, where `pt` is on the first row.
+    fn insert_with<F>(&mut self, pt: &Point, insert: F) -> BufResult<Vec<Line>>
+        where F: FnOnce(&mut Buffer, &Point) -> BufResult<Vec<Line>> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        let last = cmp::min(pt.r + 1, self.n_rows);
+        let mut scratch = self.scratch(pt.r, last);
+        insert(&mut scratch, &Point::new(0, pt.c))?;
+        let n_rows = self.apply_scratch(scratch, pt.r, last);
+        Ok(self.get_lines(pt.r, pt.r + n_rows))
+    }
+
+    pub fn insert_at_pt(&mut self, string: &str, pt: &Point) -> BufResult<Vec<Line>> {
+        self.insert_with(pt, |scratch, pt| scratch.insert_at_pt(string, pt))
+    }
+
+    /// Inserts `string` at `pt` as if it were typed, pairing brackets and
+    /// expanding abbreviations like `Buffer::insert_typed_expanding()`.
+    pub fn insert_typed_expanding(&mut self, string: &str, pt: &Point) -> BufResult<Vec<Line>> {
+        self.insert_with(pt, |scratch, pt| scratch.insert_typed_expanding(string, pt))
+    }
+
+    pub fn delete_region(&mut self, start: &Point, end: &Point) -> BufResult<String> {
+        if !self.is_valid_point(start) {
+            return Err(BufErr::InvalidStartPoint);
+        }
+        if !self.is_valid_point(end) {
+            return Err(BufErr::InvalidEndPoint);
+        }
+        if end < start {
+            return Err(BufErr::InvalidDeletionLength);
+        }
+        let last = cmp::min(end.r + 1, self.n_rows);
+        let mut scratch = self.scratch(start.r, last);
+        let deleted = scratch.delete_region(&Point::new(0, start.c),
+                                            &Point::new(end.r - start.r, end.c))?;
+        self.apply_scratch(scratch, start.r, last);
+        Ok(deleted)
+    }
+
+    /// Reads every row into a whole buffer and makes the edits made so far
+    /// again on it, so that they can be undone the same way. Fails if the
+    /// source has changed since it was opened, since the edits were made to
+    /// rows that may no longer be there.
+    pub fn to_buffer(&self) -> BufResult<Buffer> {
+        if self.source.changed() {
+            return Err(BufErr::SourceChanged);
+        }
+        let mut buffer = Buffer::new();
+        buffer.lines = (0..self.source.len()).map(|r| self.source.line(r)).collect();
+        buffer.text_len = cmp::max(text_len(&buffer.lines), 1);
+        for edits in &self.edits {
+            buffer.begin_transaction();
+            for edit in edits {
+                let result = if edit.deleted.is_empty() {
+                    buffer.insert_at_pt(&edit.inserted, &edit.start).map(|_| ())
+                } else {
+                    buffer.delete_region(&edit.start, &edit.old_end()).map(|_| ())
+                };
+                if result.is_err() {
+                    return Err(BufErr::SourceChanged);
+                }
+            }
+            buffer.end_transaction();
+        }
+        Ok(buffer)
+    }
+}
@@ -1,9 +1,61 @@
 #[macro_use] extern crate serde_derive;
+extern crate fancy_regex;
 
-use std::cmp::Ordering;
+use std::cell::RefCell;
+use std::cmp::{self, Ordering};
+use std::collections::HashMap;
 use std::fmt;
+use std::mem;
+use std::sync::mpsc::Sender;
 
-#[derive(Debug, Eq, Serialize, Deserialize)]
+use observe::Follow;
+
+mod abbrev;
+mod block;
+mod column;
+mod complete;
+mod diff;
+mod fold;
+mod highlight;
+mod indent;
+mod lazy;
+mod lines;
+mod motion;
+mod observe;
+mod operator;
+mod pairs;
+mod props;
+mod protect;
+mod regex;
+mod selection;
+mod snapshot;
+mod snippet;
+mod syntax;
+mod textmate;
+mod textobj;
+mod transform;
+mod wrap;
+pub use column::char_width;
+pub use complete::Completions;
+pub use diff::{Change, ChangeKind};
+pub use fold::{FoldKind, FoldingRange};
+pub use highlight::{Grammar, Highlighter, Span, Style};
+pub use lazy::{LazyBuffer, LineSource};
+pub use lines::SortKind;
+pub use motion::{CharClass, Motion};
+pub use observe::ChangeEvent;
+pub use operator::{Operator, OpResult, Target};
+pub use props::{LineProperty, TextProperty};
+pub use textobj::TextObject;
+pub use transform::{convert_case, Case, TransposeUnit};
+pub use wrap::DisplayLine;
+pub use selection::{Selection, SelectionSet};
+pub use snapshot::Snapshot;
+pub use snippet::TabStop;
+pub use syntax::{Direction, Node, NodeKind, SyntaxTree};
+pub use textmate::{TextMateCapture, TextMateGrammar, TextMateRule};
+
+#[derive(Debug, Clone, Copy, Eq, Serialize, Deserialize)]
 pub struct Point {
     r: usize,
     c: usize,
@@ -40,18 +92,191 @@ impl Point {
             c: c
         }
     }
+
+    pub fn row(&self) -> usize {
+        self.r
+    }
+
+    pub fn col(&self) -> usize {
+        self.c
+    }
+
+    /// Returns the point just past `string` if it were inserted at this point.
+    pub fn advanced_by(&self, string: &str) -> Point {
+        let mut pt = *self;
+        for ch in string.chars() {
+            if ch == '\n' {
+                pt.r += 1;
+                pt.c = 0;
+            } else {
+                pt.c += 1;
+            }
+        }
+        pt
+    }
+
+    /// Returns where this point ends up after the text between `start` and
+    /// `old_end` is replaced by text ending at `new_end`. Points at or before
+    /// `start` don't move, points inside the replaced text collapse to
+    /// `new_end` and points after it are shifted along with the text.
+    pub fn shifted(&self, start: &Point, old_end: &Point, new_end: &Point) -> Point {
+        if *self <= *start {
+            *self
+        } else if *self < *old_end {
+            *new_end
+        } else if self.r == old_end.r {
+            Point::new(new_end.r, new_end.c + self.c - old_end.c)
+        } else {
+            Point::new(self.r + new_end.r - old_end.r, self.c)
+        }
+    }
+}
+
+/// A primitive change to the buffer: `deleted` was removed at `start` and
+/// `inserted` was put in its place.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub start: Point,
+    pub deleted: String,
+    pub inserted: String
+}
+
+impl Edit {
+    pub fn old_end(&self) -> Point {
+        self.start.advanced_by(&self.deleted)
+    }
+
+    pub fn new_end(&self) -> Point {
+        self.start.advanced_by(&self.inserted)
+    }
+
+    /// Returns where `pt` ends up after this edit is applied.
+    pub fn shift(&self, pt: &Point) -> Point {
+        pt.shifted(&self.start, &self.old_end(), &self.new_end())
+    }
+}
+
+/// Per-buffer settings.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Options {
+    /// Characters besides letters and digits that make up words
+    #[serde(rename = "wordChars")]
+    pub word_chars: String,
+    /// Number of columns in one level of indentation
+    #[serde(rename = "indentWidth", default = "default_indent_width")]
+    pub indent_width: usize,
+    /// Whether to indent with spaces rather than tabs
+    #[serde(rename = "expandTabs", default = "default_expand_tabs")]
+    pub expand_tabs: bool,
+    /// Number of columns between tab stops
+    #[serde(rename = "tabWidth", default = "default_tab_width")]
+    pub tab_width: usize,
+    /// Whether typing an opening bracket or quote also inserts its closer
+    #[serde(rename = "autoPair", default)]
+    pub auto_pair: bool,
+    /// Whether every edit, undo included, is refused
+    #[serde(rename = "readOnly", default)]
+    pub read_only: bool
+}
+
+fn default_indent_width() -> usize {
+    4
+}
+
+fn default_expand_tabs() -> bool {
+    true
+}
+
+fn default_tab_width() -> usize {
+    8
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            word_chars: "_".to_string(),
+            indent_width: default_indent_width(),
+            expand_tabs: default_expand_tabs(),
+            tab_width: default_tab_width(),
+            auto_pair: false,
+            read_only: false
+        }
+    }
+}
+
+impl Options {
+    /// Returns the text inserted for one level of indentation.
+    pub fn indent_unit(&self) -> String {
+        if self.expand_tabs {
+            std::iter::repeat(' ').take(self.indent_width).collect()
+        } else {
+            "\t".to_string()
+        }
+    }
+
+    /// Checks that the settings can be used, which a tab width of zero
+    /// can't.
+    pub fn validate(&self) -> BufResult<()> {
+        if self.tab_width == 0 {
+            return Err(BufErr::InvalidOptions);
+        }
+        Ok(())
+    }
 }
 
 pub struct Buffer {
     pub lines: Vec<Vec<char>>,
     pub text_len: usize,
-    pub point: Point
+    pub point: Point,
+    pub options: Options,
+    /// Words that expand to their values when typed followed by a separator
+    pub abbreviations: HashMap<String, String>,
+    undo_stack: Vec<Vec<Edit>>,
+    // Edits made since the outermost open transaction began
+    transaction: Vec<Edit>,
+    // Where each change made directly inside the outermost open transaction
+    // starts in it, so that undo() can take back just the last one
+    transaction_steps: Vec<usize>,
+    // Where each open transaction starts in it, outermost first
+    transaction_starts: Vec<usize>,
+    transaction_depth: usize,
+    undoing: bool,
+    // Every edit applied since the last call to take_changes(), including
+    // those made by undo
+    changes: Vec<Edit>,
+    // Number of edits applied, including those made by undo
+    version: usize,
+    observers: Vec<Sender<ChangeEvent>>,
+    // The rest follow the text through the same change events observers
+    // get, handed to them as each edit is made. They're used through &self
+    // as well, hence the RefCells.
+    // Updated lazily whenever lines are returned
+    highlighter: Option<RefCell<Highlighter>>,
+    // Built on demand, and only scanned again where the buffer changed
+    syntax_tree: RefCell<syntax::TreeCache>,
+    // Wrapped rows for the widths most recently asked for
+    wrap_caches: RefCell<Vec<wrap::WrapCache>>,
+    // Words for completion, updated lazily like the highlighter
+    word_index: RefCell<complete::WordIndex>,
+    // Rows shared with snapshots, kept up to date once the first is taken
+    snapshot_rows: RefCell<Option<snapshot::Rows>>,
+    // Regions of text that can't be changed, in order
+    protected: RefCell<Vec<(Point, Point)>>,
+    // Text properties in order of where they start
+    properties: RefCell<Vec<props::TextProperty>>,
+    next_property_id: usize,
+    // Tab stops of the snippet being filled in
+    snippet: RefCell<Option<snippet::SnippetSession>>
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Line {
     pub line: String,
-    pub number: usize
+    pub number: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spans: Vec<Span>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<LineProperty>
 }
 
 #[derive(PartialEq, Debug)]
@@ -59,7 +284,25 @@ pub enum BufErr {
     InvalidPoint,
     InvalidStartPoint,
     InvalidEndPoint,
-    InvalidDeletionLength
+    InvalidDeletionLength,
+    NothingToUndo,
+    InvalidBlock,
+    NoTextObject,
+    NoSyntaxNode,
+    InvalidWidth,
+    NothingToTranspose,
+    NoCommentSyntax,
+    InvalidSnippet,
+    NoSnippet,
+    InvalidPatch,
+    /// The 1-based number of the first hunk that doesn't match the buffer
+    PatchConflict(usize),
+    /// The buffer is read-only, or the text is protected
+    ReadOnly,
+    NoProperty,
+    InvalidOptions,
+    /// The file a lazy buffer reads its rows from has changed since
+    SourceChanged
 }
 
 impl fmt::Display for BufErr {
@@ -69,6 +312,21 @@ impl fmt::Display for BufErr {
             &BufErr::InvalidStartPoint => { write!(f, "invalid start point") }
             &BufErr::InvalidEndPoint => { write!(f, "invalid end point") }
             &BufErr::InvalidDeletionLength => { write!(f, "invalid deletion length") }
+            &BufErr::NothingToUndo => { write!(f, "nothing to undo") }
+            &BufErr::InvalidBlock => { write!(f, "invalid block") }
+            &BufErr::NoTextObject => { write!(f, "no text object at point") }
+            &BufErr::NoSyntaxNode => { write!(f, "no such syntax node") }
+            &BufErr::InvalidWidth => { write!(f, "invalid width") }
+            &BufErr::NothingToTranspose => { write!(f, "nothing to transpose") }
+            &BufErr::NoCommentSyntax => { write!(f, "no comment syntax for this grammar") }
+            &BufErr::InvalidSnippet => { write!(f, "invalid snippet") }
+            &BufErr::NoSnippet => { write!(f, "no snippet in progress") }
+            &BufErr::InvalidPatch => { write!(f, "invalid patch") }
+            &BufErr::PatchConflict(hunk) => { write!(f, "hunk {} does not apply", hunk) }
+            &BufErr::ReadOnly => { write!(f, "text is read-only") }
+            &BufErr::NoProperty => { write!(f, "no such text property") }
+            &BufErr::InvalidOptions => { write!(f, "invalid options") }
+            &BufErr::SourceChanged => { write!(f, "file changed on disk since it was opened") }
         }
     }
 }
@@ -105,7 +363,9 @@ impl IntoLine for String {
     fn into_line(self, number: usize) -> Line {
         Line {
             number: number,
-            line: self
+            line: self,
+            spans: vec![],
+            properties: vec![]
         }
     }
 }
@@ -114,16 +374,21 @@ impl<'a> IntoLine for &'a str {
     fn into_line(self, number: usize) -> Line {
         Line {
             number: number,
-            line: self.to_string()
+            line: self.to_string(),
+            spans: vec![],
+            properties: vec![]
         }
     }
 }
 
 impl Line {
-    fn new(number: usize, line: String) -> Line {
+    fn new(number: usize, line: String, spans: Vec<Span>,
+           properties: Vec<LineProperty>) -> Line {
         Line {
             number: number,
-            line: line
+            line: line,
+            spans: spans,
+            properties: properties
         }
     }
 }
@@ -133,7 +398,27 @@ impl Buffer {
         Buffer {
             lines: vec![],
             text_len: 0,
-            point: Point { r: 0, c: 0 }
+            point: Point { r: 0, c: 0 },
+            options: Options::default(),
+            abbreviations: HashMap::new(),
+            undo_stack: vec![],
+            transaction: vec![],
+            transaction_steps: vec![],
+            transaction_starts: vec![],
+            transaction_depth: 0,
+            undoing: false,
+            changes: vec![],
+            version: 0,
+            observers: vec![],
+            highlighter: None,
+            syntax_tree: RefCell::new(syntax::TreeCache::new()),
+            wrap_caches: RefCell::new(vec![]),
+            word_index: RefCell::new(complete::WordIndex::new()),
+            snapshot_rows: RefCell::new(None),
+            protected: RefCell::new(vec![]),
+            properties: RefCell::new(vec![]),
+            next_property_id: 0,
+            snippet: RefCell::new(None)
         }
     }
 
@@ -162,10 +447,23 @@ impl Buffer {
             row == self.lines.len() && col != 0 {
             return Err(BufErr::InvalidPoint);
         }
+        self.check_writable(pt, pt)?;
         let lines = string.split("\n")
             .map(|string| string.to_string().chars().collect::<Vec<_>>())
             .collect::<Vec<_>>();
 
+        // Inserting text without a terminating newline past the end of the
+        // buffer also adds the newline, so record it as part of the edit
+        let mut inserted = string.to_string();
+        if row == self.lines.len() && lines[lines.len() - 1].len() != 0 {
+            inserted.push('\n');
+        }
+        self.record(Edit {
+            start: *pt,
+            deleted: String::new(),
+            inserted: inserted
+        });
+
         // When we return the modified lines, we need to be careful not to run
         // past the end of self.lines if the inserted string ends in a newline
         // and the insertion point is at past the end of the buffer (i.e.,
@@ -204,11 +502,7 @@ impl Buffer {
         }
         self.text_len += string.len();
 
-        let mut modified_lines = vec![];
-        for (i, line) in self.lines[row..row + n_lines].iter().enumerate() {
-            modified_lines.push(Line::new(row + i, line.iter().cloned().collect()));
-        }
-        Ok(modified_lines)
+        Ok(self.get_lines(row, row + n_lines))
     }
 
     pub fn region_to_str(&self, start: &Point, end: &Point) -> BufResult<String> {
@@ -238,7 +532,7 @@ impl Buffer {
                 .cloned()
                 .collect::<String>());
         }
-        string += &(self.lines[start.r].iter().cloned().collect::<String>() + "\n");
+        string += &(self.lines[start.r][start.c..].iter().cloned().collect::<String>() + "\n");
         for line in &self.lines[start.r + 1..end.r] {
             string += &(line.iter().cloned().collect::<String>() + "\n");
         }
@@ -270,10 +564,17 @@ impl Buffer {
         if end == start {
             return Ok("".to_string());
         }
+        self.check_writable(start, end)?;
 
         let mut string = String::from("");
         if start.r == end.r {
-            return Ok(self.lines[start.r].drain(start.c..end.c).collect::<String>());
+            let string = self.lines[start.r].drain(start.c..end.c).collect::<String>();
+            self.record(Edit {
+                start: *start,
+                deleted: string.clone(),
+                inserted: String::new()
+            });
+            return Ok(string);
         }
         string += &(self.lines[start.r].drain(start.c..).collect::<String>() + "\n");
         for line in &self.lines[start.r + 1..end.r] {
@@ -288,12 +589,22 @@ impl Buffer {
         string += &last_part;
         self.lines.drain(start.r + 1..end.r);
 
+        let mut deleted = string.clone();
         if start.r < self.lines.len() - 1 {
             // Combine with rest of last line in deleted region
             let rest_of_last_line = self.lines[start.r + 1].clone();
             self.lines[start.r].extend(rest_of_last_line);
             self.lines.remove(start.r + 1);
+        } else {
+            // The region ran to the end of the buffer, so the newline ending
+            // the start line is still there
+            deleted.pop();
         }
+        self.record(Edit {
+            start: *start,
+            deleted: deleted,
+            inserted: String::new()
+        });
 
         self.text_len -= string.len();
         if self.text_len == 0 {
@@ -302,6 +613,232 @@ impl Buffer {
         Ok(string)
     }
 
+    /// Replaces the text between `start` and `end` with `string` as a single
+    /// undo step.
+    pub fn replace_region(&mut self, start: &Point, end: &Point, string: &str)
+        -> BufResult<Vec<Line>> {
+        self.region_to_str(start, end)?;
+        self.begin_transaction();
+        let result = self.delete_region(start, end)
+            .and_then(|_| self.insert_at_pt(string, start));
+        self.end_transaction_with(result)
+    }
+
+    /// Returns the number of characters before `pt`, counting a newline at
+    /// the end of each line.
+    pub fn point_to_offset(&self, pt: &Point) -> usize {
+        self.lines[..cmp::min(pt.r, self.lines.len())].iter()
+            .fold(0, |offset, line| offset + line.len() + 1) + pt.c
+    }
+
+    /// Returns the point `offset` characters into the buffer, or the end of
+    /// the buffer if it is too short.
+    pub fn offset_to_point(&self, offset: usize) -> Point {
+        let mut offset = offset;
+        for (r, line) in self.lines.iter().enumerate() {
+            if offset <= line.len() {
+                return Point::new(r, offset);
+            }
+            offset -= line.len() + 1;
+        }
+        Point::new(self.lines.len(), 0)
+    }
+
+    pub fn is_valid_point(&self, pt: &Point) -> bool {
+        !(pt.r > self.lines.len() ||
+            (pt.r < self.lines.len() && pt.c > self.lines[pt.r].len()) ||
+            pt.r == self.lines.len() && pt.c != 0)
+    }
+
+    /// Returns rows `start` up to but not including `end`, stopping early at
+    /// the end of the buffer.
+    pub fn get_lines(&self, start: usize, end: usize) -> Vec<Line> {
+        let end = cmp::min(end, self.lines.len());
+        if start >= end {
+            return vec![];
+        }
+        if let Some(ref highlighter) = self.highlighter {
+            highlighter.borrow_mut().update(&self.lines, end);
+        }
+        self.lines[start..end].iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let spans = match self.highlighter {
+                    Some(ref highlighter) => highlighter.borrow_mut().spans(start + i),
+                    None => vec![]
+                };
+                Line::new(start + i, line.iter().cloned().collect(), spans,
+                          self.properties_on_line(start + i))
+            })
+            .collect()
+    }
+
+    /// Highlights the buffer with `grammar` from now on, or stops
+    /// highlighting it if there is none.
+    pub fn set_grammar(&mut self, grammar: Option<Grammar>) {
+        self.highlighter = grammar.map(|grammar| RefCell::new(Highlighter::new(grammar)));
+        self.syntax_tree.borrow_mut().clear();
+    }
+
+    pub fn grammar(&self) -> Option<Grammar> {
+        self.highlighter.as_ref().map(|highlighter| highlighter.borrow().grammar.clone())
+    }
+
+    /// Groups every edit made until the matching end_transaction() into a
+    /// single undo step. Transactions may be nested.
+    pub fn begin_transaction(&mut self) {
+        self.transaction_depth += 1;
+        self.transaction_starts.push(self.transaction.len());
+        if self.transaction_depth == 2 {
+            self.transaction_steps.push(self.transaction.len());
+        }
+    }
+
+    pub fn end_transaction(&mut self) {
+        assert!(self.transaction_depth > 0);
+        self.transaction_depth -= 1;
+        self.transaction_starts.pop();
+        if self.transaction_depth == 1 &&
+           self.transaction_steps.last() == Some(&self.transaction.len()) {
+            // Nothing was changed inside the inner transaction
+            self.transaction_steps.pop();
+        }
+        if self.transaction_depth == 0 {
+            self.transaction_steps.clear();
+        }
+        if self.transaction_depth == 0 && self.transaction.len() > 0 {
+            let edits = mem::replace(&mut self.transaction, vec![]);
+            self.undo_stack.push(edits);
+        }
+    }
+
+    /// Ends the innermost transaction after reverting every edit made since
+    /// it began, for when what it was grouping failed part of the way
+    /// through.
+    pub fn abort_transaction(&mut self) {
+        let start = *self.transaction_starts.last().expect("no open transaction");
+        let edits = self.transaction.split_off(start);
+        self.undoing = true;
+        for edit in edits.iter().rev() {
+            let reverted = self.revert(edit);
+            debug_assert!(reverted.is_ok());
+        }
+        self.undoing = false;
+        self.end_transaction();
+    }
+
+    /// Ends the innermost transaction, aborting it if `result` is an error so
+    /// that a command that fails changes nothing.
+    pub fn end_transaction_with<T>(&mut self, result: BufResult<T>) -> BufResult<T> {
+        if result.is_err() {
+            self.abort_transaction();
+        } else {
+            self.end_transaction();
+        }
+        result
+    }
+
+    /// Returns the edits that the next call to undo() will revert, in the
+    /// order they were made.
+    pub fn last_change(&self) -> Option<&[Edit]> {
+        self.undo_stack.last().map(|edits| &edits[..])
+    }
+
+    /// Returns every edit applied since the last call, so that anything
+    /// tracking positions in the buffer can catch up.
+    pub fn take_changes(&mut self) -> Vec<Edit> {
+        mem::replace(&mut self.changes, vec![])
+    }
+
+    /// Reverts the most recent undo step and returns the lines it touched.
+    /// Inside a transaction, such as while a macro plays, that's the last
+    /// change made since the transaction began, if there is one.
+    pub fn undo(&mut self) -> BufResult<Vec<Line>> {
+        if self.options.read_only {
+            return Err(BufErr::ReadOnly);
+        }
+        let edits = match self.transaction_steps.pop() {
+            Some(start) => self.transaction.split_off(start),
+            None => match self.undo_stack.pop() {
+                Some(edits) => edits,
+                None => return Err(BufErr::NothingToUndo)
+            }
+        };
+        self.undoing = true;
+        let mut first_row = self.lines.len();
+        let mut last_row = 0;
+        let mut result = Ok(());
+        for edit in edits.iter().rev() {
+            result = self.revert(edit);
+            if result.is_err() {
+                break;
+            }
+            first_row = cmp::min(first_row, edit.start.r);
+            last_row = cmp::max(last_row, edit.old_end().r);
+        }
+        self.undoing = false;
+        result?;
+        Ok(self.get_lines(first_row, last_row + 1))
+    }
+
+    fn revert(&mut self, edit: &Edit) -> BufResult<()> {
+        let end = edit.new_end();
+        let to_end_of_buffer = end.r == self.lines.len();
+        self.delete_region(&edit.start, &end)?;
+        if to_end_of_buffer && edit.start.c == 0 && end != edit.start {
+            // delete_region() leaves an empty line behind when it deletes
+            // through the end of the buffer, but that line didn't exist
+            // before the edit either
+            self.lines.pop();
+            self.text_len = if self.lines.len() == 0 { 0 } else { self.text_len - 1 };
+            let row = self.lines.len();
+            self.record(Edit {
+                start: Point::new(row, 0),
+                deleted: "\n".to_string(),
+                inserted: String::new()
+            });
+        }
+        self.insert_at_pt(&edit.deleted, &edit.start)?;
+        Ok(())
+    }
+
+    fn record(&mut self, edit: Edit) {
+        if edit.deleted.len() == 0 && edit.inserted.len() == 0 {
+            return;
+        }
+        // Everything that follows the text hears about the edit from this
+        self.version += 1;
+        let event = ChangeEvent::new(&edit, self.version);
+        self.follow(&event);
+        observe::notify(&mut self.observers, &event);
+        self.changes.push(edit.clone());
+        if self.undoing {
+            return;
+        }
+        if self.transaction_depth == 1 {
+            self.transaction_steps.push(self.transaction.len());
+        }
+        if self.transaction_depth > 0 {
+            self.transaction.push(edit);
+        } else {
+            self.undo_stack.push(vec![edit]);
+        }
+    }
+
+    /// Brings everything that follows the text up to date with `event`.
+    fn follow(&mut self, event: &ChangeEvent) {
+        if let Some(ref mut highlighter) = self.highlighter {
+            highlighter.get_mut().follow(event);
+        }
+        self.syntax_tree.get_mut().follow(event);
+        self.wrap_caches.get_mut().follow(event);
+        self.word_index.get_mut().follow(event);
+        self.snapshot_rows.get_mut().follow(event);
+        self.protected.get_mut().follow(event);
+        self.properties.get_mut().follow(event);
+        self.snippet.get_mut().follow(event);
+    }
+
     pub fn to_str(&mut self) -> String {
         self.region_to_str(&Point::new(0, 0), &Point::new(self.lines.len(), 0)).unwrap()
     }
@@ -0,0 +1,190 @@
+use std::cmp::{self, Ordering};
+
+use {Buffer, BufErr, BufResult, Line, Point};
+
+/// How `sort_lines` compares rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortKind {
+    #[serde(rename = "lexical")]
+    Lexical,
+    /// By the first number in each row. Rows without one go first.
+    #[serde(rename = "numeric")]
+    Numeric,
+    #[serde(rename = "caseInsensitive")]
+    CaseInsensitive
+}
+
+/// Returns the first number in `line`, which may be negative or have a
+/// fractional part.
+fn first_number(line: &[char]) -> Option<f64> {
+    let start = match line.iter().position(|ch| ch.is_digit(10)) {
+        Some(start) => start,
+        None => return None
+    };
+    let mut end = start;
+    while end < line.len() && line[end].is_digit(10) {
+        end += 1;
+    }
+    if end + 1 < line.len() && line[end] == '.' && line[end + 1].is_digit(10) {
+        end += 1;
+        while end < line.len() && line[end].is_digit(10) {
+            end += 1;
+        }
+    }
+    let start = if start > 0 && line[start - 1] == '-' { start - 1 } else { start };
+    line[start..end].iter().cloned().collect::<String>().parse().ok()
+}
+
+fn lowercase(row: &[char]) -> Vec<char> {
+    row.iter().flat_map(|ch| ch.to_lowercase()).collect()
+}
+
+fn compare_rows(a: &[char], b: &[char], kind: SortKind) -> Ordering {
+    match kind {
+        SortKind::Lexical => a.cmp(b),
+        SortKind::Numeric => {
+            match (first_number(a), first_number(b)) {
+                (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+                (x, y) => x.is_some().cmp(&y.is_some())
+            }
+        }
+        SortKind::CaseInsensitive => lowercase(a).cmp(&lowercase(b))
+    }
+}
+
+impl Buffer {
+    fn check_rows(&self, first: usize, last: usize) -> BufResult<()> {
+        if last < first || last >= self.lines.len() {
+            return Err(BufErr::InvalidPoint);
+        }
+        Ok(())
+    }
+
+    /// Replaces rows `first` through `last` with `rows` as a single edit,
+    /// unless they're the same already.
+    fn replace_rows(&mut self, first: usize, last: usize, rows: Vec<Vec<char>>)
+        -> BufResult<Vec<Line>> {
+        let n_rows = rows.len();
+        if rows[..] != self.lines[first..last + 1] {
+            let string = rows.into_iter()
+                .map(|row| row.into_iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let end = Point::new(last, self.lines[last].len());
+            self.replace_region(&Point::new(first, 0), &end, &string)?;
+        }
+        Ok(self.get_lines(first, first + n_rows))
+    }
+
+    /// Joins rows `first` through `last` into one, or row `first` with the
+    /// next if they're the same. Leading whitespace on the joined rows is
+    /// replaced with a single space, or nothing if either side is blank.
+    pub fn join_lines(&mut self, first: usize, last: usize) -> BufResult<Vec<Line>> {
+        self.check_rows(first, last)?;
+        let last = if last == first { cmp::min(first + 1, self.lines.len() - 1) } else { last };
+        self.begin_transaction();
+        let mut result = Ok(());
+        for r in (first..last).rev() {
+            let line = &self.lines[r];
+            let trimmed = line.iter().rposition(|ch| !ch.is_whitespace()).map_or(0, |c| c + 1);
+            let next = self.first_non_blank(r + 1);
+            let space = if trimmed == 0 || next.c == self.lines[r + 1].len() { "" } else { " " };
+            result = self.replace_region(&Point::new(r, trimmed), &next, space).map(|_| ());
+            if result.is_err() {
+                break;
+            }
+        }
+        self.end_transaction_with(result)?;
+        Ok(self.get_lines(first, first + 1))
+    }
+
+    /// Inserts a copy of rows `first` through `last` after them.
+    pub fn duplicate_lines(&mut self, first: usize, last: usize) -> BufResult<Vec<Line>> {
+        self.check_rows(first, last)?;
+        let end = Point::new(last, self.lines[last].len());
+        let string = "\n".to_string() + &self.region_to_str(&Point::new(first, 0), &end)?;
+        self.insert_at_pt(&string, &end)
+    }
+
+    /// Inserts a copy of the text from `start` to `end` after it.
+    pub fn duplicate_region(&mut self, start: &Point, end: &Point) -> BufResult<Vec<Line>> {
+        let string = self.region_to_str(start, end)?;
+        self.insert_at_pt(&string, end)
+    }
+
+    /// Moves rows `first` through `last` down `by` rows, or up if it's
+    /// negative, stopping at the start or end of the buffer.
+    pub fn move_lines(&mut self, first: usize, last: usize, by: isize) -> BufResult<Vec<Line>> {
+        self.check_rows(first, last)?;
+        let mut rows = self.lines[first..last + 1].to_vec();
+        if by < 0 {
+            // -isize::MIN doesn't fit in an isize, but is past the start anyway
+            let up = by.checked_neg().map_or(first, |up| cmp::min(up as usize, first));
+            let top = first - up;
+            let mut above = self.lines[top..first].to_vec();
+            rows.append(&mut above);
+            self.replace_rows(top, last, rows)
+        } else {
+            let bottom = last + cmp::min(by as usize, self.lines.len() - 1 - last);
+            let mut below = self.lines[last + 1..bottom + 1].to_vec();
+            below.append(&mut rows);
+            self.replace_rows(first, bottom, below)
+        }
+    }
+
+    /// Sorts rows `first` through `last`. Rows that compare equal keep their
+    /// order, even when `reverse` is set.
+    pub fn sort_lines(&mut self, first: usize, last: usize, kind: SortKind, reverse: bool)
+        -> BufResult<Vec<Line>> {
+        self.check_rows(first, last)?;
+        let mut rows = self.lines[first..last + 1].to_vec();
+        rows.sort_by(|a, b| {
+            let order = compare_rows(a, b, kind);
+            if reverse { order.reverse() } else { order }
+        });
+        self.replace_rows(first, last, rows)
+    }
+
+    /// Removes rows between `first` and `last` that are the same as an
+    /// earlier one.
+    pub fn unique_lines(&mut self, first: usize, last: usize) -> BufResult<Vec<Line>> {
+        self.check_rows(first, last)?;
+        let mut rows: Vec<Vec<char>> = vec![];
+        for row in &self.lines[first..last + 1] {
+            if !rows.contains(row) {
+                rows.push(row.clone());
+            }
+        }
+        self.replace_rows(first, last, rows)
+    }
+
+    /// Reverses the order of rows `first` through `last`.
+    pub fn reverse_lines(&mut self, first: usize, last: usize) -> BufResult<Vec<Line>> {
+        self.check_rows(first, last)?;
+        let mut rows = self.lines[first..last + 1].to_vec();
+        rows.reverse();
+        self.replace_rows(first, last, rows)
+    }
+
+    /// Removes whitespace from the ends of rows `first` through `last`.
+    pub fn trim_trailing_whitespace(&mut self, first: usize, last: usize)
+        -> BufResult<Vec<Line>> {
+        self.check_rows(first, last)?;
+        self.begin_transaction();
+        let mut result = Ok(());
+        for r in first..last + 1 {
+            let len = self.lines[r].len();
+            let trimmed = self.lines[r].iter().rposition(|ch| !ch.is_whitespace())
+                .map_or(0, |c| c + 1);
+            if trimmed < len {
+                result = self.delete_region(&Point::new(r, trimmed), &Point::new(r, len))
+                    .map(|_| ());
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+        self.end_transaction_with(result)?;
+        Ok(self.get_lines(first, last + 1))
+    }
+}
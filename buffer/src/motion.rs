@@ -0,0 +1,350 @@
+use std::cmp;
+
+use {Buffer, BufErr, BufResult, Point};
+
+/// A way of moving a point through the buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Motion {
+    #[serde(rename = "charForward")]
+    CharForward,
+    #[serde(rename = "charBackward")]
+    CharBackward,
+    #[serde(rename = "wordForward")]
+    WordForward,
+    #[serde(rename = "wordBackward")]
+    WordBackward,
+    #[serde(rename = "wordEnd")]
+    WordEnd,
+    #[serde(rename = "subwordForward")]
+    SubwordForward,
+    #[serde(rename = "subwordBackward")]
+    SubwordBackward,
+    #[serde(rename = "lineStart")]
+    LineStart,
+    #[serde(rename = "lineEnd")]
+    LineEnd,
+    #[serde(rename = "firstNonBlank")]
+    FirstNonBlank,
+    #[serde(rename = "paragraphForward")]
+    ParagraphForward,
+    #[serde(rename = "paragraphBackward")]
+    ParagraphBackward,
+    #[serde(rename = "sentenceForward")]
+    SentenceForward,
+    #[serde(rename = "sentenceBackward")]
+    SentenceBackward,
+    #[serde(rename = "matchBracket")]
+    MatchBracket,
+    #[serde(rename = "bufferStart")]
+    BufferStart,
+    #[serde(rename = "bufferEnd")]
+    BufferEnd,
+    /// Up a row, keeping to the same visual column where possible
+    #[serde(rename = "lineUp")]
+    LineUp,
+    #[serde(rename = "lineDown")]
+    LineDown
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum CharClass {
+    Space,
+    Punctuation,
+    Word
+}
+
+impl Buffer {
+    /// Moves `pt` by `motion` `count` times. Motions stop at the start and end
+    /// of the buffer rather than failing.
+    pub fn move_point(&self, pt: &Point, motion: Motion, count: usize) -> BufResult<Point> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        let mut pt = *pt;
+        if motion == Motion::LineUp || motion == Motion::LineDown {
+            // Move all the rows at once so that short rows in between don't
+            // lose the column
+            let r = if motion == Motion::LineUp {
+                pt.r.saturating_sub(count)
+            } else {
+                cmp::min(pt.r + count, self.lines.len().saturating_sub(1))
+            };
+            return Ok(self.point_at_visual_col(r, self.visual_col_at(&pt)));
+        }
+        // Once on a bracket, jumping to its match twice comes back to it
+        let count = if motion == Motion::MatchBracket && count > 2 { 2 - count % 2 } else { count };
+        for _ in 0..count {
+            let next = match motion {
+                Motion::CharForward => self.next_point(&pt).unwrap_or(pt),
+                Motion::CharBackward => self.point_before(&pt),
+                Motion::WordForward => self.word_forward(&pt),
+                Motion::WordBackward => self.word_backward(&pt),
+                Motion::WordEnd => self.word_end(&pt),
+                Motion::SubwordForward => self.subword_forward(&pt),
+                Motion::SubwordBackward => self.subword_backward(&pt),
+                Motion::LineStart => Point::new(pt.r, 0),
+                Motion::LineEnd => Point::new(pt.r, self.line_len(pt.r)),
+                Motion::FirstNonBlank => self.first_non_blank(pt.r),
+                Motion::ParagraphForward => self.paragraph_forward(&pt),
+                Motion::ParagraphBackward => self.paragraph_backward(&pt),
+                Motion::SentenceForward => self.sentence_forward(&pt),
+                Motion::SentenceBackward => self.sentence_backward(&pt),
+                Motion::MatchBracket => self.match_bracket(&pt).unwrap_or(pt),
+                Motion::BufferStart => Point::new(0, 0),
+                Motion::BufferEnd => self.end_point(),
+                Motion::LineUp | Motion::LineDown => unreachable!()
+            };
+            // Counts come from clients, so don't go on once there's nowhere
+            // further to go
+            if next == pt {
+                break;
+            }
+            pt = next;
+        }
+        Ok(pt)
+    }
+
+    /// Returns the character at `pt`, treating the end of every line but the
+    /// last as a newline.
+    pub fn char_at(&self, pt: &Point) -> Option<char> {
+        if pt.r >= self.lines.len() {
+            None
+        } else if pt.c < self.lines[pt.r].len() {
+            Some(self.lines[pt.r][pt.c])
+        } else if pt.r + 1 < self.lines.len() {
+            Some('\n')
+        } else {
+            None
+        }
+    }
+
+    /// Returns the point one character after `pt`, wrapping to the start of
+    /// the next line, or None at the end of the buffer.
+    pub fn next_point(&self, pt: &Point) -> Option<Point> {
+        if pt.r >= self.lines.len() {
+            None
+        } else if pt.c < self.lines[pt.r].len() {
+            Some(Point::new(pt.r, pt.c + 1))
+        } else if pt.r + 1 < self.lines.len() {
+            Some(Point::new(pt.r + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the end of the last line in the buffer.
+    pub fn end_point(&self) -> Point {
+        match self.lines.len() {
+            0 => Point::new(0, 0),
+            n => Point::new(n - 1, self.lines[n - 1].len())
+        }
+    }
+
+    pub fn line_len(&self, r: usize) -> usize {
+        if r < self.lines.len() { self.lines[r].len() } else { 0 }
+    }
+
+    pub fn is_word_char(&self, ch: char) -> bool {
+        ch.is_alphanumeric() || self.options.word_chars.contains(ch)
+    }
+
+    pub fn char_class(&self, ch: char) -> CharClass {
+        if ch.is_whitespace() {
+            CharClass::Space
+        } else if self.is_word_char(ch) {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+
+    fn class_at(&self, pt: &Point) -> Option<CharClass> {
+        self.char_at(pt).map(|ch| self.char_class(ch))
+    }
+
+    pub fn is_blank_line(&self, r: usize) -> bool {
+        r < self.lines.len() && self.lines[r].iter().all(|ch| ch.is_whitespace())
+    }
+
+    fn word_forward(&self, pt: &Point) -> Point {
+        let mut pt = *pt;
+        let class = self.class_at(&pt);
+        if class != Some(CharClass::Space) {
+            while self.class_at(&pt).is_some() && self.class_at(&pt) == class {
+                pt = self.next_point(&pt).unwrap();
+            }
+        }
+        while self.class_at(&pt) == Some(CharClass::Space) {
+            pt = self.next_point(&pt).unwrap();
+        }
+        pt
+    }
+
+    fn word_backward(&self, pt: &Point) -> Point {
+        let mut pt = self.point_before(pt);
+        while self.class_at(&pt) == Some(CharClass::Space) && pt != Point::new(0, 0) {
+            pt = self.point_before(&pt);
+        }
+        let class = self.class_at(&pt);
+        while pt != Point::new(0, 0) && self.class_at(&self.point_before(&pt)) == class {
+            pt = self.point_before(&pt);
+        }
+        pt
+    }
+
+    fn word_end(&self, pt: &Point) -> Point {
+        let mut pt = match self.next_point(pt) {
+            Some(pt) => pt,
+            None => return *pt
+        };
+        while self.class_at(&pt) == Some(CharClass::Space) {
+            pt = self.next_point(&pt).unwrap();
+        }
+        let class = self.class_at(&pt);
+        while let Some(next) = self.next_point(&pt) {
+            if self.class_at(&next).is_none() || self.class_at(&next) != class {
+                break;
+            }
+            pt = next;
+        }
+        pt
+    }
+
+    /// Returns whether a subword starts at `pt`, e.g. at the `W` and the `b`
+    /// in `fooWord_bar` or the `S` in `HTTPServer`.
+    fn is_subword_start(&self, pt: &Point) -> bool {
+        let ch = match self.char_at(pt) {
+            Some(ch) if self.is_word_char(ch) && ch != '_' => ch,
+            _ => return false
+        };
+        if pt.c == 0 {
+            return true;
+        }
+        let prev = self.lines[pt.r][pt.c - 1];
+        let next = self.char_at(&Point::new(pt.r, pt.c + 1));
+        !self.is_word_char(prev) || prev == '_' ||
+            (prev.is_lowercase() && ch.is_uppercase()) ||
+            (prev.is_uppercase() && ch.is_uppercase() && next.map_or(false, |n| n.is_lowercase())) ||
+            (prev.is_alphabetic() && ch.is_numeric()) ||
+            (prev.is_numeric() && ch.is_alphabetic())
+    }
+
+    fn subword_forward(&self, pt: &Point) -> Point {
+        let word = self.word_forward(pt);
+        let mut p = *pt;
+        while let Some(next) = self.next_point(&p) {
+            if next >= word {
+                break;
+            }
+            if self.is_subword_start(&next) {
+                return next;
+            }
+            p = next;
+        }
+        word
+    }
+
+    fn subword_backward(&self, pt: &Point) -> Point {
+        let word = self.word_backward(pt);
+        let mut p = self.point_before(pt);
+        while p > word {
+            if self.is_subword_start(&p) {
+                return p;
+            }
+            p = self.point_before(&p);
+        }
+        word
+    }
+
+    pub fn first_non_blank(&self, r: usize) -> Point {
+        if r >= self.lines.len() {
+            return Point::new(r, 0);
+        }
+        let c = self.lines[r].iter()
+            .position(|ch| !ch.is_whitespace())
+            .unwrap_or(self.lines[r].len());
+        Point::new(r, c)
+    }
+
+    /// Moves to the next blank line after the current paragraph, or the end
+    /// of the buffer.
+    fn paragraph_forward(&self, pt: &Point) -> Point {
+        let mut r = pt.r;
+        while self.is_blank_line(r) {
+            r += 1;
+        }
+        while r < self.lines.len() && !self.is_blank_line(r) {
+            r += 1;
+        }
+        if r < self.lines.len() { Point::new(r, 0) } else { self.end_point() }
+    }
+
+    /// Moves to the blank line before the current paragraph, or the start of
+    /// the buffer.
+    fn paragraph_backward(&self, pt: &Point) -> Point {
+        let mut r = pt.r;
+        while r > 0 && (r >= self.lines.len() || self.is_blank_line(r)) {
+            r -= 1;
+        }
+        while r > 0 && !self.is_blank_line(r) {
+            r -= 1;
+        }
+        Point::new(r, 0)
+    }
+
+    /// Returns whether a sentence starts at `pt`: the first non-blank
+    /// character of the buffer or of a paragraph, or the first one after
+    /// whitespace following a `.`, `!` or `?` (and any closing quotes or
+    /// brackets).
+    fn is_sentence_start(&self, pt: &Point) -> bool {
+        match self.char_at(pt) {
+            Some(ch) if !ch.is_whitespace() => {}
+            _ => return false
+        }
+        let mut p = *pt;
+        let mut skipped_space = false;
+        while p != Point::new(0, 0) {
+            let prev = self.point_before(&p);
+            match self.char_at(&prev) {
+                Some(ch) if ch.is_whitespace() => {
+                    if ch == '\n' && prev.c == 0 {
+                        // An empty line separates paragraphs
+                        return true;
+                    }
+                    skipped_space = true;
+                    p = prev;
+                }
+                Some(ch) if skipped_space => {
+                    let mut ch = ch;
+                    let mut prev = prev;
+                    while "\"')]".contains(ch) && prev.c > 0 {
+                        prev = Point::new(prev.r, prev.c - 1);
+                        ch = self.lines[prev.r][prev.c];
+                    }
+                    return ".!?".contains(ch);
+                }
+                _ => return false
+            }
+        }
+        true
+    }
+
+    fn sentence_forward(&self, pt: &Point) -> Point {
+        let mut p = *pt;
+        while let Some(next) = self.next_point(&p) {
+            if self.is_sentence_start(&next) {
+                return next;
+            }
+            p = next;
+        }
+        p
+    }
+
+    fn sentence_backward(&self, pt: &Point) -> Point {
+        let mut p = self.point_before(pt);
+        while p != Point::new(0, 0) && !self.is_sentence_start(&p) {
+            p = self.point_before(&p);
+        }
+        p
+    }
+}
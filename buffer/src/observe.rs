@@ -0,0 +1,69 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use {Buffer, Edit, Point};
+
+/// A change to the text, as sent to observers: the text from `start` to
+/// `old_end` was `old_text` and is now `new_text`, which ends at `new_end`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ChangeEvent {
+    pub start: Point,
+    #[serde(rename = "oldEnd")]
+    pub old_end: Point,
+    #[serde(rename = "newEnd")]
+    pub new_end: Point,
+    #[serde(rename = "oldText")]
+    pub old_text: String,
+    #[serde(rename = "newText")]
+    pub new_text: String,
+    /// The buffer's version once the change has been made
+    pub version: usize
+}
+
+impl ChangeEvent {
+    /// Returns `edit` as the change that brought the buffer to `version`.
+    pub fn new(edit: &Edit, version: usize) -> ChangeEvent {
+        ChangeEvent {
+            start: edit.start,
+            old_end: edit.old_end(),
+            new_end: edit.new_end(),
+            old_text: edit.deleted.clone(),
+            new_text: edit.inserted.clone(),
+            version: version
+        }
+    }
+
+    /// Returns the change as an edit to the buffer's text.
+    pub fn edit(&self) -> Edit {
+        Edit {
+            start: self.start,
+            deleted: self.old_text.clone(),
+            inserted: self.new_text.clone()
+        }
+    }
+}
+
+/// Something kept in step with the buffer's text by being handed the change
+/// events its observers get.
+pub trait Follow {
+    fn follow(&mut self, event: &ChangeEvent);
+}
+
+impl Buffer {
+    /// Returns a stream of every change made to the text from now on,
+    /// including those made by undo. Dropping the receiver unregisters it.
+    pub fn observe(&mut self) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.observers.push(sender);
+        receiver
+    }
+
+    /// Returns the number of changes made to the text so far.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+}
+
+/// Sends `event` to every observer, forgetting the ones that have gone away.
+pub fn notify(observers: &mut Vec<Sender<ChangeEvent>>, event: &ChangeEvent) {
+    observers.retain(|observer| observer.send(event.clone()).is_ok());
+}
@@ -0,0 +1,158 @@
+use std::cmp;
+
+use {Buffer, BufResult, Line, Point};
+use motion::Motion;
+use textobj::TextObject;
+
+/// An action applied to the text covered by a motion or text object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operator {
+    #[serde(rename = "delete")]
+    Delete,
+    /// Deletes the text, leaving the point where it was for the client to
+    /// start inserting
+    #[serde(rename = "change")]
+    Change,
+    #[serde(rename = "yank")]
+    Yank,
+    #[serde(rename = "indent")]
+    Indent,
+    #[serde(rename = "dedent")]
+    Dedent,
+    #[serde(rename = "uppercase")]
+    Uppercase,
+    #[serde(rename = "lowercase")]
+    Lowercase,
+    #[serde(rename = "toggleCase")]
+    ToggleCase
+}
+
+/// What an operator applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Target {
+    /// The text between the point and where `motion` takes it
+    #[serde(rename = "motion")]
+    Motion {
+        motion: Motion,
+        count: usize
+    },
+    #[serde(rename = "textObject")]
+    TextObject {
+        object: TextObject,
+        inner: bool
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct OpResult {
+    /// Lines changed by the operator
+    pub lines: Vec<Line>,
+    /// The text the operator applied to, before it was changed
+    pub text: String,
+    /// Where the point should be placed afterwards
+    pub point: Point
+}
+
+impl Buffer {
+    /// Returns the region `target` covers when applied at `pt`.
+    pub fn target_range(&self, pt: &Point, target: Target) -> BufResult<(Point, Point)> {
+        match target {
+            Target::Motion { motion, count } => {
+                let dest = self.move_point(pt, motion, count)?;
+                let mut start = cmp::min(*pt, dest);
+                let mut end = cmp::max(*pt, dest);
+                // These motions land on the last character they cover
+                if motion == Motion::WordEnd || motion == Motion::MatchBracket {
+                    end = self.next_point(&end).unwrap_or(end);
+                }
+                // and these cover whole lines
+                if motion == Motion::LineUp || motion == Motion::LineDown {
+                    start = Point::new(start.r, 0);
+                    end = Point::new(cmp::min(end.r + 1, self.lines.len()), 0);
+                    // Take the newline before the last lines rather than
+                    // leaving an empty line behind
+                    if end.r == self.lines.len() && start.r > 0 {
+                        start = Point::new(start.r - 1, self.line_len(start.r - 1));
+                    }
+                }
+                Ok((start, end))
+            }
+            Target::TextObject { object, inner } => self.text_object(pt, object, inner)
+        }
+    }
+
+    /// Applies `op` to the region `target` covers at `pt` as a single undo
+    /// step.
+    pub fn apply_operator(&mut self, op: Operator, pt: &Point, target: Target)
+        -> BufResult<OpResult> {
+        let (start, end) = self.target_range(pt, target)?;
+        let text = self.region_to_str(&start, &end)?;
+        let (lines, point) = match op {
+            Operator::Delete | Operator::Change => {
+                (self.replace_region(&start, &end, "")?, start)
+            }
+            Operator::Yank => (vec![], start),
+            Operator::Indent | Operator::Dedent => {
+                let last = if end.c == 0 && end.r > start.r { end.r - 1 } else { end.r };
+                let lines = self.indent_rows(start.r, last, op == Operator::Indent)?;
+                (lines, self.first_non_blank(start.r))
+            }
+            Operator::Uppercase => (self.replace_region(&start, &end, &text.to_uppercase())?, start),
+            Operator::Lowercase => (self.replace_region(&start, &end, &text.to_lowercase())?, start),
+            Operator::ToggleCase => {
+                let toggled = text.chars().flat_map(|ch| {
+                    if ch.is_uppercase() {
+                        ch.to_lowercase().collect::<Vec<_>>()
+                    } else {
+                        ch.to_uppercase().collect::<Vec<_>>()
+                    }
+                }).collect::<String>();
+                (self.replace_region(&start, &end, &toggled)?, start)
+            }
+        };
+        Ok(OpResult {
+            lines: lines,
+            text: text,
+            point: point
+        })
+    }
+
+    /// Adds or removes one level of indentation on rows `first` through
+    /// `last`. Blank lines aren't indented.
+    pub fn indent_rows(&mut self, first: usize, last: usize, indent: bool) -> BufResult<Vec<Line>> {
+        let last = cmp::min(last, self.lines.len().saturating_sub(1));
+        let unit = self.options.indent_unit();
+        let width = self.options.indent_width;
+        self.begin_transaction();
+        let mut result = Ok(());
+        for r in first..last + 1 {
+            if r >= self.lines.len() {
+                break;
+            }
+            let edit = if indent {
+                if self.is_blank_line(r) {
+                    continue;
+                }
+                self.insert_at_pt(&unit, &Point::new(r, 0)).map(|_| ())
+            } else {
+                // Remove up to one indent width's worth of leading whitespace
+                let mut cols = 0;
+                let mut n = 0;
+                for &ch in &self.lines[r] {
+                    if cols >= width || (ch != ' ' && ch != '\t') {
+                        break;
+                    }
+                    cols += if ch == '\t' { width } else { 1 };
+                    n += 1;
+                }
+                self.delete_region(&Point::new(r, 0), &Point::new(r, n)).map(|_| ())
+            };
+            if edit.is_err() {
+                result = edit;
+                break;
+            }
+        }
+        self.end_transaction_with(result)?;
+        Ok(self.get_lines(first, last + 1))
+    }
+}
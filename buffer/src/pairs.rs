@@ -0,0 +1,190 @@
+use {Buffer, BufResult, Line, Point};
+use syntax::{SyntaxTree, NodeKind};
+
+const OPEN_BRACKETS: [char; 3] = ['(', '[', '{'];
+const CLOSE_BRACKETS: [char; 3] = [')', ']', '}'];
+// Used when there's no grammar to say which characters delimit strings
+const DEFAULT_QUOTES: [char; 2] = ['"', '\''];
+
+impl Buffer {
+    fn quotes(&self) -> Vec<char> {
+        match self.grammar() {
+            Some(grammar) => grammar.string_delimiters,
+            None => DEFAULT_QUOTES.to_vec()
+        }
+    }
+
+    /// Returns the position of the bracket or quote matching the one at `pt`.
+    /// With a grammar set, brackets inside strings and comments are ignored
+    /// and quotes are matched using the highlighted strings. Without one,
+    /// quotes are only matched on the same line.
+    pub fn match_bracket(&self, pt: &Point) -> Option<Point> {
+        let ch = match self.char_at(pt) {
+            Some(ch) => ch,
+            None => return None
+        };
+        if self.highlighter.is_some() {
+            if OPEN_BRACKETS.contains(&ch) || CLOSE_BRACKETS.contains(&ch) ||
+                self.quotes().contains(&ch) {
+                return self.match_in_tree(&self.syntax_tree(), pt, ch);
+            }
+            return None;
+        }
+        if self.quotes().contains(&ch) {
+            return self.match_quote(pt, ch);
+        }
+        if let Some(i) = OPEN_BRACKETS.iter().position(|&b| b == ch) {
+            let mut depth = 0;
+            let mut p = *pt;
+            while let Some(next) = self.next_point(&p) {
+                p = next;
+                match self.char_at(&p) {
+                    Some(c) if c == OPEN_BRACKETS[i] => depth += 1,
+                    Some(c) if c == CLOSE_BRACKETS[i] => {
+                        if depth == 0 {
+                            return Some(p);
+                        }
+                        depth -= 1;
+                    }
+                    _ => {}
+                }
+            }
+        } else if let Some(i) = CLOSE_BRACKETS.iter().position(|&b| b == ch) {
+            let mut depth = 0;
+            let mut p = *pt;
+            while p != Point::new(0, 0) {
+                p = self.point_before(&p);
+                match self.char_at(&p) {
+                    Some(c) if c == CLOSE_BRACKETS[i] => depth += 1,
+                    Some(c) if c == OPEN_BRACKETS[i] => {
+                        if depth == 0 {
+                            return Some(p);
+                        }
+                        depth -= 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        None
+    }
+
+    /// Finds the bracket or string node that starts or ends with `ch` at
+    /// `pt` and returns the other end of it, if it's closed.
+    fn match_in_tree(&self, tree: &SyntaxTree, pt: &Point, ch: char) -> Option<Point> {
+        for child in &tree.children {
+            let node = &child.node;
+            if node.end <= *pt {
+                continue;
+            }
+            if node.start > *pt {
+                break;
+            }
+            match node.kind {
+                NodeKind::Parens | NodeKind::Brackets | NodeKind::Braces | NodeKind::String => {}
+                NodeKind::Region => return self.match_in_tree(child, pt, ch),
+                _ => return None
+            }
+            // Unclosed brackets and strings run to the end of the buffer or
+            // line, so check the node really ends with the closing character
+            let last = self.point_before(&node.end);
+            let closer = if node.kind == NodeKind::String {
+                self.char_at(&node.start)
+            } else {
+                self.char_at(&node.start)
+                    .and_then(|open| OPEN_BRACKETS.iter().position(|&b| b == open))
+                    .map(|i| CLOSE_BRACKETS[i])
+            };
+            let closed = node.end.r < self.lines.len() && last > node.start &&
+                self.char_at(&last) == closer;
+            if node.start == *pt {
+                return if closed { Some(last) } else { None };
+            }
+            if last == *pt && closed && closer == Some(ch) {
+                return Some(node.start);
+            }
+            return self.match_in_tree(child, pt, ch);
+        }
+        None
+    }
+
+    /// Matches the quote at `pt` with another on the same line, treating
+    /// every other unescaped quote as opening a string.
+    fn match_quote(&self, pt: &Point, ch: char) -> Option<Point> {
+        let line = &self.lines[pt.r];
+        let quotes = (0..line.len())
+            .filter(|&c| line[c] == ch && (c == 0 || line[c - 1] != '\\'))
+            .collect::<Vec<_>>();
+        let i = match quotes.iter().position(|&c| c == pt.c) {
+            Some(i) => i,
+            None => return None
+        };
+        let other = if i % 2 == 0 { i + 1 } else { i - 1 };
+        quotes.get(other).map(|&c| Point::new(pt.r, c))
+    }
+
+    /// Returns the character that closes a pair opened by `ch`, if any.
+    fn closer_for(&self, ch: char) -> Option<char> {
+        match OPEN_BRACKETS.iter().position(|&b| b == ch) {
+            Some(i) => Some(CLOSE_BRACKETS[i]),
+            None if self.quotes().contains(&ch) => Some(ch),
+            None => None
+        }
+    }
+
+    /// Returns the region to replace when `string` is typed at `pt`, with the
+    /// text to put before and after the cursor. With `options.auto_pair`
+    /// set, typing an opening bracket or quote also inserts its closer, and
+    /// typing a closer that's already at `pt` types over it.
+    pub fn typed_edit(&self, string: &str, pt: &Point) -> (Point, Point, String, String) {
+        let plain = (*pt, *pt, string.to_string(), String::new());
+        let mut chars = string.chars();
+        let ch = match (chars.next(), chars.next()) {
+            (Some(ch), None) if self.options.auto_pair => ch,
+            _ => return plain
+        };
+        let next = self.char_at(pt);
+        if next == Some(ch) && (CLOSE_BRACKETS.contains(&ch) || self.quotes().contains(&ch)) {
+            if let Some(end) = self.next_point(pt) {
+                return (*pt, end, string.to_string(), String::new());
+            }
+        }
+        let closer = match self.closer_for(ch) {
+            Some(closer) => closer,
+            None => return plain
+        };
+        // Only pair before whitespace or a closer, so that brackets typed in
+        // front of existing text don't get in the way
+        let before_gap = next.map_or(true, |next| {
+            next.is_whitespace() || CLOSE_BRACKETS.contains(&next)
+        });
+        // An apostrophe in a word isn't the start of a string
+        let after_word = pt.c > 0 && pt.r < self.lines.len() &&
+            self.is_word_char(self.lines[pt.r][pt.c - 1]);
+        if !before_gap || (closer == ch && after_word) {
+            return plain;
+        }
+        (*pt, *pt, string.to_string(), closer.to_string())
+    }
+
+    /// Returns whether `pt` is between an opening bracket or quote and its
+    /// closer, with nothing in between.
+    pub fn is_empty_pair(&self, pt: &Point) -> bool {
+        if pt.c == 0 || pt.r >= self.lines.len() || pt.c >= self.lines[pt.r].len() {
+            return false;
+        }
+        let line = &self.lines[pt.r];
+        self.closer_for(line[pt.c - 1]) == Some(line[pt.c])
+    }
+
+    /// Inserts `string` at `pt` as if it were typed, pairing brackets and
+    /// quotes when `options.auto_pair` is set. The cursor belongs after the
+    /// typed text either way.
+    pub fn insert_typed(&mut self, string: &str, pt: &Point) -> BufResult<Vec<Line>> {
+        let (start, end, before, after) = self.typed_edit(string, pt);
+        if start == end && after.is_empty() {
+            return self.insert_at_pt(string, pt);
+        }
+        self.replace_region(&start, &end, &(before + &after))
+    }
+}
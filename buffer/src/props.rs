@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use {Buffer, BufErr, BufResult, Point};
+use observe::{ChangeEvent, Follow};
+
+/// Key-value data attached to a range of text, such as a diagnostic, a link
+/// or an inline hint. It moves with the text like a point does, so text
+/// typed at its start goes inside it and text typed at its end doesn't.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextProperty {
+    pub id: usize,
+    pub start: Point,
+    pub end: Point,
+    pub properties: BTreeMap<String, String>
+}
+
+/// The part of a text property on one line, from column `start` up to but
+/// not including `end`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineProperty {
+    pub id: usize,
+    pub start: usize,
+    pub end: usize,
+    pub properties: BTreeMap<String, String>
+}
+
+impl TextProperty {
+    /// Returns the part of the property on row `r` of `len` characters, if
+    /// any. An empty property is on the row it's at.
+    pub fn on_line(&self, r: usize, len: usize) -> Option<LineProperty> {
+        if r < self.start.r || r > self.end.r ||
+           r == self.end.r && self.end.c == 0 && self.start < self.end {
+            return None;
+        }
+        Some(LineProperty {
+            id: self.id,
+            start: if r == self.start.r { self.start.c } else { 0 },
+            end: if r == self.end.r { self.end.c } else { len },
+            properties: self.properties.clone()
+        })
+    }
+}
+
+impl Buffer {
+    /// Attaches `properties` to the text from `start` to `end` and returns
+    /// the property's id. The range may be empty.
+    pub fn add_property(&mut self, start: &Point, end: &Point,
+                        properties: BTreeMap<String, String>) -> BufResult<usize> {
+        self.region_to_str(start, end)?;
+        let id = self.next_property_id;
+        self.next_property_id += 1;
+        let mut props = self.properties.borrow_mut();
+        let props = &mut *props;
+        props.push(TextProperty {
+            id: id,
+            start: *start,
+            end: *end,
+            properties: properties
+        });
+        props.sort_by_key(|prop| (prop.start, prop.id));
+        Ok(id)
+    }
+
+    /// Removes the property with `id` and returns it.
+    pub fn remove_property(&mut self, id: usize) -> BufResult<TextProperty> {
+        let mut props = self.properties.borrow_mut();
+        let props = &mut *props;
+        match props.iter().position(|prop| prop.id == id) {
+            Some(i) => Ok(props.remove(i)),
+            None => Err(BufErr::NoProperty)
+        }
+    }
+
+    /// Returns every text property, in order of where they start.
+    pub fn properties(&self) -> Vec<TextProperty> {
+        self.properties.borrow().clone()
+    }
+
+    /// Returns the parts of the text properties on row `r`.
+    pub fn properties_on_line(&self, r: usize) -> Vec<LineProperty> {
+        let len = match self.lines.get(r) {
+            Some(line) => line.len(),
+            None => return vec![]
+        };
+        self.properties.borrow().iter()
+            .filter_map(|prop| prop.on_line(r, len))
+            .collect()
+    }
+}
+
+/// Text properties move with the text. Ones whose text was all deleted are
+/// dropped, but ones that were always empty are kept.
+impl Follow for Vec<TextProperty> {
+    fn follow(&mut self, event: &ChangeEvent) {
+        let edit = event.edit();
+        self.retain(|prop| {
+            prop.start == prop.end || edit.shift(&prop.start) != edit.shift(&prop.end)
+        });
+        for prop in self.iter_mut() {
+            prop.start = edit.shift(&prop.start);
+            prop.end = edit.shift(&prop.end);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::cmp;
+
+use {Buffer, BufErr, BufResult, Point};
+use observe::{ChangeEvent, Follow};
+
+impl Buffer {
+    /// Stops the text from `start` to `end` from being changed, except by
+    /// undo. Text can still be inserted just before or after it.
+    pub fn protect_region(&mut self, start: &Point, end: &Point) -> BufResult<()> {
+        self.region_to_str(start, end)?;
+        if start != end {
+            let mut protected = self.protected.borrow_mut();
+            let protected = &mut *protected;
+            protected.push((*start, *end));
+            protected.sort();
+        }
+        Ok(())
+    }
+
+    /// Lets the text from `start` to `end` be changed again, shrinking or
+    /// splitting the protected regions around it.
+    pub fn unprotect_region(&mut self, start: &Point, end: &Point) -> BufResult<()> {
+        self.region_to_str(start, end)?;
+        let mut protected = vec![];
+        for &(s, e) in self.protected.borrow().iter() {
+            if e <= *start || *end <= s {
+                protected.push((s, e));
+                continue;
+            }
+            if s < *start {
+                protected.push((s, *start));
+            }
+            if *end < e {
+                protected.push((*end, e));
+            }
+        }
+        *self.protected.borrow_mut() = protected;
+        Ok(())
+    }
+
+    /// Returns the start and end of every protected region, in order.
+    pub fn protected_regions(&self) -> Vec<(Point, Point)> {
+        self.protected.borrow().clone()
+    }
+
+    /// Returns an error if replacing the text from `start` to `end` would
+    /// change a read-only buffer or protected text. Undo is always allowed
+    /// once it's started, so that it can put protected text back.
+    pub fn check_writable(&self, start: &Point, end: &Point) -> BufResult<()> {
+        if self.undoing {
+            return Ok(());
+        }
+        if self.options.read_only {
+            return Err(BufErr::ReadOnly);
+        }
+        let protected = self.protected.borrow().iter().any(|&(s, e)| {
+            if start == end { s < *start && *start < e } else { s < *end && *start < e }
+        });
+        if protected { Err(BufErr::ReadOnly) } else { Ok(()) }
+    }
+}
+
+/// Protected regions move with the text, and any that end up empty are
+/// dropped. Text inserted at either end of a region goes outside it.
+impl Follow for Vec<(Point, Point)> {
+    fn follow(&mut self, event: &ChangeEvent) {
+        let edit = event.edit();
+        for region in self.iter_mut() {
+            let start = if region.0 == edit.start { edit.new_end() } else { edit.shift(&region.0) };
+            let end = edit.shift(&region.1);
+            *region = (start, cmp::max(start, end));
+        }
+        self.retain(|&(start, end)| start != end);
+    }
+}
@@ -0,0 +1,155 @@
+use fancy_regex::{self, RegexBuilder};
+use std::fmt;
+
+// Most times a search may backtrack before it fails, so that patterns that
+// backtrack badly can't hang the highlighter. Patterns that don't need
+// backtracking are matched in linear time and never reach it.
+const BACKTRACK_LIMIT: usize = 100000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegexErr {
+    Invalid(String),
+    /// A search backtracked too many times and was given up
+    TooComplex
+}
+
+impl fmt::Display for RegexErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegexErr::Invalid(ref what) => { write!(f, "invalid: {}", what) }
+            RegexErr::TooComplex => { write!(f, "search backtracked too many times") }
+        }
+    }
+}
+
+/// The groups a pattern matched, by number, as ranges of chars.
+pub type Captures = Vec<Option<(usize, usize)>>;
+
+/// Text to search, along with where each of its chars starts in it, since
+/// matches are found in the string but given in chars.
+pub struct Text {
+    string: String,
+    starts: Vec<usize>
+}
+
+impl Text {
+    pub fn new(chars: &[char]) -> Text {
+        let string = chars.iter().cloned().collect::<String>();
+        let mut starts = string.char_indices().map(|(i, _)| i).collect::<Vec<_>>();
+        starts.push(string.len());
+        Text {
+            string: string,
+            starts: starts
+        }
+    }
+
+    /// Returns the number of chars in the text.
+    pub fn len(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    /// Returns chars `start` up to `end` of the text.
+    pub fn slice(&self, start: usize, end: usize) -> &str {
+        &self.string[self.starts[start]..self.starts[end]]
+    }
+
+    fn char_at(&self, byte: usize) -> usize {
+        self.starts.binary_search(&byte).unwrap_or_else(|i| i)
+    }
+}
+
+/// A pattern in the Oniguruma syntax TextMate grammars are written in, as
+/// far as the `fancy_regex` crate understands it. `^` and `$` match at the
+/// start and end of lines, as they always do in Oniguruma.
+pub struct Regex {
+    regex: fancy_regex::Regex,
+    // Whether the pattern has a `\G`, which only matches where the search
+    // starts
+    anchored: bool
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RegexErr> {
+        let (translated, anchored) = translate(pattern);
+        let regex = RegexBuilder::new(&format!("(?m){}", translated))
+            .backtrack_limit(BACKTRACK_LIMIT)
+            .build()
+            .map_err(|err| RegexErr::Invalid(err.to_string()))?;
+        Ok(Regex {
+            regex: regex,
+            anchored: anchored
+        })
+    }
+
+    /// Returns the first match in `text` that starts at or after char
+    /// `start`, failing if finding it takes too much backtracking.
+    pub fn find_at(&self, text: &Text, start: usize) -> Result<Option<Captures>, RegexErr> {
+        // `\G` becomes `\A`, which matches where the text searched starts,
+        // so only the rest of the text is searched
+        let offset = if self.anchored { text.starts[start] } else { 0 };
+        let found = self.regex.captures_from_pos(&text.string[offset..],
+                                                 text.starts[start] - offset);
+        let captures = match found {
+            Ok(Some(captures)) => captures,
+            Ok(None) => return Ok(None),
+            Err(fancy_regex::Error::BacktrackLimitExceeded) => return Err(RegexErr::TooComplex),
+            Err(err) => return Err(RegexErr::Invalid(err.to_string()))
+        };
+        Ok(Some((0..captures.len()).map(|i| captures.get(i).map(|group| {
+            (text.char_at(group.start() + offset), text.char_at(group.end() + offset))
+        })).collect()))
+    }
+}
+
+/// Rewrites the parts of `pattern` that Oniguruma has but `fancy_regex`
+/// doesn't: `\h` and `\H` for hex digits, `\Z` and `\G`. Also returns
+/// whether there was a `\G`.
+fn translate(pattern: &str) -> (String, bool) {
+    let mut translated = String::new();
+    let mut anchored = false;
+    let mut in_class = false;
+    let mut chars = pattern.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some('h') if in_class => translated.push_str("0-9a-fA-F"),
+                Some('h') => translated.push_str("[0-9a-fA-F]"),
+                Some('H') if !in_class => translated.push_str("[^0-9a-fA-F]"),
+                Some('Z') if !in_class => translated.push_str(r"(?=\n?\z)"),
+                Some('G') if !in_class => {
+                    anchored = true;
+                    translated.push_str(r"\A");
+                }
+                Some(other) => {
+                    translated.push('\\');
+                    translated.push(other);
+                }
+                None => translated.push('\\')
+            },
+            '[' if !in_class => {
+                in_class = true;
+                translated.push(ch);
+                // A `]` straight after the opening bracket is part of the class
+                if chars.peek() == Some(&'^') {
+                    translated.push('^');
+                    chars.next();
+                }
+                if chars.peek() == Some(&']') {
+                    translated.push(']');
+                    chars.next();
+                }
+            }
+            ']' if in_class => {
+                in_class = false;
+                translated.push(ch);
+            }
+            _ => translated.push(ch)
+        }
+    }
+    (translated, anchored)
+}
+
+/// Escapes `text` so that it matches itself.
+pub fn escape(text: &str) -> String {
+    fancy_regex::escape(text).into_owned()
+}
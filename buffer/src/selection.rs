@@ -0,0 +1,201 @@
+use std::cmp;
+
+use {Buffer, BufErr, BufResult, Edit, Line, Point};
+
+/// A selected range of text. The anchor stays put while the head moves, so
+/// an empty selection is just a cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    pub anchor: Point,
+    pub head: Point
+}
+
+impl Selection {
+    pub fn new(anchor: Point, head: Point) -> Selection {
+        Selection {
+            anchor: anchor,
+            head: head
+        }
+    }
+
+    pub fn cursor(pt: Point) -> Selection {
+        Selection::new(pt, pt)
+    }
+
+    pub fn start(&self) -> Point {
+        cmp::min(self.anchor, self.head)
+    }
+
+    pub fn end(&self) -> Point {
+        cmp::max(self.anchor, self.head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    /// Returns this selection moved to follow `edit`.
+    pub fn shifted(&self, edit: &Edit) -> Selection {
+        Selection::new(edit.shift(&self.anchor), edit.shift(&self.head))
+    }
+}
+
+/// The selections a client has in a buffer, kept sorted and non-overlapping.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SelectionSet {
+    pub selections: Vec<Selection>
+}
+
+impl SelectionSet {
+    pub fn new(selections: Vec<Selection>) -> SelectionSet {
+        let mut set = SelectionSet {
+            selections: selections
+        };
+        set.normalize();
+        set
+    }
+
+    /// Sorts the selections and merges any that overlap or share a cursor
+    /// position.
+    pub fn normalize(&mut self) {
+        self.selections.sort_by(|a, b| a.start().cmp(&b.start()));
+        let mut merged: Vec<Selection> = vec![];
+        for sel in self.selections.drain(..) {
+            let overlaps = match merged.last() {
+                Some(prev) => sel.start() < prev.end() || sel.start() == prev.start() ||
+                    (sel.start() == prev.end() && (sel.is_empty() || prev.is_empty())),
+                None => false
+            };
+            if overlaps {
+                let prev = merged.pop().unwrap();
+                let start = prev.start();
+                let end = cmp::max(prev.end(), sel.end());
+                // Keep the direction of the earlier selection
+                merged.push(if prev.head < prev.anchor {
+                    Selection::new(end, start)
+                } else {
+                    Selection::new(start, end)
+                });
+            } else {
+                merged.push(sel);
+            }
+        }
+        self.selections = merged;
+    }
+
+    /// Moves every selection to follow `edits`, applied in order.
+    pub fn shift(&mut self, edits: &[Edit]) {
+        for edit in edits {
+            for sel in self.selections.iter_mut() {
+                *sel = sel.shifted(edit);
+            }
+        }
+        self.normalize();
+    }
+}
+
+impl Buffer {
+    /// Replaces every selection with `string`, leaving a cursor after each
+    /// insertion. Cursors pair brackets and quotes as `insert_typed` does.
+    /// All of the insertions form a single undo step.
+    pub fn insert_at_selections(&mut self, string: &str, sels: &mut SelectionSet)
+        -> BufResult<Vec<Line>> {
+        self.edit_selections_around(sels, |buf, sel| {
+            if sel.is_empty() {
+                buf.typed_edit(string, &sel.head)
+            } else {
+                (sel.start(), sel.end(), string.to_string(), String::new())
+            }
+        })
+    }
+
+    /// Deletes every selection. Empty selections delete the character before
+    /// the cursor instead, along with the one after it if `options.auto_pair`
+    /// is set and they're an empty pair. All of the deletions form a single
+    /// undo step.
+    pub fn delete_selections(&mut self, sels: &mut SelectionSet) -> BufResult<Vec<Line>> {
+        self.edit_selections(sels, |buf, sel| {
+            if sel.is_empty() && buf.options.auto_pair && buf.is_empty_pair(&sel.head) {
+                let after = Point::new(sel.head.r, sel.head.c + 1);
+                (buf.point_before(&sel.head), after, String::new())
+            } else if sel.is_empty() {
+                (buf.point_before(&sel.head), sel.head, String::new())
+            } else {
+                (sel.start(), sel.end(), String::new())
+            }
+        })
+    }
+
+    /// Returns the point one character before `pt`, wrapping to the end of
+    /// the previous line.
+    pub fn point_before(&self, pt: &Point) -> Point {
+        if pt.c > 0 {
+            Point::new(pt.r, pt.c - 1)
+        } else if pt.r > 0 {
+            Point::new(pt.r - 1, self.lines[pt.r - 1].len())
+        } else {
+            *pt
+        }
+    }
+
+    /// Replaces the region `f` computes for each selection with the string it
+    /// returns, then collapses each selection to a cursor after its
+    /// replacement. Nothing is changed unless every region is valid.
+    pub fn edit_selections<F>(&mut self, sels: &mut SelectionSet, mut f: F)
+        -> BufResult<Vec<Line>> where F: FnMut(&Buffer, &Selection) -> (Point, Point, String) {
+        self.edit_selections_around(sels, |buf, sel| {
+            let (start, end, string) = f(buf, sel);
+            (start, end, string, String::new())
+        })
+    }
+
+    /// Like `edit_selections`, but `f` also returns text to insert after
+    /// the cursor.
+    fn edit_selections_around<F>(&mut self, sels: &mut SelectionSet, mut f: F)
+        -> BufResult<Vec<Line>>
+        where F: FnMut(&Buffer, &Selection) -> (Point, Point, String, String) {
+        sels.normalize();
+        if sels.selections.len() == 0 {
+            return Ok(vec![]);
+        }
+        let regions = sels.selections.iter().map(|sel| f(self, sel)).collect::<Vec<_>>();
+        for &(ref start, ref end, _, _) in &regions {
+            if !self.is_valid_point(start) {
+                return Err(BufErr::InvalidStartPoint);
+            }
+            if !self.is_valid_point(end) {
+                return Err(BufErr::InvalidEndPoint);
+            }
+            if end < start {
+                return Err(BufErr::InvalidDeletionLength);
+            }
+        }
+
+        self.begin_transaction();
+        let mut result = Ok(());
+        let mut new_sels = sels.selections.clone();
+        // Work backwards so that earlier regions are still where we computed
+        // them to be, shifting the cursors we've already placed as we go
+        for (i, &(start, end, ref before, ref after)) in regions.iter().enumerate().rev() {
+            let string = before.clone() + after;
+            result = self.delete_region(&start, &end)
+                .and_then(|_| self.insert_at_pt(&string, &start))
+                .map(|_| ());
+            if result.is_err() {
+                break;
+            }
+            let new_end = start.advanced_by(&string);
+            for sel in new_sels[i + 1..].iter_mut() {
+                *sel = Selection::new(sel.anchor.shifted(&start, &end, &new_end),
+                                      sel.head.shifted(&start, &end, &new_end));
+            }
+            new_sels[i] = Selection::cursor(start.advanced_by(before));
+        }
+        self.end_transaction_with(result)?;
+
+        let first_row = regions[0].0.r;
+        let last_row = new_sels[new_sels.len() - 1].head.r;
+        *sels = SelectionSet::new(new_sels);
+        Ok(self.get_lines(first_row, last_row + 1))
+    }
+}
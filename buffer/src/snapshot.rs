@@ -0,0 +1,193 @@
+use std::cmp;
+use std::sync::Arc;
+
+use {Buffer, Edit, IntoLine, Line, TextProperty};
+use observe::{ChangeEvent, Follow};
+
+// Most rows a chunk holds. Editing a row copies the rest of its chunk, and
+// cloning a snapshot copies a pointer per chunk.
+const CHUNK_ROWS: usize = 64;
+
+/// Rows of text split into chunks that are shared between snapshots until
+/// an edit changes the rows in them.
+#[derive(Clone)]
+pub struct Rows {
+    chunks: Vec<Arc<Vec<Vec<char>>>>,
+    len: usize
+}
+
+impl Rows {
+    pub fn new(lines: &[Vec<char>]) -> Rows {
+        let mut rows = Rows {
+            chunks: vec![],
+            len: 0
+        };
+        rows.splice(0, 0, lines.to_vec());
+        rows
+    }
+
+    fn get(&self, r: usize) -> Option<&Vec<char>> {
+        let mut start = 0;
+        for chunk in &self.chunks {
+            if r < start + chunk.len() {
+                return Some(&chunk[r - start]);
+            }
+            start += chunk.len();
+        }
+        None
+    }
+
+    /// Replaces `n_old` rows starting at `row` with `rows`, copying only the
+    /// chunks they're in and the one after them.
+    fn splice(&mut self, row: usize, n_old: usize, rows: Vec<Vec<char>>) {
+        let mut first = 0;
+        let mut first_row = 0;
+        while first + 1 < self.chunks.len() && first_row + self.chunks[first].len() <= row {
+            first_row += self.chunks[first].len();
+            first += 1;
+        }
+        // Taking the next chunk as well stops small chunks from piling up
+        let mut last = first;
+        let mut last_row = first_row;
+        while last < self.chunks.len() && (last == first || last_row <= row + n_old) {
+            last_row += self.chunks[last].len();
+            last += 1;
+        }
+        let mut merged = vec![];
+        for chunk in &self.chunks[first..last] {
+            merged.extend(chunk.iter().cloned());
+        }
+        let start = row - first_row;
+        self.len = self.len + rows.len() - n_old;
+        let after = merged.split_off(start + n_old);
+        merged.truncate(start);
+        merged.extend(rows);
+        merged.extend(after);
+
+        let mut chunks = vec![];
+        if merged.len() > 0 {
+            let n_chunks = (merged.len() + CHUNK_ROWS - 1) / CHUNK_ROWS;
+            let size = (merged.len() + n_chunks - 1) / n_chunks;
+            chunks.extend(merged.chunks(size).map(|chunk| Arc::new(chunk.to_vec())));
+        }
+        let after = self.chunks.split_off(last);
+        self.chunks.truncate(first);
+        self.chunks.extend(chunks);
+        self.chunks.extend(after);
+    }
+
+    /// Makes the same change to the rows as `edit` made to the buffer.
+    pub fn edit(&mut self, edit: &Edit) {
+        let old_end = edit.old_end();
+        let first = edit.start.r;
+        let last = cmp::min(old_end.r + 1, self.len);
+        // The affected rows as text, each ending in a newline
+        let mut text = vec![];
+        let mut end = old_end.c;
+        for r in first..last {
+            let row = self.get(r).unwrap();
+            if r < old_end.r {
+                end += row.len() + 1;
+            }
+            text.extend(row.iter().cloned());
+            text.push('\n');
+        }
+        let after = text.split_off(end);
+        text.truncate(edit.start.c);
+        text.extend(edit.inserted.chars());
+        text.extend(after);
+        let mut rows = text.split(|&ch| ch == '\n').map(|row| row.to_vec()).collect::<Vec<_>>();
+        // What follows the last newline
+        debug_assert!(rows[rows.len() - 1].is_empty());
+        rows.pop();
+        self.splice(first, last - first, rows);
+    }
+}
+
+/// Rows are only kept up to date once a snapshot has been taken.
+impl Follow for Option<Rows> {
+    fn follow(&mut self, event: &ChangeEvent) {
+        if let Some(ref mut rows) = *self {
+            rows.edit(&event.edit());
+        }
+    }
+}
+
+/// An unchanging copy of a buffer's text and text properties, which is
+/// cheap to make and to clone and can be read from any thread while the
+/// buffer goes on being edited.
+#[derive(Clone)]
+pub struct Snapshot {
+    rows: Rows,
+    text_len: usize,
+    properties: Arc<Vec<TextProperty>>,
+    version: usize
+}
+
+impl Snapshot {
+    pub fn len(&self) -> usize {
+        self.rows.len
+    }
+
+    /// Returns the length of the text in bytes, like `Buffer::text_len`.
+    pub fn text_len(&self) -> usize {
+        self.text_len
+    }
+
+    /// Returns the buffer's version when the snapshot was taken.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    pub fn line(&self, r: usize) -> &[char] {
+        match self.rows.get(r) {
+            Some(row) => row,
+            None => panic!("row {} out of range", r)
+        }
+    }
+
+    /// Returns rows `start` up to but not including `end` with their text
+    /// properties, stopping early at the end of the snapshot.
+    pub fn get_lines(&self, start: usize, end: usize) -> Vec<Line> {
+        (start..cmp::min(end, self.len())).map(|r| {
+            let line = self.line(r);
+            let mut line_obj = line.iter().cloned().collect::<String>().into_line(r);
+            line_obj.properties = self.properties.iter()
+                .filter_map(|prop| prop.on_line(r, line.len()))
+                .collect();
+            line_obj
+        }).collect()
+    }
+
+    pub fn properties(&self) -> &[TextProperty] {
+        &self.properties
+    }
+
+    pub fn to_str(&self) -> String {
+        let mut string = String::with_capacity(self.text_len);
+        for chunk in &self.rows.chunks {
+            for row in chunk.iter() {
+                string.extend(row.iter());
+                string.push('\n');
+            }
+        }
+        string
+    }
+}
+
+impl Buffer {
+    /// Returns a snapshot of the buffer as it is now. The first one copies
+    /// every row, but later ones only copy the rows edited since.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut rows = self.snapshot_rows.borrow_mut();
+        if rows.is_none() {
+            *rows = Some(Rows::new(&self.lines));
+        }
+        Snapshot {
+            rows: rows.as_ref().unwrap().clone(),
+            text_len: self.text_len,
+            properties: Arc::new(self.properties()),
+            version: self.version
+        }
+    }
+}
@@ -0,0 +1,455 @@
+use std::cmp;
+use std::collections::HashMap;
+
+use {Buffer, BufErr, BufResult, Edit, Line, Point};
+use observe::{ChangeEvent, Follow};
+use selection::Selection;
+
+/// A place in an inserted snippet to jump to. Every range holds the same
+/// text: the first is the placeholder and the rest mirror it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TabStop {
+    pub index: usize,
+    pub ranges: Vec<Selection>,
+    /// Alternatives the client can offer for the placeholder
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<String>
+}
+
+/// Parsed snippet syntax, before tab stops without text of their own are
+/// given their placeholder's.
+#[derive(Debug)]
+enum Part {
+    Text(String),
+    Stop {
+        index: usize,
+        children: Vec<Part>,
+        choices: Vec<String>
+    }
+}
+
+struct Parser<F> where F: Fn(&str) -> Option<String> {
+    chars: Vec<char>,
+    pos: usize,
+    // Returns the value of a variable, or None to use its default
+    variable: F
+}
+
+impl<F> Parser<F> where F: Fn(&str) -> Option<String> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        if self.peek() == Some(ch) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, ch: char) -> BufResult<()> {
+        if self.eat(ch) { Ok(()) } else { Err(BufErr::InvalidSnippet) }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().map_or(false, |ch| ch.is_digit(10)) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().cloned().collect::<String>().parse().ok()
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let start = self.pos;
+        while self.peek().map_or(false, |ch| ch == '_' || ch.is_alphanumeric()) {
+            self.pos += 1;
+        }
+        if self.pos > start && !self.chars[start].is_digit(10) {
+            Some(self.chars[start..self.pos].iter().cloned().collect())
+        } else {
+            self.pos = start;
+            None
+        }
+    }
+
+    /// Reads text up to an unescaped character in `stop`. A backslash
+    /// escapes `$`, `}`, `\` and anything in `stop`.
+    fn text(&mut self, stop: &str) -> String {
+        let mut text = String::new();
+        while let Some(ch) = self.peek() {
+            if ch == '$' || stop.contains(ch) {
+                break;
+            }
+            self.pos += 1;
+            if ch == '\\' {
+                match self.peek() {
+                    Some(next) if next == '$' || next == '}' || next == '\\' ||
+                        stop.contains(next) => {
+                        text.push(next);
+                        self.pos += 1;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            text.push(ch);
+        }
+        text
+    }
+
+    /// Parses everything up to an unescaped `}` if `nested` is set, or the
+    /// end of the snippet otherwise.
+    fn parts(&mut self, nested: bool) -> BufResult<Vec<Part>> {
+        let mut parts = vec![];
+        loop {
+            let text = self.text(if nested { "}" } else { "" });
+            if !text.is_empty() {
+                parts.push(Part::Text(text));
+            }
+            match self.peek() {
+                None => return if nested { Err(BufErr::InvalidSnippet) } else { Ok(parts) },
+                Some('}') => return Ok(parts),
+                _ => {}
+            }
+            self.pos += 1;
+            let part = self.dollar()?;
+            parts.extend(part);
+        }
+    }
+
+    /// Parses what follows a `$`.
+    fn dollar(&mut self) -> BufResult<Vec<Part>> {
+        let braced = self.eat('{');
+        if let Some(index) = self.number() {
+            let mut children = vec![];
+            let mut choices = vec![];
+            if braced {
+                if self.eat(':') {
+                    children = self.parts(true)?;
+                } else if self.eat('|') {
+                    loop {
+                        choices.push(self.text(",|"));
+                        if !self.eat(',') {
+                            break;
+                        }
+                    }
+                    self.expect('|')?;
+                }
+                self.expect('}')?;
+            }
+            return Ok(vec![Part::Stop {
+                index: index,
+                children: children,
+                choices: choices
+            }]);
+        }
+        let name = match self.name() {
+            Some(name) => name,
+            // A lone dollar sign is just text
+            None if !braced => return Ok(vec![Part::Text("$".to_string())]),
+            None => return Err(BufErr::InvalidSnippet)
+        };
+        let mut default = vec![];
+        if braced {
+            if self.eat(':') {
+                default = self.parts(true)?;
+            }
+            self.expect('}')?;
+        }
+        Ok(match (self.variable)(&name) {
+            Some(value) => vec![Part::Text(value)],
+            None => default
+        })
+    }
+}
+
+/// Snippet text with tab stops as character offsets into it.
+struct Rendered {
+    text: Vec<char>,
+    // Index, start and end of each occurrence of a tab stop, and whether
+    // it's a placeholder rather than a mirror
+    stops: Vec<(usize, usize, usize, bool)>,
+    choices: HashMap<usize, Vec<String>>
+}
+
+/// Finds the text each tab stop should have: its first placeholder, or its
+/// first choice.
+fn collect_defaults(parts: &[Part], defaults: &mut HashMap<usize, Vec<Part>>,
+                    choices: &mut HashMap<usize, Vec<String>>) {
+    for part in parts {
+        if let Part::Stop { index, ref children, choices: ref stop_choices } = *part {
+            if !stop_choices.is_empty() && !choices.contains_key(&index) {
+                choices.insert(index, stop_choices.clone());
+                defaults.entry(index).or_insert(vec![Part::Text(stop_choices[0].clone())]);
+            }
+            if !children.is_empty() && !defaults.contains_key(&index) {
+                // Children are rendered in place, so only their text is
+                // copied to mirrors
+                defaults.insert(index, vec![Part::Text(plain_text(children))]);
+            }
+            collect_defaults(children, defaults, choices);
+        }
+    }
+}
+
+fn plain_text(parts: &[Part]) -> String {
+    parts.iter().map(|part| match *part {
+        Part::Text(ref text) => text.clone(),
+        Part::Stop { ref children, ref choices, .. } => {
+            if children.is_empty() {
+                choices.first().cloned().unwrap_or_default()
+            } else {
+                plain_text(children)
+            }
+        }
+    }).collect()
+}
+
+impl Buffer {
+    fn render(&self, parts: &[Part], defaults: &HashMap<usize, Vec<Part>>, indent: &str,
+              rendered: &mut Rendered) {
+        for part in parts {
+            match *part {
+                Part::Text(ref text) => {
+                    for ch in text.chars() {
+                        match ch {
+                            '\n' => {
+                                rendered.text.push('\n');
+                                rendered.text.extend(indent.chars());
+                            }
+                            '\t' => rendered.text.extend(self.options.indent_unit().chars()),
+                            _ => rendered.text.push(ch)
+                        }
+                    }
+                }
+                Part::Stop { index, ref children, ref choices } => {
+                    let start = rendered.text.len();
+                    let i = rendered.stops.len();
+                    let placeholder = !children.is_empty() || !choices.is_empty();
+                    rendered.stops.push((index, start, start, placeholder));
+                    if children.is_empty() {
+                        if let Some(default) = defaults.get(&index) {
+                            self.render(default, defaults, indent, rendered);
+                        }
+                    } else {
+                        self.render(children, defaults, indent, rendered);
+                    }
+                    rendered.stops[i].2 = rendered.text.len();
+                }
+            }
+        }
+    }
+
+    fn snippet_variable(&self, pt: &Point, name: &str) -> Option<String> {
+        match name {
+            "TM_CURRENT_LINE" if pt.r < self.lines.len() => {
+                Some(self.lines[pt.r].iter().cloned().collect())
+            }
+            "TM_LINE_INDEX" => Some(pt.r.to_string()),
+            "TM_LINE_NUMBER" => Some((pt.r + 1).to_string()),
+            _ => None
+        }
+    }
+
+    /// Inserts `snippet` at `pt` as a single edit and starts jumping between
+    /// its tab stops, returning the changed lines and the first stop. Tab
+    /// stops are written `$1` or `${1}`, placeholders `${1:text}`, choices
+    /// `${1|one,two|}` and variables `$NAME` or `${NAME:default}`. Repeating
+    /// a tab stop mirrors its text. `$0` is where the cursor ends up, the end
+    /// of the snippet if it isn't given. New lines are indented like the row
+    /// of `pt`.
+    pub fn insert_snippet(&mut self, snippet: &str, pt: &Point)
+        -> BufResult<(Vec<Line>, TabStop)> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        let parts = {
+            let mut parser = Parser {
+                chars: snippet.chars().collect(),
+                pos: 0,
+                variable: |name: &str| self.snippet_variable(pt, name)
+            };
+            parser.parts(false)?
+        };
+        let mut defaults = HashMap::new();
+        let mut rendered = Rendered {
+            text: vec![],
+            stops: vec![],
+            choices: HashMap::new()
+        };
+        collect_defaults(&parts, &mut defaults, &mut rendered.choices);
+        let indent = if pt.r < self.lines.len() {
+            self.lines[pt.r][..self.first_non_blank(pt.r).c].iter().cloned().collect()
+        } else {
+            String::new()
+        };
+        self.render(&parts, &defaults, &indent, &mut rendered);
+        if !rendered.stops.iter().any(|&(index, _, _, _)| index == 0) {
+            let end = rendered.text.len();
+            rendered.stops.push((0, end, end, false));
+        }
+
+        let text = rendered.text.iter().cloned().collect::<String>();
+        let lines = self.insert_at_pt(&text, pt)?;
+        let offset_point = |offset: usize| {
+            pt.advanced_by(&rendered.text[..offset].iter().cloned().collect::<String>())
+        };
+        let mut stops: Vec<TabStop> = vec![];
+        for &(index, start, end, _) in &rendered.stops {
+            let range = Selection::new(offset_point(start), offset_point(end));
+            if let Some(stop) = stops.iter_mut().find(|stop| stop.index == index) {
+                stop.ranges.push(range);
+                continue;
+            }
+            stops.push(TabStop {
+                index: index,
+                ranges: vec![range],
+                choices: rendered.choices.get(&index).cloned().unwrap_or_default()
+            });
+        }
+        // Placeholders go first so that mirrors copy them
+        for stop in stops.iter_mut() {
+            if let Some(i) = rendered.stops.iter()
+                .filter(|&&(index, _, _, _)| index == stop.index)
+                .position(|&(_, _, _, placeholder)| placeholder) {
+                let range = stop.ranges.remove(i);
+                stop.ranges.insert(0, range);
+            }
+        }
+        // $0 comes last
+        stops.sort_by_key(|stop| if stop.index == 0 { usize::max_value() } else { stop.index });
+        let first = stops[0].clone();
+        *self.snippet.borrow_mut() = if stops.len() > 1 {
+            Some(SnippetSession {
+                stops: stops,
+                current: 0
+            })
+        } else {
+            None
+        };
+        Ok((lines, first))
+    }
+
+    /// Moves to the next tab stop of the snippet being filled in. Reaching
+    /// `$0` finishes the snippet.
+    pub fn next_tab_stop(&mut self) -> BufResult<TabStop> {
+        self.update_snippet_mirrors();
+        let mut snippet = self.snippet.borrow_mut();
+        let (stop, finished) = match *snippet {
+            Some(ref mut session) => {
+                session.current = cmp::min(session.current + 1, session.stops.len() - 1);
+                (session.stops[session.current].clone(),
+                 session.current == session.stops.len() - 1)
+            }
+            None => return Err(BufErr::NoSnippet)
+        };
+        if finished {
+            *snippet = None;
+        }
+        Ok(stop)
+    }
+
+    /// Moves back to the previous tab stop, or stays on the first.
+    pub fn prev_tab_stop(&mut self) -> BufResult<TabStop> {
+        self.update_snippet_mirrors();
+        match *self.snippet.borrow_mut() {
+            Some(ref mut session) => {
+                session.current = session.current.saturating_sub(1);
+                Ok(session.stops[session.current].clone())
+            }
+            None => Err(BufErr::NoSnippet)
+        }
+    }
+
+    /// Returns the tab stop being filled in, if there is one.
+    pub fn current_tab_stop(&self) -> Option<TabStop> {
+        self.snippet.borrow().as_ref()
+            .map(|session| session.stops[session.current].clone())
+    }
+
+    /// Stops jumping between tab stops, leaving the text as it is.
+    pub fn cancel_snippet(&mut self) {
+        *self.snippet.borrow_mut() = None;
+    }
+
+    /// Copies each placeholder's text to its mirrors where it's changed. The
+    /// copies are part of the most recent undo step, so undoing the change
+    /// to the placeholder undoes them too.
+    pub fn update_snippet_mirrors(&mut self) {
+        let max_copies = match *self.snippet.borrow() {
+            Some(ref session) => session.stops.iter().map(|stop| stop.ranges.len()).sum(),
+            None => return
+        };
+        if self.transaction_depth == 0 {
+            if let Some(edits) = self.undo_stack.pop() {
+                self.transaction = edits;
+            }
+        }
+        self.begin_transaction();
+        for _ in 0..max_copies {
+            let copy = self.snippet.borrow().as_ref().and_then(|session| {
+                session.stops.iter().filter_map(|stop| {
+                    let text = self.region_to_str(&stop.ranges[0].start(), &stop.ranges[0].end())
+                        .unwrap_or_default();
+                    stop.ranges[1..].iter()
+                        .find(|range| {
+                            self.region_to_str(&range.start(), &range.end()).ok().as_ref() !=
+                                Some(&text)
+                        })
+                        .map(|range| (range.start(), range.end(), text.clone()))
+                }).next()
+            });
+            match copy {
+                Some((start, end, text)) => {
+                    if self.replace_region(&start, &end, &text).is_err() {
+                        break;
+                    }
+                }
+                None => break
+            }
+        }
+        self.end_transaction();
+    }
+}
+
+/// The tab stops of the snippet being filled in, kept up to date with
+/// edits.
+pub struct SnippetSession {
+    stops: Vec<TabStop>,
+    current: usize
+}
+
+impl SnippetSession {
+    /// Moves every tab stop to follow `edit`. Text inserted at the end of a
+    /// range goes into it, so typing after a placeholder or into an empty
+    /// one extends it.
+    fn edit(&mut self, edit: &Edit) {
+        for stop in self.stops.iter_mut() {
+            for range in stop.ranges.iter_mut() {
+                // Text inserted at the start of a placeholder goes before it,
+                // unless it's empty
+                let start = if range.start() == edit.start && !range.is_empty() {
+                    edit.new_end()
+                } else {
+                    edit.shift(&range.start())
+                };
+                let end = if range.end() == edit.start {
+                    edit.new_end()
+                } else {
+                    edit.shift(&range.end())
+                };
+                *range = Selection::new(start, cmp::max(start, end));
+            }
+        }
+    }
+}
+
+impl Follow for Option<SnippetSession> {
+    fn follow(&mut self, event: &ChangeEvent) {
+        if let Some(ref mut session) = *self {
+            session.edit(&event.edit());
+        }
+    }
+}
@@ -0,0 +1,545 @@
+use std::cmp;
+
+use {Buffer, BufErr, BufResult, Point};
+use highlight::{LineState, Span, Style};
+use observe::{ChangeEvent, Follow};
+use textmate::{self, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeKind {
+    /// The whole buffer
+    #[serde(rename = "document")]
+    Document,
+    #[serde(rename = "parens")]
+    Parens,
+    #[serde(rename = "brackets")]
+    Brackets,
+    #[serde(rename = "braces")]
+    Braces,
+    #[serde(rename = "string")]
+    String,
+    /// A comment, or several with nothing but line breaks between them
+    #[serde(rename = "comment")]
+    Comment,
+    /// A run of word characters, e.g. an identifier, keyword or number
+    #[serde(rename = "word")]
+    Word,
+    /// A region of a TextMate grammar, from where its begin pattern matched
+    /// up to where its end pattern did
+    #[serde(rename = "region")]
+    Region,
+    /// Text that a rule of a TextMate grammar matched
+    #[serde(rename = "token")]
+    Token
+}
+
+/// A node of the syntax tree, from `start` up to but not including `end`.
+/// Nodes from a TextMate grammar have the scope of the rule that matched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub scope: Option<String>,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "parent")]
+    Parent,
+    #[serde(rename = "firstChild")]
+    FirstChild,
+    #[serde(rename = "lastChild")]
+    LastChild,
+    #[serde(rename = "nextSibling")]
+    NextSibling,
+    #[serde(rename = "prevSibling")]
+    PrevSibling
+}
+
+/// The syntax of a buffer. With a TextMate grammar, its nodes are the
+/// regions and tokens that the grammar's rules matched, with the brackets
+/// and words between them. Other grammars only say where strings and
+/// comments are, so the tree is just how brackets nest around them.
+#[derive(Debug, Clone)]
+pub struct SyntaxTree {
+    pub node: Node,
+    pub children: Vec<SyntaxTree>,
+    // Whether a region of the grammar opened the node, rather than a bracket
+    region: bool
+}
+
+impl SyntaxTree {
+    fn new(kind: NodeKind, scope: Option<String>, start: Point, end: Point) -> SyntaxTree {
+        SyntaxTree {
+            node: Node {
+                kind: kind,
+                scope: scope,
+                start: start,
+                end: end
+            },
+            children: vec![],
+            region: false
+        }
+    }
+
+    fn contains(&self, start: &Point, end: &Point) -> bool {
+        self.node.start <= *start && *end <= self.node.end
+    }
+
+    fn is(&self, start: &Point, end: &Point) -> bool {
+        self.node.start == *start && self.node.end == *end
+    }
+
+    /// Returns the indices of the children leading to the deepest node
+    /// containing `start` to `end`.
+    fn path_to(&self, start: &Point, end: &Point) -> Vec<usize> {
+        let mut path = vec![];
+        let mut tree = self;
+        loop {
+            match tree.children.iter().position(|child| child.contains(start, end)) {
+                Some(i) => {
+                    path.push(i);
+                    tree = &tree.children[i];
+                }
+                None => return path
+            }
+        }
+    }
+
+    fn at(&self, path: &[usize]) -> &SyntaxTree {
+        path.iter().fold(self, |tree, &i| &tree.children[i])
+    }
+
+    fn collect(&self, kind: Option<NodeKind>, scope: Option<&str>, start: &Point, end: &Point,
+               nodes: &mut Vec<Node>) {
+        let overlaps = if start == end {
+            self.node.start <= *start && *start <= self.node.end
+        } else {
+            self.node.start < *end && *start < self.node.end
+        };
+        if !overlaps {
+            return;
+        }
+        let in_scope = |selector| {
+            self.node.scope.as_ref().map_or(false, |scope| textmate::in_scope(scope, selector))
+        };
+        if kind.map_or(true, |kind| kind == self.node.kind) && scope.map_or(true, in_scope) {
+            nodes.push(self.node.clone());
+        }
+        for child in &self.children {
+            child.collect(kind, scope, start, end, nodes);
+        }
+    }
+
+    /// Moves the tree and everything in it `added` rows down and `removed`
+    /// rows up.
+    fn shift_rows(&mut self, added: usize, removed: usize) {
+        self.node.start.r = self.node.start.r + added - removed;
+        self.node.end.r = self.node.end.r + added - removed;
+        for child in self.children.iter_mut() {
+            child.shift_rows(added, removed);
+        }
+    }
+}
+
+/// The syntax tree of a buffer, kept between edits so that only the rows
+/// that changed need scanning again. The top level nodes before and after
+/// them are reused as they are.
+pub struct TreeCache {
+    tree: Option<SyntaxTree>,
+    // For each row, the highlighter state it starts in if the tree can be
+    // split before it: no bracket is open there and no top level node runs
+    // across it
+    splits: Vec<Option<LineState>>,
+    // Rows from dirty.0 up to dirty.1 have changed since the tree was built
+    dirty: Option<(usize, usize)>
+}
+
+impl TreeCache {
+    pub fn new() -> TreeCache {
+        TreeCache {
+            tree: None,
+            splits: vec![],
+            dirty: None
+        }
+    }
+
+    /// Throws the tree away, such as when the grammar changes.
+    pub fn clear(&mut self) {
+        self.tree = None;
+        self.splits.clear();
+        self.dirty = None;
+    }
+}
+
+impl Follow for TreeCache {
+    fn follow(&mut self, event: &ChangeEvent) {
+        let tree = match self.tree {
+            Some(ref mut tree) => tree,
+            None => return
+        };
+        let start = event.start.r;
+        let old_end = event.old_end.r;
+        let new_end = event.new_end.r;
+        if old_end >= self.splits.len() {
+            self.splits.resize(old_end + 1, None);
+        }
+        let removed = old_end - start;
+        let added = new_end - start;
+        self.splits.drain(start + 1..old_end + 1);
+        for _ in 0..added {
+            self.splits.insert(start + 1, None);
+        }
+        // Top level nodes on the changed rows are scanned again, and the
+        // ones after them move with the text
+        tree.children.retain(|child| {
+            child.node.end <= Point::new(start, 0) || child.node.start.r > old_end
+        });
+        for child in tree.children.iter_mut() {
+            if child.node.start.r > old_end {
+                child.shift_rows(added, removed);
+            }
+        }
+        self.dirty = Some(match self.dirty {
+            Some((from, to)) => {
+                let to = if to > old_end { to + added - removed } else { to };
+                (cmp::min(from, start), cmp::max(to, new_end + 1))
+            }
+            None => (start, new_end + 1)
+        });
+    }
+}
+
+/// Returns the kind of node for text in `scope` that's a string or comment.
+fn scope_kind(scope: &str) -> Option<NodeKind> {
+    if textmate::in_scope(scope, "string") {
+        Some(NodeKind::String)
+    } else if textmate::in_scope(scope, "comment") {
+        Some(NodeKind::Comment)
+    } else {
+        None
+    }
+}
+
+fn bracket_kind(ch: char) -> Option<(NodeKind, bool)> {
+    match ch {
+        '(' => Some((NodeKind::Parens, true)),
+        ')' => Some((NodeKind::Parens, false)),
+        '[' => Some((NodeKind::Brackets, true)),
+        ']' => Some((NodeKind::Brackets, false)),
+        '{' => Some((NodeKind::Braces, true)),
+        '}' => Some((NodeKind::Braces, false)),
+        _ => None
+    }
+}
+
+/// Adds `tree` to `parent`, merging it into the last child if both are
+/// strings or comments and only a line break is between them. Returns
+/// whether they were merged.
+fn add_child(parent: &mut SyntaxTree, tree: SyntaxTree, lines: &[Vec<char>]) -> bool {
+    if let Some(last) = parent.children.last_mut() {
+        let kind = tree.node.kind;
+        if last.node.kind == kind && (kind == NodeKind::String || kind == NodeKind::Comment) &&
+            tree.node.start.c == 0 && last.node.end.r + 1 == tree.node.start.r &&
+            last.node.end.c == lines[last.node.end.r].len() {
+            last.node.end = tree.node.end;
+            return true;
+        }
+    }
+    parent.children.push(tree);
+    false
+}
+
+/// Returns whether the innermost node open in `stack` is a string or comment
+/// region, inside which brackets and words aren't nodes of their own.
+fn in_literal(stack: &[SyntaxTree]) -> bool {
+    stack.last().map_or(false, |tree| {
+        tree.region && (tree.node.kind == NodeKind::String || tree.node.kind == NodeKind::Comment)
+    })
+}
+
+impl Buffer {
+    /// Returns the syntax tree, scanning whatever rows have changed since it
+    /// was last asked for. What the grammar matched comes from the
+    /// highlighter, which likewise only highlights rows again where the
+    /// buffer changed.
+    pub fn syntax_tree(&self) -> SyntaxTree {
+        let mut cache = self.syntax_tree.borrow_mut();
+        let cache = &mut *cache;
+        if cache.tree.is_none() {
+            cache.splits = vec![None; self.lines.len()];
+            cache.dirty = Some((0, self.lines.len()));
+        }
+        if let Some((from, to)) = cache.dirty.take() {
+            self.update_syntax_tree(cache, from, to);
+        }
+        cache.tree.clone().unwrap()
+    }
+
+    /// Scans the rows from `from` up to `to` again, along with any before
+    /// them back to where the tree can be split and any after them up to
+    /// where it's the same as before.
+    fn update_syntax_tree(&self, cache: &mut TreeCache, from: usize, to: usize) {
+        let mut highlighter = self.highlighter.as_ref().map(|highlighter| highlighter.borrow_mut());
+        let mut row_info = |r: usize| -> (LineState, Vec<Span>, Option<Vec<Token>>) {
+            match highlighter {
+                Some(ref mut highlighter) => {
+                    let highlighter = &mut **highlighter;
+                    highlighter.update(&self.lines, r + 1);
+                    let state = highlighter.state(r);
+                    let tokens = highlighter.parse_line(&self.lines[r], state.clone());
+                    (state, highlighter.spans(r), tokens)
+                }
+                None => (LineState::Normal, vec![], None)
+            }
+        };
+        let end_of_buffer = Point::new(self.lines.len(), 0);
+        cache.splits.resize(self.lines.len(), None);
+        let mut old = cache.tree.take()
+            .unwrap_or(SyntaxTree::new(NodeKind::Document, None, Point::new(0, 0), end_of_buffer));
+        old.node.end = end_of_buffer;
+        let mut r = cmp::min(from, self.lines.len());
+        while r > 0 && cache.splits[r].is_none() {
+            r -= 1;
+        }
+        let split = old.children.iter().position(|child| child.node.start >= Point::new(r, 0))
+            .unwrap_or(old.children.len());
+        let mut after = old.children.split_off(split);
+        let mut stack = vec![old];
+        while r < self.lines.len() {
+            let (state, spans, tokens) = row_info(r);
+            if stack.len() == 1 && r >= to && cache.splits[r].as_ref() == Some(&state) {
+                // The rest was scanned from the same state before
+                let reused = after.iter().position(|child| child.node.start >= Point::new(r, 0))
+                    .unwrap_or(after.len());
+                let mut rest = after.split_off(reused).into_iter();
+                if let Some(first) = rest.next() {
+                    if add_child(&mut stack[0], first, &self.lines) {
+                        cache.splits[r] = None;
+                    }
+                }
+                stack[0].children.extend(rest);
+                break;
+            }
+            cache.splits[r] = if stack.len() == 1 { Some(state) } else { None };
+            match tokens {
+                Some(tokens) => self.scan_tokens(r, &tokens, &mut stack, &mut cache.splits),
+                None => {
+                    let end = self.lines[r].len();
+                    self.scan_text(r, 0, end, &spans, &mut stack, &mut cache.splits);
+                }
+            }
+            r += 1;
+        }
+        // Anything left open runs to the end of the buffer
+        while stack.len() > 1 {
+            let tree = stack.pop().unwrap();
+            add_child(stack.last_mut().unwrap(), tree, &self.lines);
+        }
+        cache.tree = stack.pop();
+    }
+
+    /// Adds the regions and tokens that a TextMate grammar matched on row
+    /// `r` to `stack`, which holds the nodes open at the start of the row,
+    /// along with the brackets and words between them.
+    fn scan_tokens(&self, r: usize, tokens: &[Token], stack: &mut Vec<SyntaxTree>,
+                   splits: &mut [Option<LineState>]) {
+        let line = &self.lines[r];
+        let end_of_buffer = Point::new(self.lines.len(), 0);
+        let mut c = 0;
+        for token in tokens {
+            // Patterns can match the line break, which isn't part of the row
+            let (start, end) = match *token {
+                Token::Match(start, end, _) | Token::Begin(start, end, _) |
+                Token::End(start, end) => (cmp::min(start, line.len()), cmp::min(end, line.len()))
+            };
+            if !in_literal(stack) {
+                self.scan_text(r, c, start, &[], stack, splits);
+            }
+            c = end;
+            let bracket = if end == start + 1 { bracket_kind(line[start]) } else { None };
+            match *token {
+                // Brackets nest whatever scope the grammar gives them
+                Token::Match(..) if bracket.is_some() && !in_literal(stack) => {
+                    self.scan_text(r, start, end, &[], stack, splits);
+                }
+                Token::Match(_, _, ref scope) => {
+                    let kind = scope_kind(scope).unwrap_or(NodeKind::Token);
+                    let tree = SyntaxTree::new(kind, Some(scope.clone()), Point::new(r, start),
+                                               Point::new(r, end));
+                    if add_child(stack.last_mut().unwrap(), tree, &self.lines) {
+                        splits[r] = None;
+                    }
+                }
+                Token::Begin(_, _, ref scope) => {
+                    let kind = scope.as_ref().and_then(|scope| scope_kind(scope))
+                        .or(bracket.and_then(|(kind, open)| if open { Some(kind) } else { None }))
+                        .unwrap_or(NodeKind::Region);
+                    let mut tree = SyntaxTree::new(kind, scope.clone(), Point::new(r, start),
+                                                   end_of_buffer);
+                    tree.region = true;
+                    stack.push(tree);
+                }
+                Token::End(..) => {
+                    // Brackets left open inside the region end with it
+                    while stack.len() > 1 && !stack.last().unwrap().region {
+                        let mut tree = stack.pop().unwrap();
+                        tree.node.end = Point::new(r, start);
+                        add_child(stack.last_mut().unwrap(), tree, &self.lines);
+                    }
+                    if stack.len() > 1 {
+                        let mut tree = stack.pop().unwrap();
+                        tree.node.end = Point::new(r, end);
+                        add_child(stack.last_mut().unwrap(), tree, &self.lines);
+                    }
+                }
+            }
+        }
+        if !in_literal(stack) {
+            self.scan_text(r, c, line.len(), &[], stack, splits);
+        }
+    }
+
+    /// Adds the brackets, strings, comments and words on row `r` from column
+    /// `from` up to `to` to `stack`, which holds the nodes open there.
+    fn scan_text(&self, r: usize, from: usize, to: usize, spans: &[Span],
+                 stack: &mut Vec<SyntaxTree>, splits: &mut [Option<LineState>]) {
+        let line = &self.lines[r];
+        let end_of_buffer = Point::new(self.lines.len(), 0);
+        let mut c = from;
+        while c < to {
+            let span = spans.iter().find(|span| span.start == c && match span.style {
+                Style::String | Style::Comment => true,
+                _ => false
+            });
+            if let Some(span) = span {
+                let kind = if span.style == Style::String {
+                    NodeKind::String
+                } else {
+                    NodeKind::Comment
+                };
+                let tree = SyntaxTree::new(kind, None, Point::new(r, c), Point::new(r, span.end));
+                if add_child(stack.last_mut().unwrap(), tree, &self.lines) {
+                    // A string or comment runs across the start of the row
+                    splits[r] = None;
+                }
+                c = span.end;
+                continue;
+            }
+            if let Some((kind, open)) = bracket_kind(line[c]) {
+                if open {
+                    stack.push(SyntaxTree::new(kind, None, Point::new(r, c), end_of_buffer));
+                } else if stack.len() > 1 && stack.last().map_or(false, |tree| {
+                    tree.node.kind == kind && !tree.region
+                }) {
+                    let mut tree = stack.pop().unwrap();
+                    tree.node.end = Point::new(r, c + 1);
+                    add_child(stack.last_mut().unwrap(), tree, &self.lines);
+                }
+                c += 1;
+            } else if self.is_word_char(line[c]) {
+                let start = c;
+                while c < to && self.is_word_char(line[c]) {
+                    c += 1;
+                }
+                let tree = SyntaxTree::new(NodeKind::Word, None, Point::new(r, start),
+                                           Point::new(r, c));
+                add_child(stack.last_mut().unwrap(), tree, &self.lines);
+            } else {
+                c += 1;
+            }
+        }
+    }
+
+    /// Returns the smallest node containing `pt`.
+    pub fn node_at(&self, pt: &Point) -> BufResult<Node> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        let tree = self.syntax_tree();
+        let mut tree = &tree;
+        while let Some(child) = tree.children.iter().find(|child| {
+            child.node.start <= *pt && *pt < child.node.end
+        }) {
+            tree = child;
+        }
+        Ok(tree.node.clone())
+    }
+
+    /// Moves from the smallest node containing `start` to `end` in
+    /// `direction`.
+    pub fn navigate_syntax(&self, start: &Point, end: &Point, direction: Direction)
+        -> BufResult<Node> {
+        self.check_range(start, end)?;
+        let tree = self.syntax_tree();
+        let mut path = tree.path_to(start, end);
+        let node = match direction {
+            Direction::Parent => {
+                path.pop().ok_or(BufErr::NoSyntaxNode)?;
+                Some(tree.at(&path).node.clone())
+            }
+            Direction::FirstChild => {
+                tree.at(&path).children.first().map(|child| child.node.clone())
+            }
+            Direction::LastChild => tree.at(&path).children.last().map(|child| child.node.clone()),
+            Direction::NextSibling | Direction::PrevSibling => {
+                let i = path.pop().ok_or(BufErr::NoSyntaxNode)?;
+                let siblings = &tree.at(&path).children;
+                if direction == Direction::NextSibling {
+                    siblings.get(i + 1).map(|sibling| sibling.node.clone())
+                } else if i > 0 {
+                    Some(siblings[i - 1].node.clone())
+                } else {
+                    None
+                }
+            }
+        };
+        node.ok_or(BufErr::NoSyntaxNode)
+    }
+
+    /// Returns the smallest node that contains `start` to `end` and is
+    /// bigger than it.
+    pub fn expand_selection(&self, start: &Point, end: &Point) -> BufResult<Node> {
+        self.check_range(start, end)?;
+        let tree = self.syntax_tree();
+        let mut path = tree.path_to(start, end);
+        while tree.at(&path).is(start, end) {
+            path.pop().ok_or(BufErr::NoSyntaxNode)?;
+        }
+        Ok(tree.at(&path).node.clone())
+    }
+
+    /// Undoes expand_selection(), returning the first of the largest nodes
+    /// inside `start` to `end`.
+    pub fn shrink_selection(&self, start: &Point, end: &Point) -> BufResult<Node> {
+        self.check_range(start, end)?;
+        let tree = self.syntax_tree();
+        let path = tree.path_to(start, end);
+        tree.at(&path).children.iter()
+            .find(|child| *start <= child.node.start && child.node.end <= *end)
+            .map(|child| child.node.clone())
+            .ok_or(BufErr::NoSyntaxNode)
+    }
+
+    /// Returns every node that overlaps `start` to `end`, outermost first,
+    /// leaving out those that aren't of `kind` or in `scope` if given. Nodes
+    /// are in a scope if theirs is it or one of its subscopes.
+    pub fn query_syntax(&self, kind: Option<NodeKind>, scope: Option<&str>, start: &Point,
+                        end: &Point) -> BufResult<Vec<Node>> {
+        self.check_range(start, end)?;
+        let mut nodes = vec![];
+        self.syntax_tree().collect(kind, scope, start, end, &mut nodes);
+        Ok(nodes)
+    }
+
+    fn check_range(&self, start: &Point, end: &Point) -> BufResult<()> {
+        if !self.is_valid_point(start) {
+            return Err(BufErr::InvalidStartPoint);
+        }
+        if !self.is_valid_point(end) || end < start {
+            return Err(BufErr::InvalidEndPoint);
+        }
+        Ok(())
+    }
+}
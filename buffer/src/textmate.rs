@@ -0,0 +1,505 @@
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+
+use highlight::{Span, Style};
+use regex::{self, Captures, Regex, Text};
+
+// Deepest that regions may nest, so that a rule whose begin pattern keeps
+// matching can't grow the state without bound
+const MAX_DEPTH: usize = 100;
+// How many times in a row the tokenizer may match without moving before
+// it skips a character
+const MAX_STALLS: usize = 16;
+// Longest part of a line that's highlighted, since even patterns that don't
+// backtrack can take time in proportion to the square of its length
+const MAX_LINE_LEN: usize = 2000;
+
+/// A grammar in TextMate's format, as also used by Sublime Text and VS
+/// Code, read from its JSON form.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextMateGrammar {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(rename = "scopeName")]
+    pub scope_name: String,
+    #[serde(rename = "fileTypes", default)]
+    pub file_types: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<TextMateRule>,
+    #[serde(default)]
+    pub repository: BTreeMap<String, TextMateRule>
+}
+
+/// A rule that matches a single pattern, a region between a begin and an
+/// end pattern, or that stands for other rules.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TextMateRule {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(rename = "contentName", default)]
+    pub content_name: Option<String>,
+    #[serde(rename = "match", default)]
+    pub match_: Option<String>,
+    #[serde(default)]
+    pub begin: Option<String>,
+    #[serde(default)]
+    pub end: Option<String>,
+    #[serde(default)]
+    pub captures: BTreeMap<String, TextMateCapture>,
+    #[serde(rename = "beginCaptures", default)]
+    pub begin_captures: BTreeMap<String, TextMateCapture>,
+    #[serde(rename = "endCaptures", default)]
+    pub end_captures: BTreeMap<String, TextMateCapture>,
+    #[serde(default)]
+    pub patterns: Vec<TextMateRule>,
+    #[serde(default)]
+    pub include: Option<String>,
+    #[serde(default)]
+    pub repository: BTreeMap<String, TextMateRule>
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextMateCapture {
+    #[serde(default)]
+    pub name: Option<String>
+}
+
+/// A region open at the start of a line: the rule that opened it, and its
+/// end pattern if that refers back to what the begin pattern matched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    rule: usize,
+    end: Option<String>
+}
+
+/// Where one of a grammar's rules matched on a line, in chars, which is
+/// what syntax trees are built from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Token {
+    /// A rule with the scope matched
+    Match(usize, usize, String),
+    /// The begin pattern of a region with the scope matched
+    Begin(usize, usize, Option<String>),
+    /// The end pattern of the innermost region matched
+    End(usize, usize)
+}
+
+enum RuleKind {
+    Match(Regex),
+    Region(Regex, Option<Regex>, String),
+    // Stands for its patterns, as the grammar itself and includes do
+    Group
+}
+
+struct Rule {
+    name: Option<String>,
+    content_name: Option<String>,
+    kind: RuleKind,
+    captures: Vec<(usize, String)>,
+    end_captures: Vec<(usize, String)>,
+    // The rules that can match inside, with groups expanded
+    patterns: Vec<usize>
+}
+
+/// A grammar's rules, compiled. Rules whose patterns don't compile are left
+/// out, as are includes of other grammars.
+pub struct Rules {
+    rules: Vec<Rule>
+}
+
+fn capture_names(captures: &BTreeMap<String, TextMateCapture>) -> Vec<(usize, String)> {
+    captures.iter().filter_map(|(group, capture)| {
+        match (group.parse(), capture.name.as_ref()) {
+            (Ok(group), Some(name)) => Some((group, name.clone())),
+            _ => None
+        }
+    }).collect()
+}
+
+struct Compiler {
+    rules: Vec<Rule>,
+    // Rules are compiled once however many times they're included
+    compiled: HashMap<*const TextMateRule, usize>,
+    // The patterns each rule was written with, before groups are expanded
+    children: Vec<Vec<usize>>
+}
+
+impl Compiler {
+    /// Looks `name` up in the repositories in scope, innermost first.
+    fn find<'a>(&self, name: &str, repositories: &[&'a BTreeMap<String, TextMateRule>])
+        -> Option<(&'a TextMateRule, usize)> {
+        (0..repositories.len()).rev().filter_map(|i| {
+            repositories[i].get(name).map(|rule| (rule, i + 1))
+        }).next()
+    }
+
+    fn add(&mut self, rule: Rule) -> usize {
+        self.rules.push(rule);
+        self.children.push(vec![]);
+        self.rules.len() - 1
+    }
+
+    fn patterns<'a>(&mut self, raw: &'a [TextMateRule],
+                repositories: &[&'a BTreeMap<String, TextMateRule>]) -> Vec<usize> {
+        raw.iter().filter_map(|rule| self.rule(rule, repositories)).collect()
+    }
+
+    fn rule<'a>(&mut self, raw: &'a TextMateRule,
+                repositories: &[&'a BTreeMap<String, TextMateRule>]) -> Option<usize> {
+        if let Some(ref include) = raw.include {
+            return match &include[..] {
+                "$self" | "$base" => Some(0),
+                _ if include.starts_with('#') => {
+                    self.find(&include[1..], repositories)
+                        .and_then(|(rule, depth)| self.rule(rule, &repositories[..depth]))
+                }
+                _ => None
+            };
+        }
+        if let Some(&index) = self.compiled.get(&(raw as *const _)) {
+            return Some(index);
+        }
+        let kind = match (raw.match_.as_ref(), raw.begin.as_ref()) {
+            (Some(pattern), _) => match Regex::new(pattern) {
+                Ok(regex) => RuleKind::Match(regex),
+                Err(_) => return None
+            },
+            (None, Some(begin)) => {
+                // Rules that continue while a pattern matches aren't supported
+                let end = match raw.end {
+                    Some(ref end) => end.clone(),
+                    None => return None
+                };
+                let end_regex = if has_backrefs(&end) {
+                    None
+                } else {
+                    match Regex::new(&end) {
+                        Ok(regex) => Some(regex),
+                        Err(_) => return None
+                    }
+                };
+                match Regex::new(begin) {
+                    Ok(regex) => RuleKind::Region(regex, end_regex, end),
+                    Err(_) => return None
+                }
+            }
+            (None, None) => RuleKind::Group
+        };
+        let captures = if raw.begin.is_some() { &raw.begin_captures } else { &raw.captures };
+        let mut captures = capture_names(captures);
+        let mut end_captures = capture_names(&raw.end_captures);
+        if raw.begin.is_some() {
+            // Captures apply to both patterns unless they have their own
+            if captures.is_empty() {
+                captures = capture_names(&raw.captures);
+            }
+            if end_captures.is_empty() {
+                end_captures = capture_names(&raw.captures);
+            }
+        }
+        let index = self.add(Rule {
+            name: raw.name.clone(),
+            content_name: raw.content_name.clone(),
+            kind: kind,
+            captures: captures,
+            end_captures: end_captures,
+            patterns: vec![]
+        });
+        self.compiled.insert(raw as *const _, index);
+        let mut repositories = repositories.to_vec();
+        repositories.push(&raw.repository);
+        self.children[index] = self.patterns(&raw.patterns, &repositories);
+        Some(index)
+    }
+
+    /// Adds the rules that `patterns` stand for to `expanded`.
+    fn expand(&self, patterns: &[usize], visited: &mut Vec<bool>, expanded: &mut Vec<usize>) {
+        for &index in patterns {
+            match self.rules[index].kind {
+                RuleKind::Group => {
+                    if !visited[index] {
+                        visited[index] = true;
+                        self.expand(&self.children[index], visited, expanded);
+                    }
+                }
+                _ => {
+                    if !expanded.contains(&index) {
+                        expanded.push(index);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn has_backrefs(pattern: &str) -> bool {
+    substitute(pattern, &[], &Text::new(&[])).is_some()
+}
+
+/// Replaces the backreferences in `pattern` with what the groups in
+/// `captures` matched, or returns None if there aren't any.
+fn substitute(pattern: &str, captures: &[Option<(usize, usize)>], text: &Text) -> Option<String> {
+    let mut substituted = String::new();
+    let mut found = false;
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            substituted.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some(digit) if digit.is_ascii_digit() && digit != '0' => {
+                found = true;
+                let group = digit.to_digit(10).unwrap() as usize;
+                if let Some(&Some((start, end))) = captures.get(group) {
+                    substituted.push_str(&regex::escape(text.slice(start, end)));
+                }
+            }
+            Some(other) => {
+                substituted.push('\\');
+                substituted.push(other);
+            }
+            None => substituted.push('\\')
+        }
+    }
+    if found { Some(substituted) } else { None }
+}
+
+/// Returns whether `scope` is `selector` or one of its subscopes, such as
+/// "string.quoted" for "string". Scopes can hold several names separated by
+/// spaces.
+pub fn in_scope(scope: &str, selector: &str) -> bool {
+    scope.split_whitespace().any(|name| {
+        name == selector ||
+            (name.starts_with(selector) && name[selector.len()..].starts_with('.'))
+    })
+}
+
+/// Returns how text in `scope` is styled, if at all.
+fn scope_style(scope: &str) -> Option<Style> {
+    scope.split_whitespace().filter_map(|name| {
+        if in_scope(name, "comment") {
+            Some(Style::Comment)
+        } else if in_scope(name, "string") {
+            Some(Style::String)
+        } else if in_scope(name, "constant.numeric") {
+            Some(Style::Number)
+        } else if in_scope(name, "keyword.operator") {
+            None
+        } else if in_scope(name, "keyword") || in_scope(name, "storage.modifier") ||
+            in_scope(name, "constant.language") {
+            Some(Style::Keyword)
+        } else if in_scope(name, "storage.type") || in_scope(name, "entity.name.type") ||
+            in_scope(name, "support.type") || in_scope(name, "support.class") {
+            Some(Style::Type)
+        } else {
+            None
+        }
+    }).next()
+}
+
+impl Rules {
+    pub fn new(grammar: &TextMateGrammar) -> Rules {
+        let mut compiler = Compiler {
+            rules: vec![],
+            compiled: HashMap::new(),
+            children: vec![]
+        };
+        // The grammar itself is rule 0, which "$self" includes
+        compiler.add(Rule {
+            name: Some(grammar.scope_name.clone()),
+            content_name: None,
+            kind: RuleKind::Group,
+            captures: vec![],
+            end_captures: vec![],
+            patterns: vec![]
+        });
+        compiler.children[0] = compiler.patterns(&grammar.patterns, &[&grammar.repository]);
+        for index in 0..compiler.rules.len() {
+            let mut visited = vec![false; compiler.rules.len()];
+            visited[index] = true;
+            let mut expanded = vec![];
+            compiler.expand(&compiler.children[index], &mut visited, &mut expanded);
+            compiler.rules[index].patterns = expanded;
+        }
+        Rules {
+            rules: compiler.rules
+        }
+    }
+
+    /// Returns the style of text inside the regions in `stack`.
+    fn style_inside(&self, stack: &[Frame]) -> Option<Style> {
+        stack.iter().rev().filter_map(|frame| {
+            let rule = &self.rules[frame.rule];
+            rule.content_name.as_ref().and_then(|scope| scope_style(scope))
+                .or_else(|| rule.name.as_ref().and_then(|scope| scope_style(scope)))
+        }).next()
+    }
+
+    /// Highlights `line`, which starts inside the regions in `stack`, and
+    /// returns its spans along with the regions open at its end.
+    pub fn highlight_line(&self, line: &[char], stack: Vec<Frame>) -> (Vec<Span>, Vec<Frame>) {
+        self.tokenize(line, stack, &mut vec![])
+    }
+
+    /// Returns where rules matched on `line`, which starts inside the
+    /// regions in `stack`, along with the regions open at its end.
+    pub fn parse_line(&self, line: &[char], stack: Vec<Frame>) -> (Vec<Token>, Vec<Frame>) {
+        let mut tokens = vec![];
+        let (_, stack) = self.tokenize(line, stack, &mut tokens);
+        (tokens, stack)
+    }
+
+    fn tokenize(&self, line: &[char], stack: Vec<Frame>, tokens: &mut Vec<Token>)
+        -> (Vec<Span>, Vec<Frame>) {
+        let mut stack = stack;
+        let line = &line[..cmp::min(line.len(), MAX_LINE_LEN)];
+        // Patterns are written expecting lines to end with a newline
+        let mut text = line.to_vec();
+        text.push('\n');
+        let text = Text::new(&text);
+        // Patterns whose searches have failed on this line, by rule and
+        // whether it's the end pattern, which aren't tried again on it
+        let mut failed: Vec<(usize, bool)> = vec![];
+        let mut styles: Vec<Option<Style>> = vec![None; text.len()];
+        let paint = |styles: &mut Vec<Option<Style>>, start: usize, end: usize,
+                     style: Option<Style>| {
+            if style.is_some() {
+                for painted in &mut styles[start..end] {
+                    *painted = style;
+                }
+            }
+        };
+        let paint_captures = |styles: &mut Vec<Option<Style>>, captures: &Captures,
+                              names: &[(usize, String)]| {
+            for &(group, ref name) in names {
+                if let Some(&Some((start, end))) = captures.get(group) {
+                    paint(styles, start, end, scope_style(name));
+                }
+            }
+        };
+        let mut pos = 0;
+        let mut stalls = 0;
+        while pos < text.len() {
+            let inside = self.style_inside(&stack);
+            let substituted = stack.last().and_then(|frame| frame.end.as_ref())
+                .and_then(|end| Regex::new(end).ok());
+            let (patterns, end) = match stack.last() {
+                Some(frame) => {
+                    let rule = &self.rules[frame.rule];
+                    let end = match rule.kind {
+                        RuleKind::Region(_, ref end, _) => substituted.as_ref().or(end.as_ref()),
+                        _ => None
+                    };
+                    (&rule.patterns, end.map(|end| (end, frame.rule)))
+                }
+                None => (&self.rules[0].patterns, None)
+            };
+            let mut search = |regex: &Regex, key: (usize, bool)| {
+                if failed.contains(&key) {
+                    return None;
+                }
+                match regex.find_at(&text, pos) {
+                    Ok(captures) => captures,
+                    Err(_) => {
+                        failed.push(key);
+                        None
+                    }
+                }
+            };
+            // The earliest match wins, and the end pattern wins ties
+            let mut best: Option<(Captures, Option<usize>)> = end
+                .and_then(|(end, rule)| search(end, (rule, true)))
+                .map(|captures| (captures, None));
+            for &index in patterns {
+                let regex = match self.rules[index].kind {
+                    RuleKind::Match(ref regex) | RuleKind::Region(ref regex, _, _) => regex,
+                    RuleKind::Group => continue
+                };
+                let earlier = |captures: &Captures| match best {
+                    Some((ref other, _)) => captures[0].unwrap().0 < other[0].unwrap().0,
+                    None => true
+                };
+                if let Some(captures) = search(regex, (index, false)) {
+                    if earlier(&captures) {
+                        best = Some((captures, Some(index)));
+                    }
+                }
+            }
+            let (captures, matched) = match best {
+                Some(best) => best,
+                None => {
+                    paint(&mut styles, pos, text.len(), inside);
+                    break;
+                }
+            };
+            let (start, end) = captures[0].unwrap();
+            paint(&mut styles, pos, start, inside);
+            match matched {
+                None => {
+                    let frame = stack.pop().unwrap();
+                    let rule = &self.rules[frame.rule];
+                    let style = rule.name.as_ref().and_then(|scope| scope_style(scope))
+                        .or(self.style_inside(&stack));
+                    paint(&mut styles, start, end, style);
+                    paint_captures(&mut styles, &captures, &rule.end_captures);
+                    tokens.push(Token::End(start, end));
+                }
+                Some(index) => {
+                    let rule = &self.rules[index];
+                    let style = rule.name.as_ref().and_then(|scope| scope_style(scope)).or(inside);
+                    paint(&mut styles, start, end, style);
+                    paint_captures(&mut styles, &captures, &rule.captures);
+                    match rule.kind {
+                        RuleKind::Region(_, _, ref end_pattern) if stack.len() < MAX_DEPTH => {
+                            stack.push(Frame {
+                                rule: index,
+                                end: substitute(end_pattern, &captures, &text)
+                            });
+                            let scope = rule.name.as_ref().or(rule.content_name.as_ref());
+                            tokens.push(Token::Begin(start, end, scope.cloned()));
+                        }
+                        _ => if let Some(ref scope) = rule.name {
+                            if end > start {
+                                tokens.push(Token::Match(start, end, scope.clone()));
+                            }
+                        }
+                    }
+                }
+            }
+            if end > pos {
+                pos = end;
+                stalls = 0;
+            } else {
+                stalls += 1;
+                if stalls > MAX_STALLS {
+                    // Matching nothing over and over, so move on
+                    let inside = self.style_inside(&stack);
+                    paint(&mut styles, pos, pos + 1, inside);
+                    pos += 1;
+                    stalls = 0;
+                }
+            }
+        }
+
+        let mut spans: Vec<Span> = vec![];
+        for (i, style) in styles.into_iter().take(line.len()).enumerate() {
+            let style = match style {
+                Some(style) => style,
+                None => continue
+            };
+            if let Some(last) = spans.last_mut() {
+                if last.end == i && last.style == style {
+                    last.end = i + 1;
+                    continue;
+                }
+            }
+            spans.push(Span {
+                start: i,
+                end: i + 1,
+                style: style
+            });
+        }
+        (spans, stack)
+    }
+}
@@ -0,0 +1,240 @@
+use std::cmp;
+
+use {Buffer, BufErr, BufResult, Point};
+use motion::CharClass;
+
+/// A span of text around a point, e.g. the word or parenthesized expression
+/// the point is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextObject {
+    #[serde(rename = "word")]
+    Word,
+    /// A run of non-blank characters
+    #[serde(rename = "bigWord")]
+    BigWord,
+    #[serde(rename = "doubleQuote")]
+    DoubleQuote,
+    #[serde(rename = "singleQuote")]
+    SingleQuote,
+    #[serde(rename = "backQuote")]
+    BackQuote,
+    #[serde(rename = "paren")]
+    Paren,
+    #[serde(rename = "bracket")]
+    Bracket,
+    #[serde(rename = "brace")]
+    Brace,
+    #[serde(rename = "paragraph")]
+    Paragraph,
+    /// An XML/HTML element
+    #[serde(rename = "tag")]
+    Tag
+}
+
+impl Buffer {
+    /// Returns the start and end of `object` around `pt`. Inner objects leave
+    /// out delimiters and surrounding whitespace.
+    pub fn text_object(&self, pt: &Point, object: TextObject, inner: bool)
+        -> BufResult<(Point, Point)> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        let range = match object {
+            TextObject::Word => self.word_object(pt, inner, false),
+            TextObject::BigWord => self.word_object(pt, inner, true),
+            TextObject::DoubleQuote => self.quote_object(pt, '"', inner),
+            TextObject::SingleQuote => self.quote_object(pt, '\'', inner),
+            TextObject::BackQuote => self.quote_object(pt, '`', inner),
+            TextObject::Paren => self.bracket_object(pt, '(', ')', inner),
+            TextObject::Bracket => self.bracket_object(pt, '[', ']', inner),
+            TextObject::Brace => self.bracket_object(pt, '{', '}', inner),
+            TextObject::Paragraph => self.paragraph_object(pt, inner),
+            TextObject::Tag => self.tag_object(pt, inner)
+        };
+        range.ok_or(BufErr::NoTextObject)
+    }
+
+    fn word_object(&self, pt: &Point, inner: bool, big: bool) -> Option<(Point, Point)> {
+        if pt.r >= self.lines.len() || self.lines[pt.r].len() == 0 {
+            return None;
+        }
+        let line = &self.lines[pt.r];
+        let class = |ch: char| {
+            match self.char_class(ch) {
+                CharClass::Punctuation if big => CharClass::Word,
+                class => class
+            }
+        };
+        let run_end = |mut c: usize| {
+            let cls = class(line[c]);
+            while c < line.len() && class(line[c]) == cls {
+                c += 1;
+            }
+            c
+        };
+        let c = cmp::min(pt.c, line.len() - 1);
+        let cls = class(line[c]);
+        let mut start = c;
+        while start > 0 && class(line[start - 1]) == cls {
+            start -= 1;
+        }
+        let mut end = run_end(c);
+        if !inner {
+            if cls == CharClass::Space {
+                // Take the word after the whitespace along with it
+                if end < line.len() {
+                    end = run_end(end);
+                }
+            } else if end < line.len() && class(line[end]) == CharClass::Space {
+                end = run_end(end);
+            } else {
+                while start > 0 && class(line[start - 1]) == CharClass::Space {
+                    start -= 1;
+                }
+            }
+        }
+        Some((Point::new(pt.r, start), Point::new(pt.r, end)))
+    }
+
+    fn quote_object(&self, pt: &Point, quote: char, inner: bool) -> Option<(Point, Point)> {
+        if pt.r >= self.lines.len() {
+            return None;
+        }
+        let line = &self.lines[pt.r];
+        let quotes = (0..line.len())
+            .filter(|&c| line[c] == quote && (c == 0 || line[c - 1] != '\\'))
+            .collect::<Vec<_>>();
+        // Pair up quotes from the start of the line, preferring the pair
+        // around the point and falling back to the next one after it
+        let pair = quotes.chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .find(|pair| pair[0] <= pt.c && pt.c <= pair[1])
+            .or_else(|| quotes.chunks(2).find(|pair| pair.len() == 2 && pair[0] > pt.c));
+        pair.map(|pair| {
+            if inner {
+                (Point::new(pt.r, pair[0] + 1), Point::new(pt.r, pair[1]))
+            } else {
+                (Point::new(pt.r, pair[0]), Point::new(pt.r, pair[1] + 1))
+            }
+        })
+    }
+
+    fn bracket_object(&self, pt: &Point, open: char, close: char, inner: bool)
+        -> Option<(Point, Point)> {
+        // Find the innermost unmatched opening bracket at or before the point
+        let mut p = *pt;
+        let mut depth = 0;
+        let open_pt = loop {
+            match self.char_at(&p) {
+                Some(ch) if ch == open => {
+                    if depth == 0 {
+                        break p;
+                    }
+                    depth -= 1;
+                }
+                Some(ch) if ch == close && p != *pt => depth += 1,
+                _ => {}
+            }
+            if p == Point::new(0, 0) {
+                return None;
+            }
+            p = self.point_before(&p);
+        };
+        let close_pt = match self.match_bracket(&open_pt) {
+            Some(close_pt) => close_pt,
+            None => return None
+        };
+        if inner {
+            Some((Point::new(open_pt.r, open_pt.c + 1), close_pt))
+        } else {
+            Some((open_pt, Point::new(close_pt.r, close_pt.c + 1)))
+        }
+    }
+
+    fn paragraph_object(&self, pt: &Point, inner: bool) -> Option<(Point, Point)> {
+        if self.lines.len() == 0 {
+            return None;
+        }
+        let r = cmp::min(pt.r, self.lines.len() - 1);
+        let blank = self.is_blank_line(r);
+        let mut start = r;
+        while start > 0 && self.is_blank_line(start - 1) == blank {
+            start -= 1;
+        }
+        let mut end = r + 1;
+        while end < self.lines.len() && self.is_blank_line(end) == blank {
+            end += 1;
+        }
+        if !inner && !blank {
+            // Take the blank lines after the paragraph, or before it if there
+            // aren't any
+            if end < self.lines.len() {
+                while end < self.lines.len() && self.is_blank_line(end) {
+                    end += 1;
+                }
+            } else {
+                while start > 0 && self.is_blank_line(start - 1) {
+                    start -= 1;
+                }
+            }
+        }
+        Some((Point::new(start, 0), Point::new(end, 0)))
+    }
+
+    fn tag_object(&self, pt: &Point, inner: bool) -> Option<(Point, Point)> {
+        let end = Point::new(self.lines.len(), 0);
+        let text = self.region_to_str(&Point::new(0, 0), &end).unwrap()
+            .chars()
+            .collect::<Vec<_>>();
+        let offset = self.point_to_offset(pt);
+
+        // Pair up opening and closing tags as (open start, open end, close
+        // start, close end)
+        let mut open_tags: Vec<(String, usize, usize)> = vec![];
+        let mut elements = vec![];
+        let mut i = 0;
+        while i < text.len() {
+            if text[i] != '<' {
+                i += 1;
+                continue;
+            }
+            let closing = i + 1 < text.len() && text[i + 1] == '/';
+            let name_start = if closing { i + 2 } else { i + 1 };
+            let mut name_end = name_start;
+            while name_end < text.len() &&
+                (text[name_end].is_alphanumeric() || "-_:.".contains(text[name_end])) {
+                name_end += 1;
+            }
+            let tag_end = match text[name_end..].iter().position(|&ch| ch == '>') {
+                Some(n) => name_end + n + 1,
+                None => break
+            };
+            if name_end == name_start {
+                i += 1;
+                continue;
+            }
+            let name = text[name_start..name_end].iter().cloned().collect::<String>();
+            if closing {
+                if let Some(n) = open_tags.iter().rposition(|tag| tag.0 == name) {
+                    let (_, open_start, open_end) = open_tags[n].clone();
+                    elements.push((open_start, open_end, i, tag_end));
+                    open_tags.truncate(n);
+                }
+            } else if text[tag_end - 2] != '/' {
+                open_tags.push((name, i, tag_end));
+            }
+            i = tag_end;
+        }
+
+        elements.iter()
+            .filter(|el| el.0 <= offset && offset < el.3)
+            .max_by_key(|el| el.0)
+            .map(|el| {
+                if inner {
+                    (self.offset_to_point(el.1), self.offset_to_point(el.2))
+                } else {
+                    (self.offset_to_point(el.0), self.offset_to_point(el.3))
+                }
+            })
+    }
+}
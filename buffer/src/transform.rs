@@ -0,0 +1,311 @@
+use std::cmp;
+
+use {Buffer, BufErr, BufResult, Line, Point};
+use column::char_col;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Case {
+    #[serde(rename = "upper")]
+    Upper,
+    #[serde(rename = "lower")]
+    Lower,
+    /// The first letter of every word in upper case and the rest in lower
+    #[serde(rename = "title")]
+    Title,
+    #[serde(rename = "snake")]
+    Snake,
+    #[serde(rename = "camel")]
+    Camel
+}
+
+/// What `transpose` swaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransposeUnit {
+    #[serde(rename = "chars")]
+    Chars,
+    #[serde(rename = "words")]
+    Words,
+    #[serde(rename = "lines")]
+    Lines
+}
+
+fn is_identifier_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+/// Splits an identifier into the words it's made of, e.g. `parse`, `HTTP`
+/// and `Request` for `parse_HTTPRequest`.
+fn subwords(ident: &[char]) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    for (i, &ch) in ident.iter().enumerate() {
+        let prev = if i > 0 { Some(ident[i - 1]) } else { None };
+        let next = ident.get(i + 1).cloned();
+        let starts_word = match prev {
+            Some(prev) if ch.is_uppercase() => {
+                prev.is_lowercase() || prev.is_numeric() ||
+                    (prev.is_uppercase() && next.map_or(false, |n| n.is_lowercase()))
+            }
+            _ => false
+        };
+        if ch == '_' || starts_word {
+            if !word.is_empty() {
+                words.push(word);
+            }
+            word = String::new();
+        }
+        if ch != '_' {
+            word.push(ch);
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => {
+            first.to_uppercase().chain(chars.flat_map(|ch| ch.to_lowercase())).collect()
+        }
+        None => String::new()
+    }
+}
+
+/// Converts a run of identifier characters, keeping any leading and
+/// trailing underscores.
+fn convert_identifier(ident: &[char], case: Case) -> String {
+    let lead = ident.iter().take_while(|&&ch| ch == '_').count();
+    if lead == ident.len() {
+        return ident.iter().cloned().collect();
+    }
+    let trail = ident.iter().rev().take_while(|&&ch| ch == '_').count();
+    let middle = &ident[lead..ident.len() - trail];
+    let words = subwords(middle);
+    let body = match case {
+        Case::Snake => words.iter().map(|w| w.to_lowercase()).collect::<Vec<_>>().join("_"),
+        Case::Camel => {
+            words.iter().enumerate()
+                .map(|(i, w)| if i == 0 { w.to_lowercase() } else { capitalize(w) })
+                .collect()
+        }
+        Case::Title => capitalize(&middle.iter().cloned().collect::<String>()),
+        Case::Upper | Case::Lower => unreachable!()
+    };
+    "_".repeat(lead) + &body + &"_".repeat(trail)
+}
+
+/// Returns `text` converted to `case`. Snake and camel case only change
+/// identifiers, leaving everything between them alone.
+pub fn convert_case(text: &str, case: Case) -> String {
+    match case {
+        Case::Upper => return text.to_uppercase(),
+        Case::Lower => return text.to_lowercase(),
+        _ => {}
+    }
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut result = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_identifier_char(chars[i]) {
+            result.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && is_identifier_char(chars[i]) {
+            i += 1;
+        }
+        result += &convert_identifier(&chars[start..i], case);
+    }
+    result
+}
+
+impl Buffer {
+    /// Converts the text from `start` to `end` to `case`.
+    pub fn change_case(&mut self, start: &Point, end: &Point, case: Case)
+        -> BufResult<Vec<Line>> {
+        let text = self.region_to_str(start, end)?;
+        let converted = convert_case(&text, case);
+        if converted == text {
+            return Ok(self.get_lines(start.r, cmp::min(end.r + 1, self.lines.len())));
+        }
+        self.replace_region(start, end, &converted)
+    }
+
+    /// Returns the start and end of the word containing `pt`, which must be
+    /// on a word character.
+    fn word_bounds(&self, pt: &Point) -> (Point, Point) {
+        let line = &self.lines[pt.r];
+        let mut start = pt.c;
+        while start > 0 && self.is_word_char(line[start - 1]) {
+            start -= 1;
+        }
+        let mut end = pt.c;
+        while end < line.len() && self.is_word_char(line[end]) {
+            end += 1;
+        }
+        (Point::new(pt.r, start), Point::new(pt.r, end))
+    }
+
+    fn next_word(&self, pt: &Point) -> Option<(Point, Point)> {
+        let mut p = *pt;
+        loop {
+            match self.char_at(&p) {
+                Some(ch) if self.is_word_char(ch) => return Some(self.word_bounds(&p)),
+                Some(_) => p = self.next_point(&p).unwrap(),
+                None => return None
+            }
+        }
+    }
+
+    fn prev_word(&self, pt: &Point) -> Option<(Point, Point)> {
+        let mut p = *pt;
+        while p != Point::new(0, 0) {
+            p = self.point_before(&p);
+            if self.char_at(&p).map_or(false, |ch| self.is_word_char(ch)) {
+                return Some(self.word_bounds(&p));
+            }
+        }
+        None
+    }
+
+    /// Swaps the characters, words or lines around `pt`. Characters before
+    /// and at `pt` are swapped, or the last two on the line at its end. The
+    /// word at `pt`, or before it if it's between words, is swapped with
+    /// the next one, or the one before at the end of the buffer. The row
+    /// `pt` is on is swapped with the one above.
+    pub fn transpose(&mut self, pt: &Point, unit: TransposeUnit) -> BufResult<Vec<Line>> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        match unit {
+            TransposeUnit::Chars => {
+                let len = self.line_len(pt.r);
+                if pt.c == 0 || len < 2 {
+                    return Err(BufErr::NothingToTranspose);
+                }
+                let second = cmp::min(pt.c, len - 1);
+                let swapped = [self.lines[pt.r][second], self.lines[pt.r][second - 1]];
+                self.replace_region(&Point::new(pt.r, second - 1), &Point::new(pt.r, second + 1),
+                                    &swapped.iter().cloned().collect::<String>())
+            }
+            TransposeUnit::Words => {
+                let words = match self.char_at(pt) {
+                    Some(ch) if self.is_word_char(ch) => {
+                        let word = self.word_bounds(pt);
+                        (Some(word), self.next_word(&word.1))
+                    }
+                    _ => (self.prev_word(pt), self.next_word(pt))
+                };
+                let (first, second) = match words {
+                    (Some(first), Some(second)) => (first, second),
+                    (Some(last), None) => match self.prev_word(&last.0) {
+                        Some(first) => (first, last),
+                        None => return Err(BufErr::NothingToTranspose)
+                    },
+                    _ => return Err(BufErr::NothingToTranspose)
+                };
+                let string = self.region_to_str(&second.0, &second.1)? +
+                    &self.region_to_str(&first.1, &second.0)? +
+                    &self.region_to_str(&first.0, &first.1)?;
+                self.replace_region(&first.0, &second.1, &string)
+            }
+            TransposeUnit::Lines => {
+                if pt.r == 0 || pt.r >= self.lines.len() {
+                    return Err(BufErr::NothingToTranspose);
+                }
+                self.move_lines(pt.r, pt.r, -1)
+            }
+        }
+    }
+
+    /// Comments out rows `first` through `last` with the grammar's line
+    /// comment, or uncomments them if they all are already. Blank rows are
+    /// left alone. Grammars without line comments get a block comment around
+    /// the rows instead.
+    pub fn toggle_line_comment(&mut self, first: usize, last: usize) -> BufResult<Vec<Line>> {
+        if last < first || last >= self.lines.len() {
+            return Err(BufErr::InvalidPoint);
+        }
+        let grammar = match self.grammar() {
+            Some(grammar) => grammar,
+            None => return Err(BufErr::NoCommentSyntax)
+        };
+        let marker = match grammar.line_comment {
+            Some(marker) => marker.chars().collect::<Vec<_>>(),
+            None => {
+                let start = self.first_non_blank(first);
+                let end = Point::new(last, self.lines[last].len());
+                return self.toggle_block_comment(&start, &end);
+            }
+        };
+        let rows = (first..last + 1).filter(|&r| !self.is_blank_line(r)).collect::<Vec<_>>();
+        let commented = rows.iter().all(|&r| {
+            self.lines[r][self.first_non_blank(r).c..].starts_with(&marker)
+        });
+        // Line the markers up with the least indented row
+        let col = rows.iter().map(|&r| self.visual_col_at(&self.first_non_blank(r))).min();
+        self.begin_transaction();
+        let mut result = Ok(());
+        for &r in &rows {
+            result = if commented {
+                let start = self.first_non_blank(r);
+                let mut end = start.c + marker.len();
+                if self.lines[r].get(end) == Some(&' ') {
+                    end += 1;
+                }
+                self.delete_region(&start, &Point::new(r, end)).map(|_| ())
+            } else {
+                let (c, _) = char_col(&self.lines[r], col.unwrap_or(0), self.options.tab_width);
+                let string = marker.iter().cloned().collect::<String>() + " ";
+                self.insert_at_pt(&string, &Point::new(r, c)).map(|_| ())
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        self.end_transaction_with(result)?;
+        Ok(self.get_lines(first, last + 1))
+    }
+
+    /// Wraps the text from `start` to `end` in the grammar's block comment
+    /// delimiters, or removes them if it's already wrapped. Whitespace around
+    /// the text is left outside the comment.
+    pub fn toggle_block_comment(&mut self, start: &Point, end: &Point) -> BufResult<Vec<Line>> {
+        let text = self.region_to_str(start, end)?.chars().collect::<Vec<_>>();
+        let (open, close) = match self.grammar().and_then(|grammar| grammar.block_comment) {
+            Some((open, close)) => {
+                (open.chars().collect::<Vec<_>>(), close.chars().collect::<Vec<_>>())
+            }
+            None => return Err(BufErr::NoCommentSyntax)
+        };
+        let lead = text.iter().take_while(|ch| ch.is_whitespace()).count();
+        let trail = text[lead..].iter().rev().take_while(|ch| ch.is_whitespace()).count();
+        let inner = &text[lead..text.len() - trail];
+        let mut body = if inner.len() >= open.len() + close.len() &&
+            inner.starts_with(&open) && inner.ends_with(&close) {
+            let mut body = &inner[open.len()..inner.len() - close.len()];
+            if body.first() == Some(&' ') {
+                body = &body[1..];
+            }
+            if body.last() == Some(&' ') {
+                body = &body[..body.len() - 1];
+            }
+            body.to_vec()
+        } else {
+            let mut body = open.clone();
+            body.push(' ');
+            body.extend_from_slice(inner);
+            body.push(' ');
+            body.extend(close);
+            body
+        };
+        let mut string = text[..lead].to_vec();
+        string.append(&mut body);
+        string.extend_from_slice(&text[text.len() - trail..]);
+        self.replace_region(start, end, &string.into_iter().collect::<String>())
+    }
+}
@@ -0,0 +1,229 @@
+use std::cmp;
+
+use {Buffer, BufErr, BufResult, Edit, Point};
+use column::{advance, visual_col};
+use observe::{ChangeEvent, Follow};
+
+// Number of widths to keep wrapped lines for, e.g. one per client window
+const MAX_WRAP_WIDTHS: usize = 4;
+
+/// Part of a row shown on one display line: characters `start` up to `end`,
+/// drawn after `indent` columns of blank space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    start: usize,
+    end: usize,
+    indent: usize
+}
+
+/// One line on screen when the buffer is wrapped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayLine {
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+    /// Columns to leave blank before the text, so that continuation lines
+    /// line up with the start of the row's text
+    pub indent: usize,
+    pub text: String
+}
+
+/// Splits `line` into segments no wider than `width` columns, breaking after
+/// whitespace where possible. Whitespace may hang past the edge rather than
+/// start a line of its own.
+fn wrap_line(line: &[char], width: usize, tab_width: usize) -> Vec<Segment> {
+    let leading = line.iter().position(|ch| !ch.is_whitespace()).unwrap_or(line.len());
+    let leading = visual_col(line, leading, tab_width);
+    let indent = if leading * 2 < width { leading } else { 0 };
+    let mut segments = vec![];
+    let mut start = 0;
+    loop {
+        let seg_indent = if segments.len() == 0 { 0 } else { indent };
+        let mut col = seg_indent;
+        let mut i = start;
+        let mut line_break = None;
+        while i < line.len() {
+            let next = advance(col, line[i], tab_width);
+            if next > width && i > start && !line[i].is_whitespace() {
+                break;
+            }
+            if i > start && line[i - 1].is_whitespace() && !line[i].is_whitespace() {
+                line_break = Some(i);
+            }
+            col = next;
+            i += 1;
+        }
+        if i >= line.len() {
+            segments.push(Segment {
+                start: start,
+                end: line.len(),
+                indent: seg_indent
+            });
+            return segments;
+        }
+        let end = if line[i - 1].is_whitespace() { i } else { line_break.unwrap_or(i) };
+        segments.push(Segment {
+            start: start,
+            end: end,
+            indent: seg_indent
+        });
+        start = end;
+    }
+}
+
+/// Wrapped rows for one width, recomputed only for rows that have changed.
+pub struct WrapCache {
+    width: usize,
+    tab_width: usize,
+    rows: Vec<Option<Vec<Segment>>>
+}
+
+impl WrapCache {
+    fn new(width: usize, tab_width: usize) -> WrapCache {
+        WrapCache {
+            width: width,
+            tab_width: tab_width,
+            rows: vec![]
+        }
+    }
+
+    /// Throws away the rows `edit` changes, which may not have been applied
+    /// to the buffer yet.
+    pub fn edit(&mut self, edit: &Edit) {
+        let start = edit.start.r;
+        if start >= self.rows.len() {
+            return;
+        }
+        let removed = cmp::min(edit.old_end().r, self.rows.len() - 1) - start;
+        let added = edit.new_end().r - start;
+        self.rows.drain(start + 1..start + 1 + removed);
+        for _ in 0..added {
+            self.rows.insert(start + 1, None);
+        }
+        self.rows[start] = None;
+    }
+
+    fn update(&mut self, lines: &[Vec<char>]) {
+        self.rows.resize(lines.len(), None);
+        for (r, line) in lines.iter().enumerate() {
+            if self.rows[r].is_none() {
+                self.rows[r] = Some(wrap_line(line, self.width, self.tab_width));
+            }
+        }
+    }
+}
+
+impl Follow for Vec<WrapCache> {
+    fn follow(&mut self, event: &ChangeEvent) {
+        let edit = event.edit();
+        for cache in self.iter_mut() {
+            cache.edit(&edit);
+        }
+    }
+}
+
+impl Buffer {
+    /// Returns the segments of every row wrapped to `width` columns.
+    fn wrapped(&self, width: usize) -> BufResult<Vec<Vec<Segment>>> {
+        if width == 0 {
+            return Err(BufErr::InvalidWidth);
+        }
+        let tab_width = self.options.tab_width;
+        let mut caches = self.wrap_caches.borrow_mut();
+        let caches = &mut *caches;
+        let mut cache = match caches.iter().position(|cache| cache.width == width) {
+            Some(i) => caches.remove(i),
+            None => WrapCache::new(width, tab_width)
+        };
+        if cache.tab_width != tab_width {
+            cache = WrapCache::new(width, tab_width);
+        }
+        cache.update(&self.lines);
+        let rows = cache.rows.iter().map(|row| row.clone().unwrap()).collect();
+        // Keep the most recently used widths at the end
+        caches.push(cache);
+        if caches.len() > MAX_WRAP_WIDTHS {
+            caches.remove(0);
+        }
+        Ok(rows)
+    }
+
+    /// Returns the number of display lines when wrapped to `width` columns.
+    pub fn display_row_count(&self, width: usize) -> BufResult<usize> {
+        Ok(self.wrapped(width)?.iter().map(|segments| segments.len()).sum())
+    }
+
+    /// Returns display lines `start` up to but not including `end` when the
+    /// buffer is wrapped to `width` columns.
+    pub fn display_lines(&self, width: usize, start: usize, end: usize)
+        -> BufResult<Vec<DisplayLine>> {
+        let wrapped = self.wrapped(width)?;
+        let mut display_lines = vec![];
+        let mut display_row = 0;
+        for (r, segments) in wrapped.iter().enumerate() {
+            if display_row >= end {
+                break;
+            }
+            for segment in segments {
+                if display_row >= start && display_row < end {
+                    display_lines.push(DisplayLine {
+                        row: r,
+                        start: segment.start,
+                        end: segment.end,
+                        indent: segment.indent,
+                        text: self.lines[r][segment.start..segment.end].iter().cloned().collect()
+                    });
+                }
+                display_row += 1;
+            }
+        }
+        Ok(display_lines)
+    }
+
+    /// Returns the display row and column `pt` is shown at when the buffer
+    /// is wrapped to `width` columns.
+    pub fn point_to_display(&self, pt: &Point, width: usize) -> BufResult<(usize, usize)> {
+        if !self.is_valid_point(pt) {
+            return Err(BufErr::InvalidPoint);
+        }
+        let wrapped = self.wrapped(width)?;
+        let display_row = wrapped[..pt.r].iter().map(|segments| segments.len()).sum::<usize>();
+        if pt.r == self.lines.len() {
+            return Ok((display_row, 0));
+        }
+        let segments = &wrapped[pt.r];
+        let i = segments.iter().position(|segment| pt.c < segment.end)
+            .unwrap_or(segments.len() - 1);
+        let segment = segments[i];
+        let col = self.lines[pt.r][segment.start..pt.c].iter()
+            .fold(segment.indent, |col, &ch| advance(col, ch, self.options.tab_width));
+        Ok((display_row + i, col))
+    }
+
+    /// Returns the point shown at `col` on display row `display_row` when
+    /// the buffer is wrapped to `width` columns, staying on that display row
+    /// if it's too short.
+    pub fn display_to_point(&self, display_row: usize, col: usize, width: usize)
+        -> BufResult<Point> {
+        let wrapped = self.wrapped(width)?;
+        let mut first = 0;
+        for (r, segments) in wrapped.iter().enumerate() {
+            if display_row >= first + segments.len() {
+                first += segments.len();
+                continue;
+            }
+            let i = display_row - first;
+            let segment = segments[i];
+            let mut vcol = segment.indent;
+            for c in segment.start..segment.end {
+                vcol = advance(vcol, self.lines[r][c], self.options.tab_width);
+                if vcol > col {
+                    return Ok(Point::new(r, c));
+                }
+            }
+            let c = if i + 1 < segments.len() { segment.end - 1 } else { segment.end };
+            return Ok(Point::new(r, c));
+        }
+        Ok(self.end_point())
+    }
+}
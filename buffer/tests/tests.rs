@@ -1,5 +1,11 @@
 extern crate buffer;
-use buffer::{Buffer, Point, BufErr, IntoLine};
+use buffer::{Buffer, Point, BufErr, Case, Change, ChangeKind, char_width, Completions, convert_case,
+             ChangeEvent, Direction, DisplayLine, FoldKind, FoldingRange, Grammar, IntoLine,
+             LazyBuffer, LineProperty, LineSource, Motion, Node, NodeKind, Operator, Selection,
+             SelectionSet, Snapshot, SortKind, Span, Style, TabStop, Target, TextMateCapture,
+             TextMateGrammar, TextMateRule, TextObject, TextProperty, TransposeUnit};
+use std::cell::Cell;
+use std::collections::BTreeMap;
 
 #[test]
 fn test_insert_empty_buffer1() {
@@ -135,6 +141,13 @@ fn test_region_to_str1() {
         BufErr::InvalidEndPoint);
 }
 
+#[test]
+fn test_region_to_str2() {
+    let buf = Buffer::with_contents("abc\ndef\nghi\n");
+    assert_eq!(buf.region_to_str(&Point::new(0, 1), &Point::new(2, 2)).unwrap(), "bc\ndef\ngh");
+    assert_eq!(buf.region_to_str(&Point::new(1, 3), &Point::new(2, 0)).unwrap(), "\n");
+}
+
 #[test]
 fn test_delete_region1() {
     let mut buf = Buffer::with_contents("abc\ndef\nghi\n");
@@ -207,3 +220,1576 @@ fn test_insert_edge_case1() {
     assert_eq!(buf.insert_at_pt("a", &Point::new(0, 1)).unwrap_err(), BufErr::InvalidPoint);
     assert_eq!(buf.insert_at_pt("a", &Point::new(1, 0)).unwrap_err(), BufErr::InvalidPoint);
 }
+
+#[test]
+fn test_insert_at_selections1() {
+    let mut buf = Buffer::with_contents("abcd\nefgh\n");
+    let mut sels = SelectionSet::new(vec![
+        Selection::cursor(Point::new(1, 2)),
+        Selection::cursor(Point::new(0, 1)),
+        Selection::cursor(Point::new(0, 3))
+    ]);
+    assert_eq!(buf.insert_at_selections("X", &mut sels),
+        Ok(vec![
+            "aXbcXd".into_line(0),
+            "efXgh".into_line(1)
+        ])
+    );
+    assert_eq!(sels.selections, vec![
+        Selection::cursor(Point::new(0, 2)),
+        Selection::cursor(Point::new(0, 5)),
+        Selection::cursor(Point::new(1, 3))
+    ]);
+    assert_eq!(buf.to_str(), "aXbcXd\nefXgh\n");
+
+    assert_eq!(buf.insert_at_selections("\n", &mut sels).unwrap().len(), 5);
+    assert_eq!(buf.to_str(), "aX\nbcX\nd\nefX\ngh\n");
+    assert_eq!(sels.selections, vec![
+        Selection::cursor(Point::new(1, 0)),
+        Selection::cursor(Point::new(2, 0)),
+        Selection::cursor(Point::new(4, 0))
+    ]);
+}
+
+#[test]
+fn test_insert_at_selections2() {
+    let mut buf = Buffer::with_contents("abc\ndef\n");
+    // Replace "bc\nd" and "f", and merge the overlapping selection
+    let mut sels = SelectionSet::new(vec![
+        Selection::new(Point::new(0, 1), Point::new(1, 1)),
+        Selection::new(Point::new(1, 0), Point::new(0, 2)),
+        Selection::new(Point::new(1, 3), Point::new(1, 2))
+    ]);
+    assert_eq!(sels.selections.len(), 2);
+    assert_eq!(buf.insert_at_selections("12", &mut sels),
+        Ok(vec![
+            "a12e12".into_line(0)
+        ])
+    );
+    assert_eq!(sels.selections, vec![
+        Selection::cursor(Point::new(0, 3)),
+        Selection::cursor(Point::new(0, 6))
+    ]);
+    assert_eq!(buf.insert_at_selections("!", &mut SelectionSet::new(vec![
+        Selection::cursor(Point::new(0, 7))
+    ])).unwrap_err(), BufErr::InvalidStartPoint);
+    assert_eq!(buf.to_str(), "a12e12\n");
+}
+
+#[test]
+fn test_delete_selections1() {
+    let mut buf = Buffer::with_contents("abc\ndef\n");
+    let mut sels = SelectionSet::new(vec![
+        Selection::cursor(Point::new(0, 0)),
+        Selection::cursor(Point::new(1, 0)),
+        Selection::new(Point::new(1, 1), Point::new(1, 3))
+    ]);
+    assert_eq!(buf.delete_selections(&mut sels),
+        Ok(vec![
+            "abcd".into_line(0)
+        ])
+    );
+    assert_eq!(sels.selections, vec![
+        Selection::cursor(Point::new(0, 0)),
+        Selection::cursor(Point::new(0, 3)),
+        Selection::cursor(Point::new(0, 4))
+    ]);
+    assert_eq!(buf.to_str(), "abcd\n");
+}
+
+#[test]
+fn test_undo1() {
+    let mut buf = Buffer::with_contents("abc\ndef\n");
+    assert_eq!(buf.undo().unwrap_err(), BufErr::NothingToUndo);
+    let mut sels = SelectionSet::new(vec![
+        Selection::cursor(Point::new(0, 1)),
+        Selection::new(Point::new(0, 3), Point::new(1, 1))
+    ]);
+    assert!(buf.insert_at_selections("xy\n", &mut sels).is_ok());
+    assert_eq!(buf.to_str(), "axy\nbcxy\nef\n");
+    assert!(buf.insert_at_pt("!", &Point::new(0, 0)).is_ok());
+    assert_eq!(buf.undo(), Ok(vec!["axy".into_line(0)]));
+    assert_eq!(buf.undo(), Ok(vec!["abc".into_line(0), "def".into_line(1)]));
+    assert_eq!(buf.to_str(), "abc\ndef\n");
+    assert_eq!(buf.undo().unwrap_err(), BufErr::NothingToUndo);
+}
+
+#[test]
+fn test_undo_end_of_buffer1() {
+    let mut buf = Buffer::with_contents("abc\ndef\n");
+    assert!(buf.insert_at_pt("gh", &Point::new(2, 0)).is_ok());
+    assert!(buf.delete_region(&Point::new(0, 0), &Point::new(3, 0)).is_ok());
+    assert_eq!(buf.to_str(), "\n");
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "abc\ndef\ngh\n");
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "abc\ndef\n");
+    assert_eq!(buf.lines.len(), 2);
+}
+
+#[test]
+fn test_undo_end_of_buffer2() {
+    let mut buf = Buffer::with_contents("abc\ndef\n");
+    buf.snapshot();
+    let changes = buf.observe();
+    buf.add_property(&Point::new(1, 0), &Point::new(1, 3), props(&[("link", "x")])).unwrap();
+    buf.take_changes();
+    buf.insert_at_pt("gh\nij", &Point::new(2, 0)).unwrap();
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "abc\ndef\n");
+    // Removing the row left behind is a change like any other
+    assert_eq!(buf.take_changes().len(), 3);
+    let events = changes.try_iter().collect::<Vec<_>>();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[2].version, buf.version());
+    assert_eq!(buf.snapshot().len(), 2);
+    assert_eq!(buf.snapshot().to_str(), buf.to_str());
+    assert_eq!(buf.properties()[0].end, Point::new(1, 3));
+}
+
+#[test]
+fn test_undo_in_transaction1() {
+    let mut buf = Buffer::with_contents("abc\n");
+    assert!(buf.insert_at_pt("1", &Point::new(0, 0)).is_ok());
+    buf.begin_transaction();
+    assert!(buf.insert_at_pt("2", &Point::new(0, 0)).is_ok());
+    assert!(buf.replace_region(&Point::new(0, 3), &Point::new(0, 4), "xy").is_ok());
+    assert_eq!(buf.to_str(), "21axyc\n");
+    // Only the last change made inside the transaction is taken back
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "21abc\n");
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "1abc\n");
+    // and then the steps before it
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "abc\n");
+    assert!(buf.insert_at_pt("3", &Point::new(0, 0)).is_ok());
+    buf.end_transaction();
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "abc\n");
+    assert_eq!(buf.undo().unwrap_err(), BufErr::NothingToUndo);
+}
+
+#[test]
+fn test_take_changes1() {
+    let mut buf = Buffer::with_contents("abc\n");
+    let mut other = SelectionSet::new(vec![Selection::new(Point::new(0, 1), Point::new(0, 3))]);
+    assert!(buf.insert_at_pt("12\n", &Point::new(0, 0)).is_ok());
+    assert!(buf.delete_region(&Point::new(1, 0), &Point::new(1, 2)).is_ok());
+    other.shift(&buf.take_changes());
+    assert_eq!(other.selections, vec![Selection::new(Point::new(1, 0), Point::new(1, 1))]);
+    assert_eq!(buf.take_changes(), vec![]);
+}
+
+#[test]
+fn test_block_to_strs1() {
+    let buf = Buffer::with_contents("abcdef\nab\n\tabc\nabcdefgh\n");
+    assert_eq!(buf.block_to_strs(&Point::new(0, 1), &Point::new(3, 4)).unwrap(), vec![
+        "bcd".to_string(),
+        "b".to_string(),
+        "".to_string(),
+        "bcd".to_string()
+    ]);
+    assert_eq!(buf.block_to_strs(&Point::new(3, 8), &Point::new(2, 3)).unwrap(), vec![
+        "ab".to_string(),
+        "".to_string()
+    ]);
+    assert_eq!(buf.block_to_strs(&Point::new(2, 5), &Point::new(3, 7)).unwrap_err(),
+        BufErr::InvalidStartPoint);
+}
+
+#[test]
+fn test_delete_block1() {
+    let mut buf = Buffer::with_contents("abcdef\nab\n\tabc\nabcdefghij\n");
+    assert_eq!(buf.delete_block(&Point::new(3, 10), &Point::new(0, 1)),
+        Ok(vec![
+            "a".into_line(0),
+            "a".into_line(1),
+            "\tc".into_line(2),
+            "a".into_line(3)
+        ])
+    );
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "abcdef\nab\n\tabc\nabcdefghij\n");
+}
+
+#[test]
+fn test_insert_block1() {
+    let mut buf = Buffer::with_contents("abcd\nab\n\tabc\n");
+    let strs = vec!["12".to_string(), "34".to_string(), "56".to_string(), "78".to_string()];
+    assert_eq!(buf.insert_block(&strs, &Point::new(0, 3)),
+        Ok(vec![
+            "abc12d".into_line(0),
+            "ab 34".into_line(1),
+            "\t56abc".into_line(2),
+            "   78".into_line(3)
+        ])
+    );
+    assert_eq!(buf.lines.len(), 4);
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "abcd\nab\n\tabc\n");
+    assert_eq!(buf.insert_block(&vec!["a\nb".to_string()], &Point::new(0, 0)).unwrap_err(),
+        BufErr::InvalidBlock);
+}
+
+#[test]
+fn test_insert_at_block1() {
+    let mut buf = Buffer::with_contents("abcd\na\nabc\n");
+    assert_eq!(buf.insert_at_block("|", &Point::new(0, 2), &Point::new(2, 3)),
+        Ok(vec![
+            "ab|cd".into_line(0),
+            "a |".into_line(1),
+            "ab|c".into_line(2)
+        ])
+    );
+}
+
+#[test]
+fn test_move_word1() {
+    let buf = Buffer::with_contents("foo_bar(baz, qux);\n  quux\n");
+    let pt = Point::new(0, 0);
+    assert_eq!(buf.move_point(&pt, Motion::WordForward, 1), Ok(Point::new(0, 7)));
+    assert_eq!(buf.move_point(&pt, Motion::WordForward, 2), Ok(Point::new(0, 8)));
+    assert_eq!(buf.move_point(&pt, Motion::WordForward, 6), Ok(Point::new(1, 2)));
+    assert_eq!(buf.move_point(&pt, Motion::WordForward, 10), Ok(Point::new(1, 6)));
+    assert_eq!(buf.move_point(&pt, Motion::WordEnd, 1), Ok(Point::new(0, 6)));
+    assert_eq!(buf.move_point(&Point::new(1, 2), Motion::WordBackward, 1), Ok(Point::new(0, 16)));
+    assert_eq!(buf.move_point(&Point::new(0, 13), Motion::WordBackward, 2), Ok(Point::new(0, 8)));
+    assert_eq!(buf.move_point(&Point::new(2, 1), Motion::WordBackward, 1).unwrap_err(),
+        BufErr::InvalidPoint);
+
+    let mut buf = Buffer::with_contents("foo_bar-baz\n");
+    buf.options.word_chars = "_-".to_string();
+    assert_eq!(buf.move_point(&pt, Motion::WordForward, 1), Ok(Point::new(0, 11)));
+}
+
+#[test]
+fn test_move_subword1() {
+    let buf = Buffer::with_contents("fooBar_baz HTTPServer2x\n");
+    let pt = Point::new(0, 0);
+    assert_eq!(buf.move_point(&pt, Motion::SubwordForward, 1), Ok(Point::new(0, 3)));
+    assert_eq!(buf.move_point(&pt, Motion::SubwordForward, 2), Ok(Point::new(0, 7)));
+    assert_eq!(buf.move_point(&pt, Motion::SubwordForward, 3), Ok(Point::new(0, 11)));
+    assert_eq!(buf.move_point(&pt, Motion::SubwordForward, 4), Ok(Point::new(0, 15)));
+    assert_eq!(buf.move_point(&pt, Motion::SubwordForward, 5), Ok(Point::new(0, 21)));
+    assert_eq!(buf.move_point(&Point::new(0, 11), Motion::SubwordBackward, 1),
+        Ok(Point::new(0, 7)));
+    assert_eq!(buf.move_point(&Point::new(0, 7), Motion::SubwordBackward, 2),
+        Ok(Point::new(0, 0)));
+}
+
+#[test]
+fn test_move_line1() {
+    let buf = Buffer::with_contents("abc\n  \tdef \n");
+    let pt = Point::new(1, 5);
+    assert_eq!(buf.move_point(&pt, Motion::LineStart, 1), Ok(Point::new(1, 0)));
+    assert_eq!(buf.move_point(&pt, Motion::LineEnd, 1), Ok(Point::new(1, 7)));
+    assert_eq!(buf.move_point(&pt, Motion::FirstNonBlank, 1), Ok(Point::new(1, 3)));
+    assert_eq!(buf.move_point(&pt, Motion::BufferStart, 1), Ok(Point::new(0, 0)));
+    assert_eq!(buf.move_point(&pt, Motion::BufferEnd, 1), Ok(Point::new(1, 7)));
+    assert_eq!(buf.move_point(&Point::new(0, 3), Motion::CharForward, 2), Ok(Point::new(1, 1)));
+    assert_eq!(buf.move_point(&Point::new(1, 0), Motion::CharBackward, 1), Ok(Point::new(0, 3)));
+    // Huge counts stop where the motion does
+    assert_eq!(buf.move_point(&pt, Motion::WordForward, usize::max_value()),
+               Ok(Point::new(1, 7)));
+    assert_eq!(buf.move_point(&pt, Motion::CharBackward, usize::max_value()),
+               Ok(Point::new(0, 0)));
+}
+
+#[test]
+fn test_move_paragraph_sentence1() {
+    let buf = Buffer::with_contents("One. Two (three).\nFour\n\nFive? \"Six.\"  Seven\n\n\nEight\n");
+    let pt = Point::new(0, 0);
+    assert_eq!(buf.move_point(&pt, Motion::ParagraphForward, 1), Ok(Point::new(2, 0)));
+    assert_eq!(buf.move_point(&pt, Motion::ParagraphForward, 2), Ok(Point::new(4, 0)));
+    assert_eq!(buf.move_point(&pt, Motion::ParagraphForward, 3), Ok(Point::new(6, 5)));
+    assert_eq!(buf.move_point(&Point::new(6, 2), Motion::ParagraphBackward, 1),
+        Ok(Point::new(5, 0)));
+    assert_eq!(buf.move_point(&Point::new(3, 2), Motion::ParagraphBackward, 2),
+        Ok(Point::new(0, 0)));
+    assert_eq!(buf.move_point(&pt, Motion::SentenceForward, 1), Ok(Point::new(0, 5)));
+    assert_eq!(buf.move_point(&pt, Motion::SentenceForward, 2), Ok(Point::new(1, 0)));
+    assert_eq!(buf.move_point(&pt, Motion::SentenceForward, 3), Ok(Point::new(3, 0)));
+    assert_eq!(buf.move_point(&pt, Motion::SentenceForward, 4), Ok(Point::new(3, 6)));
+    assert_eq!(buf.move_point(&pt, Motion::SentenceForward, 5), Ok(Point::new(3, 14)));
+    assert_eq!(buf.move_point(&pt, Motion::SentenceForward, 6), Ok(Point::new(6, 0)));
+    assert_eq!(buf.move_point(&Point::new(3, 16), Motion::SentenceBackward, 1),
+        Ok(Point::new(3, 14)));
+    assert_eq!(buf.move_point(&Point::new(3, 14), Motion::SentenceBackward, 2),
+        Ok(Point::new(3, 0)));
+}
+
+#[test]
+fn test_match_bracket1() {
+    let buf = Buffer::with_contents("f(a[1], {\n  (b)\n})\n");
+    assert_eq!(buf.move_point(&Point::new(0, 1), Motion::MatchBracket, 1), Ok(Point::new(2, 1)));
+    assert_eq!(buf.move_point(&Point::new(2, 0), Motion::MatchBracket, 1), Ok(Point::new(0, 8)));
+    assert_eq!(buf.move_point(&Point::new(0, 3), Motion::MatchBracket, 1), Ok(Point::new(0, 5)));
+    assert_eq!(buf.move_point(&Point::new(1, 2), Motion::MatchBracket, 1), Ok(Point::new(1, 4)));
+    assert_eq!(buf.move_point(&Point::new(0, 0), Motion::MatchBracket, 1), Ok(Point::new(0, 0)));
+    assert_eq!(buf.move_point(&Point::new(0, 1), Motion::MatchBracket, usize::max_value()),
+               Ok(Point::new(2, 1)));
+    assert_eq!(buf.move_point(&Point::new(0, 1), Motion::MatchBracket, 1000000000),
+               Ok(Point::new(0, 1)));
+}
+
+#[test]
+fn test_match_bracket2() {
+    let mut buf = Buffer::with_contents("f(\")\", 'a') // (x\n");
+    assert_eq!(buf.match_bracket(&Point::new(0, 2)), Some(Point::new(0, 4)));
+    assert_eq!(buf.match_bracket(&Point::new(0, 4)), Some(Point::new(0, 2)));
+    // Without a grammar brackets in strings still count
+    assert_eq!(buf.match_bracket(&Point::new(0, 1)), Some(Point::new(0, 3)));
+    buf.set_grammar(Grammar::builtin("rust"));
+    assert_eq!(buf.match_bracket(&Point::new(0, 1)), Some(Point::new(0, 10)));
+    assert_eq!(buf.match_bracket(&Point::new(0, 10)), Some(Point::new(0, 1)));
+    assert_eq!(buf.match_bracket(&Point::new(0, 4)), Some(Point::new(0, 2)));
+    assert_eq!(buf.match_bracket(&Point::new(0, 3)), None);
+    assert_eq!(buf.match_bracket(&Point::new(0, 15)), None);
+}
+
+#[test]
+fn test_auto_pair1() {
+    let mut buf = Buffer::with_contents("foo\nbar\n");
+    buf.options.auto_pair = true;
+    let mut sels = SelectionSet::new(vec![Selection::cursor(Point::new(0, 3)),
+                                          Selection::cursor(Point::new(1, 0))]);
+    buf.insert_at_selections("(", &mut sels).unwrap();
+    assert_eq!(buf.to_str(), "foo()\n(bar\n");
+    assert_eq!(sels.selections, vec![Selection::cursor(Point::new(0, 4)),
+                                     Selection::cursor(Point::new(1, 1))]);
+    buf.insert_at_selections(")", &mut sels).unwrap();
+    assert_eq!(buf.to_str(), "foo()\n()bar\n");
+    assert_eq!(sels.selections, vec![Selection::cursor(Point::new(0, 5)),
+                                     Selection::cursor(Point::new(1, 2))]);
+    buf.delete_selections(&mut sels).unwrap();
+    assert_eq!(buf.to_str(), "foo(\n(bar\n");
+    let mut sels = SelectionSet::new(vec![Selection::cursor(Point::new(0, 4))]);
+    buf.insert_at_selections("\"", &mut sels).unwrap();
+    assert_eq!(buf.to_str(), "foo(\"\"\n(bar\n");
+    buf.delete_selections(&mut sels).unwrap();
+    assert_eq!(buf.to_str(), "foo(\n(bar\n");
+    buf.insert_typed("'", &Point::new(1, 4)).unwrap();
+    assert_eq!(buf.to_str(), "foo(\n(bar'\n");
+    buf.insert_typed("[", &Point::new(0, 0)).unwrap();
+    assert_eq!(buf.to_str(), "[foo(\n(bar'\n");
+    buf.options.auto_pair = false;
+    buf.insert_typed("{", &Point::new(0, 5)).unwrap();
+    assert_eq!(buf.to_str(), "[foo({\n(bar'\n");
+}
+
+#[test]
+fn test_text_object_word1() {
+    let buf = Buffer::with_contents("foo bar.baz  qux\n");
+    let word = TextObject::Word;
+    assert_eq!(buf.text_object(&Point::new(0, 5), word, true),
+        Ok((Point::new(0, 4), Point::new(0, 7))));
+    assert_eq!(buf.text_object(&Point::new(0, 5), word, false),
+        Ok((Point::new(0, 3), Point::new(0, 7))));
+    assert_eq!(buf.text_object(&Point::new(0, 1), word, false),
+        Ok((Point::new(0, 0), Point::new(0, 4))));
+    assert_eq!(buf.text_object(&Point::new(0, 14), word, false),
+        Ok((Point::new(0, 11), Point::new(0, 16))));
+    assert_eq!(buf.text_object(&Point::new(0, 5), TextObject::BigWord, true),
+        Ok((Point::new(0, 4), Point::new(0, 11))));
+    assert_eq!(buf.text_object(&Point::new(1, 0), word, true).unwrap_err(),
+        BufErr::NoTextObject);
+}
+
+#[test]
+fn test_text_object_delimited1() {
+    let buf = Buffer::with_contents("f(a, \"b\\\"c\", [d])\n{\n  x { y }\n}\n");
+    assert_eq!(buf.text_object(&Point::new(0, 7), TextObject::DoubleQuote, true),
+        Ok((Point::new(0, 6), Point::new(0, 10))));
+    assert_eq!(buf.text_object(&Point::new(0, 1), TextObject::DoubleQuote, false),
+        Ok((Point::new(0, 5), Point::new(0, 11))));
+    assert_eq!(buf.text_object(&Point::new(0, 14), TextObject::Paren, true),
+        Ok((Point::new(0, 2), Point::new(0, 16))));
+    assert_eq!(buf.text_object(&Point::new(0, 16), TextObject::Paren, false),
+        Ok((Point::new(0, 1), Point::new(0, 17))));
+    assert_eq!(buf.text_object(&Point::new(0, 14), TextObject::Bracket, false),
+        Ok((Point::new(0, 13), Point::new(0, 16))));
+    assert_eq!(buf.text_object(&Point::new(2, 3), TextObject::Brace, true),
+        Ok((Point::new(1, 1), Point::new(3, 0))));
+    assert_eq!(buf.text_object(&Point::new(2, 7), TextObject::Brace, false),
+        Ok((Point::new(2, 4), Point::new(2, 9))));
+    assert_eq!(buf.text_object(&Point::new(0, 3), TextObject::Brace, false).unwrap_err(),
+        BufErr::NoTextObject);
+}
+
+#[test]
+fn test_text_object_paragraph_tag1() {
+    let buf = Buffer::with_contents("a\nb\n\n\nc\n");
+    assert_eq!(buf.text_object(&Point::new(1, 0), TextObject::Paragraph, true),
+        Ok((Point::new(0, 0), Point::new(2, 0))));
+    assert_eq!(buf.text_object(&Point::new(1, 0), TextObject::Paragraph, false),
+        Ok((Point::new(0, 0), Point::new(4, 0))));
+    assert_eq!(buf.text_object(&Point::new(4, 0), TextObject::Paragraph, false),
+        Ok((Point::new(2, 0), Point::new(5, 0))));
+
+    let buf = Buffer::with_contents("<div a=\"1\"><p>x<br/>y</p>\n</div>\n");
+    assert_eq!(buf.text_object(&Point::new(0, 15), TextObject::Tag, true),
+        Ok((Point::new(0, 14), Point::new(0, 21))));
+    assert_eq!(buf.text_object(&Point::new(0, 15), TextObject::Tag, false),
+        Ok((Point::new(0, 11), Point::new(0, 25))));
+    assert_eq!(buf.text_object(&Point::new(0, 3), TextObject::Tag, true),
+        Ok((Point::new(0, 11), Point::new(1, 0))));
+}
+
+#[test]
+fn test_apply_operator1() {
+    let mut buf = Buffer::with_contents("foo(bar, baz) qux\n");
+    let res = buf.apply_operator(Operator::Delete, &Point::new(0, 5),
+        Target::TextObject { object: TextObject::Paren, inner: true }).unwrap();
+    assert_eq!(res.text, "bar, baz");
+    assert_eq!(res.point, Point::new(0, 4));
+    assert_eq!(res.lines, vec!["foo() qux".into_line(0)]);
+
+    let res = buf.apply_operator(Operator::Uppercase, &Point::new(0, 0),
+        Target::Motion { motion: Motion::WordEnd, count: 1 }).unwrap();
+    assert_eq!(res.lines, vec!["FOO() qux".into_line(0)]);
+
+    let res = buf.apply_operator(Operator::Yank, &Point::new(0, 6),
+        Target::Motion { motion: Motion::LineEnd, count: 1 }).unwrap();
+    assert_eq!(res.text, "qux");
+    assert_eq!(res.lines, vec![]);
+
+    assert!(buf.undo().is_ok());
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "foo(bar, baz) qux\n");
+}
+
+#[test]
+fn test_apply_operator_indent1() {
+    let mut buf = Buffer::with_contents("a\n\n  b\n\tc\n");
+    let para = Target::TextObject { object: TextObject::Paragraph, inner: false };
+    let res = buf.apply_operator(Operator::Indent, &Point::new(0, 0), para).unwrap();
+    assert_eq!(res.lines, vec!["    a".into_line(0), "".into_line(1)]);
+    assert_eq!(res.point, Point::new(0, 4));
+    let res = buf.apply_operator(Operator::Dedent, &Point::new(2, 0), para).unwrap();
+    assert_eq!(res.lines, vec!["".into_line(1), "b".into_line(2), "c".into_line(3)]);
+    buf.options.expand_tabs = false;
+    let res = buf.apply_operator(Operator::Indent, &Point::new(2, 0),
+        Target::Motion { motion: Motion::CharForward, count: 1 }).unwrap();
+    assert_eq!(res.lines, vec!["\tb".into_line(2)]);
+
+    // Whitespace-only rows count as blank too
+    let mut buf = Buffer::with_contents("a\n  \t\nb\n");
+    buf.indent_rows(0, 2, true).unwrap();
+    assert_eq!(buf.to_str(), "    a\n  \t\n    b\n");
+}
+
+fn span(start: usize, end: usize, style: Style) -> Span {
+    Span { start: start, end: end, style: style }
+}
+
+#[test]
+fn test_highlight1() {
+    let mut buf = Buffer::with_contents("fn f() -> u8 { 42 } // x\nlet s = \"a\\\"b\";\n");
+    buf.set_grammar(Grammar::builtin("rust"));
+    let lines = buf.get_lines(0, 2);
+    assert_eq!(lines[0].spans, vec![
+        span(0, 2, Style::Keyword),
+        span(10, 12, Style::Type),
+        span(15, 17, Style::Number),
+        span(20, 24, Style::Comment)
+    ]);
+    assert_eq!(lines[1].spans, vec![
+        span(0, 3, Style::Keyword),
+        span(8, 14, Style::String)
+    ]);
+    buf.set_grammar(None);
+    assert_eq!(buf.get_lines(0, 1), vec!["fn f() -> u8 { 42 } // x".into_line(0)]);
+}
+
+#[test]
+fn test_highlight_incremental1() {
+    let mut buf = Buffer::with_contents("a\nb */ c\nlet d\n");
+    buf.set_grammar(Grammar::builtin("rust"));
+    assert_eq!(buf.get_lines(2, 3)[0].spans, vec![span(0, 3, Style::Keyword)]);
+
+    // Opening a comment changes how the following lines are highlighted
+    let lines = buf.insert_at_pt("/* ", &Point::new(0, 0)).unwrap();
+    assert_eq!(lines[0].spans, vec![span(0, 4, Style::Comment)]);
+    let lines = buf.get_lines(1, 3);
+    assert_eq!(lines[0].spans, vec![span(0, 4, Style::Comment)]);
+    assert_eq!(lines[1].spans, vec![span(0, 3, Style::Keyword)]);
+
+    let lines = buf.insert_at_pt("x\ny\n", &Point::new(1, 0)).unwrap();
+    assert_eq!(lines[0].spans, vec![span(0, 1, Style::Comment)]);
+    assert_eq!(lines[1].spans, vec![span(0, 1, Style::Comment)]);
+    assert_eq!(buf.get_lines(3, 5)[0].spans, vec![span(0, 4, Style::Comment)]);
+
+    assert!(buf.undo().is_ok());
+    assert!(buf.undo().is_ok());
+    let lines = buf.get_lines(0, 3);
+    assert_eq!(lines[0].spans, vec![]);
+    assert_eq!(lines[1].spans, vec![]);
+    assert_eq!(lines[2].spans, vec![span(0, 3, Style::Keyword)]);
+
+    // Rows added at the end start in the state the last row ends in
+    buf.insert_at_pt("/*", &Point::new(2, 0)).unwrap();
+    buf.get_lines(0, 3);
+    buf.insert_at_pt("e\n", &Point::new(3, 0)).unwrap();
+    assert_eq!(buf.get_lines(3, 4)[0].spans, vec![span(0, 1, Style::Comment)]);
+}
+
+fn textmate_rule(name: &str, pattern: &str) -> TextMateRule {
+    TextMateRule {
+        name: Some(name.to_string()),
+        match_: Some(pattern.to_string()),
+        ..Default::default()
+    }
+}
+
+fn textmate_region(name: &str, begin: &str, end: &str) -> TextMateRule {
+    TextMateRule {
+        name: Some(name.to_string()),
+        begin: Some(begin.to_string()),
+        end: Some(end.to_string()),
+        ..Default::default()
+    }
+}
+
+fn textmate_captures(names: &[(&str, &str)]) -> BTreeMap<String, TextMateCapture> {
+    names.iter().map(|&(group, name)| {
+        (group.to_string(), TextMateCapture { name: Some(name.to_string()) })
+    }).collect()
+}
+
+fn textmate_grammar(patterns: Vec<TextMateRule>, repository: BTreeMap<String, TextMateRule>)
+    -> Grammar {
+    Grammar::from_textmate(TextMateGrammar {
+        name: Some("toy".to_string()),
+        scope_name: "source.toy".to_string(),
+        file_types: vec![],
+        patterns: patterns,
+        repository: repository
+    })
+}
+
+#[test]
+fn test_textmate1() {
+    let mut string = textmate_region("string.quoted.double.toy", "\"", "\"");
+    string.patterns = vec![textmate_rule("constant.character.escape.toy", r"\\.")];
+    let mut declaration = textmate_rule("meta.struct.toy", r"\b(struct)\s+([A-Z]\w*)");
+    declaration.captures = textmate_captures(&[("1", "keyword.other.toy"),
+                                               ("2", "entity.name.type.toy")]);
+    let mut repository = BTreeMap::new();
+    repository.insert("comments".to_string(), TextMateRule {
+        patterns: vec![textmate_rule("comment.line.double-slash.toy", "//.*$"),
+                       textmate_region("comment.block.toy", r"/\*", r"\*/")],
+        ..Default::default()
+    });
+    let patterns = vec![
+        TextMateRule { include: Some("#comments".to_string()), ..Default::default() },
+        textmate_rule("keyword.control.toy", r"\b(?:fn|let|if)\b"),
+        textmate_rule("constant.numeric.toy", r"\b\d+(?:\.\d+)?\b"),
+        string,
+        declaration
+    ];
+    let mut buf = Buffer::with_contents("fn f() { let x = 4.5; } // x\n\
+                                         struct Foo \"a\\\"b\" /* c\n\
+                                         d */ if\n");
+    buf.set_grammar(Some(textmate_grammar(patterns, repository)));
+    let lines = buf.get_lines(0, 3);
+    assert_eq!(lines[0].spans, vec![
+        span(0, 2, Style::Keyword),
+        span(9, 12, Style::Keyword),
+        span(17, 20, Style::Number),
+        span(24, 28, Style::Comment)
+    ]);
+    assert_eq!(lines[1].spans, vec![
+        span(0, 6, Style::Keyword),
+        span(7, 10, Style::Type),
+        span(11, 17, Style::String),
+        span(18, 22, Style::Comment)
+    ]);
+    assert_eq!(lines[2].spans, vec![span(0, 4, Style::Comment), span(5, 7, Style::Keyword)]);
+
+    // Commenting out the line that opens the block comment ends it there
+    buf.insert_at_pt("// ", &Point::new(1, 0)).unwrap();
+    let lines = buf.get_lines(1, 3);
+    assert_eq!(lines[0].spans, vec![span(0, 25, Style::Comment)]);
+    assert_eq!(lines[1].spans, vec![span(5, 7, Style::Keyword)]);
+}
+
+#[test]
+fn test_textmate2() {
+    // The end of a region can refer back to what began it
+    let mut heredoc = textmate_region("string.unquoted.heredoc.toy", r"<<(\w+)$", r"^\1$");
+    heredoc.begin_captures = textmate_captures(&[("0", "keyword.operator.heredoc.toy")]);
+    let patterns = vec![
+        heredoc,
+        textmate_rule("keyword.toy", r"(?i)\bselect\b"),
+        textmate_rule("constant.numeric.toy", r"(?<=#)\h+"),
+        // Patterns that don't compile are left out
+        textmate_rule("keyword.toy", r"(unclosed")
+    ];
+    let mut buf = Buffer::with_contents("x <<EOT\nselect EOF\nEOT\nSELECT #ff\n");
+    buf.set_grammar(Some(textmate_grammar(patterns, BTreeMap::new())));
+    let lines = buf.get_lines(0, 4);
+    assert_eq!(lines[0].spans, vec![span(2, 7, Style::String)]);
+    assert_eq!(lines[1].spans, vec![span(0, 10, Style::String)]);
+    assert_eq!(lines[2].spans, vec![span(0, 3, Style::String)]);
+    assert_eq!(lines[3].spans, vec![span(0, 6, Style::Keyword), span(8, 10, Style::Number)]);
+
+    buf.insert_at_pt("F", &Point::new(0, 6)).unwrap();
+    let lines = buf.get_lines(0, 4);
+    assert_eq!(lines[1].spans, vec![span(0, 10, Style::String)]);
+    assert_eq!(lines[2].spans, vec![span(0, 3, Style::String)]);
+    assert_eq!(lines[3].spans, vec![span(0, 10, Style::String)]);
+}
+
+#[test]
+fn test_textmate3() {
+    // Patterns that backtrack too much give up on the line rather than
+    // hanging the highlighter, and the rest of the grammar still applies
+    let patterns = vec![
+        textmate_rule("keyword.toy", r"(a*)*\1b"),
+        textmate_rule("keyword.toy", r"(a|aa)+\1b"),
+        textmate_rule("keyword.toy", r"(a+)+(?=b)"),
+        textmate_rule("keyword.toy", r"(a*)*b"),
+        textmate_rule("constant.numeric.toy", r"\d+")
+    ];
+    let line = "a".repeat(1000) + " 42 ab";
+    let mut buf = Buffer::with_contents(&(line + "\n"));
+    buf.set_grammar(Some(textmate_grammar(patterns, BTreeMap::new())));
+    assert_eq!(buf.get_lines(0, 1)[0].spans,
+               vec![span(1001, 1003, Style::Number), span(1004, 1006, Style::Keyword)]);
+}
+
+fn node(kind: NodeKind, start: (usize, usize), end: (usize, usize)) -> Node {
+    Node {
+        kind: kind,
+        scope: None,
+        start: Point::new(start.0, start.1),
+        end: Point::new(end.0, end.1)
+    }
+}
+
+fn scoped_node(kind: NodeKind, scope: &str, start: (usize, usize), end: (usize, usize)) -> Node {
+    Node { scope: Some(scope.to_string()), ..node(kind, start, end) }
+}
+
+#[test]
+fn test_syntax_tree1() {
+    let mut buf = Buffer::with_contents("f(a, \"(\") {\n  b[c]\n}\n");
+    buf.set_grammar(Grammar::builtin("rust"));
+    let word_c = node(NodeKind::Word, (1, 4), (1, 5));
+    let brackets = node(NodeKind::Brackets, (1, 3), (1, 6));
+    let braces = node(NodeKind::Braces, (0, 10), (2, 1));
+    assert_eq!(buf.node_at(&Point::new(1, 4)), Ok(word_c.clone()));
+    assert_eq!(buf.node_at(&Point::new(0, 7)), Ok(node(NodeKind::String, (0, 5), (0, 8))));
+    assert_eq!(buf.node_at(&Point::new(0, 9)),
+        Ok(node(NodeKind::Document, (0, 0), (3, 0))));
+
+    assert_eq!(buf.expand_selection(&Point::new(1, 4), &Point::new(1, 4)), Ok(word_c));
+    assert_eq!(buf.expand_selection(&Point::new(1, 4), &Point::new(1, 5)), Ok(brackets.clone()));
+    assert_eq!(buf.expand_selection(&Point::new(1, 3), &Point::new(1, 6)), Ok(braces.clone()));
+    assert_eq!(buf.shrink_selection(&Point::new(0, 10), &Point::new(2, 1)),
+        Ok(node(NodeKind::Word, (1, 2), (1, 3))));
+    assert_eq!(buf.shrink_selection(&Point::new(1, 4), &Point::new(1, 5)),
+        Err(BufErr::NoSyntaxNode));
+
+    assert_eq!(buf.navigate_syntax(&Point::new(1, 2), &Point::new(1, 3), Direction::NextSibling),
+        Ok(brackets));
+    assert_eq!(buf.navigate_syntax(&Point::new(1, 3), &Point::new(1, 6), Direction::Parent),
+        Ok(braces));
+    assert_eq!(buf.navigate_syntax(&Point::new(0, 1), &Point::new(0, 9), Direction::LastChild),
+        Ok(node(NodeKind::String, (0, 5), (0, 8))));
+    assert_eq!(buf.navigate_syntax(&Point::new(0, 0), &Point::new(0, 1), Direction::PrevSibling),
+        Err(BufErr::NoSyntaxNode));
+
+    assert_eq!(buf.query_syntax(Some(NodeKind::Word), None, &Point::new(0, 0), &Point::new(1, 0)),
+        Ok(vec![node(NodeKind::Word, (0, 0), (0, 1)), node(NodeKind::Word, (0, 2), (0, 3))]));
+
+    // The tree follows edits
+    buf.insert_at_pt("(", &Point::new(1, 2)).unwrap();
+    buf.insert_at_pt(")", &Point::new(1, 7)).unwrap();
+    assert_eq!(buf.node_at(&Point::new(1, 2)), Ok(node(NodeKind::Parens, (1, 2), (1, 8))));
+}
+
+#[test]
+fn test_syntax_tree2() {
+    let contents = (0..20).map(|i| format!("fn f{}() {{ g(\"{}\") }} // x\n", i, i))
+        .collect::<String>();
+    let mut buf = Buffer::with_contents(&contents);
+    buf.set_grammar(Grammar::builtin("rust"));
+    let edits: &[(usize, usize, usize, usize, &str)] = &[
+        (3, 2, 3, 2, "ab"),
+        (5, 0, 5, 0, "{\n"),
+        (12, 0, 12, 0, "}"),
+        (7, 3, 9, 1, ""),
+        (2, 0, 2, 0, "/*"),
+        (4, 0, 4, 0, "*/"),
+        (10, 5, 10, 5, "\"\n"),
+        (0, 0, 1, 0, ""),
+        (19, 0, 19, 0, "\n(")
+    ];
+    // Whatever's reused, the tree matches one built from scratch
+    for &(r1, c1, r2, c2, text) in edits {
+        buf.syntax_tree();
+        buf.replace_region(&Point::new(r1, c1), &Point::new(r2, c2), text).unwrap();
+        if r1 % 2 == 0 {
+            buf.insert_at_pt("[x]", &Point::new(15, 0)).unwrap();
+        }
+        let mut fresh = Buffer::with_contents(&buf.to_str());
+        fresh.set_grammar(Grammar::builtin("rust"));
+        let end = Point::new(buf.lines.len(), 0);
+        assert_eq!(buf.query_syntax(None, None, &Point::new(0, 0), &end),
+                   fresh.query_syntax(None, None, &Point::new(0, 0), &end));
+    }
+    while buf.undo().is_ok() {}
+    let mut fresh = Buffer::with_contents(&contents);
+    fresh.set_grammar(Grammar::builtin("rust"));
+    assert_eq!(buf.query_syntax(None, None, &Point::new(0, 0), &Point::new(20, 0)),
+               fresh.query_syntax(None, None, &Point::new(0, 0), &Point::new(20, 0)));
+}
+
+#[test]
+fn test_syntax_tree3() {
+    let include_self = TextMateRule { include: Some("$self".to_string()), ..Default::default() };
+    let mut function = textmate_region("meta.function.toy", r"\bfn\b", r"(?<=\})");
+    function.patterns = vec![include_self.clone()];
+    let mut block = textmate_region("meta.block.toy", r"\{", r"\}");
+    block.patterns = vec![include_self];
+    let mut string = textmate_region("string.quoted.double.toy", "\"", "\"");
+    string.patterns = vec![textmate_rule("constant.character.escape.toy", r"\\.")];
+    let patterns = vec![
+        function,
+        block,
+        textmate_rule("keyword.control.toy", r"\bif\b"),
+        textmate_rule("comment.line.double-slash.toy", "//.*$"),
+        string
+    ];
+    let grammar = textmate_grammar(patterns, BTreeMap::new());
+    let contents = "fn f(a) {\n  if \"x{\" { g(a) } // }\n}\n";
+    let mut buf = Buffer::with_contents(contents);
+    buf.set_grammar(Some(grammar.clone()));
+    // The tree follows the grammar's regions, with brackets and words between
+    // what its rules matched
+    let function = scoped_node(NodeKind::Region, "meta.function.toy", (0, 0), (2, 1));
+    let outer = scoped_node(NodeKind::Braces, "meta.block.toy", (0, 8), (2, 1));
+    let inner = scoped_node(NodeKind::Braces, "meta.block.toy", (1, 10), (1, 18));
+    let keyword = scoped_node(NodeKind::Token, "keyword.control.toy", (1, 2), (1, 4));
+    let string = scoped_node(NodeKind::String, "string.quoted.double.toy", (1, 5), (1, 9));
+    let comment = scoped_node(NodeKind::Comment, "comment.line.double-slash.toy", (1, 19), (1, 23));
+    assert_eq!(buf.node_at(&Point::new(0, 1)), Ok(function.clone()));
+    assert_eq!(buf.node_at(&Point::new(1, 14)), Ok(node(NodeKind::Word, (1, 14), (1, 15))));
+    // Brackets inside strings aren't nodes
+    assert_eq!(buf.node_at(&Point::new(1, 7)), Ok(string.clone()));
+    assert_eq!(buf.node_at(&Point::new(1, 21)), Ok(comment.clone()));
+    assert_eq!(buf.expand_selection(&Point::new(1, 12), &Point::new(1, 16)), Ok(inner.clone()));
+    assert_eq!(buf.expand_selection(&Point::new(0, 8), &Point::new(2, 1)), Ok(function));
+    assert_eq!(buf.shrink_selection(&Point::new(0, 8), &Point::new(2, 1)), Ok(keyword.clone()));
+    assert_eq!(buf.navigate_syntax(&Point::new(1, 2), &Point::new(1, 4), Direction::NextSibling),
+        Ok(string));
+    assert_eq!(buf.navigate_syntax(&Point::new(1, 2), &Point::new(1, 4), Direction::Parent),
+        Ok(outer.clone()));
+    assert_eq!(buf.query_syntax(None, Some("meta.block"), &Point::new(0, 0), &Point::new(3, 0)),
+        Ok(vec![outer, inner]));
+    assert_eq!(buf.query_syntax(Some(NodeKind::Token), None, &Point::new(0, 0), &Point::new(3, 0)),
+        Ok(vec![keyword]));
+    assert_eq!(buf.match_bracket(&Point::new(0, 8)), Some(Point::new(2, 0)));
+    assert_eq!(buf.match_bracket(&Point::new(1, 17)), Some(Point::new(1, 10)));
+    assert_eq!(buf.match_bracket(&Point::new(1, 7)), None);
+
+    // Rows scanned again after edits give the same tree as a fresh buffer
+    let edits: &[(usize, usize, usize, usize, &str)] = &[
+        (1, 6, 1, 6, "\n"),
+        (0, 0, 0, 0, "if \"a\n"),
+        (3, 0, 3, 0, "{ fn g() {\n"),
+        (1, 0, 1, 1, ""),
+        (0, 0, 2, 0, "")
+    ];
+    for &(r1, c1, r2, c2, text) in edits {
+        buf.syntax_tree();
+        buf.replace_region(&Point::new(r1, c1), &Point::new(r2, c2), text).unwrap();
+        let mut fresh = Buffer::with_contents(&buf.to_str());
+        fresh.set_grammar(Some(grammar.clone()));
+        let end = Point::new(buf.lines.len(), 0);
+        assert_eq!(buf.query_syntax(None, None, &Point::new(0, 0), &end),
+                   fresh.query_syntax(None, None, &Point::new(0, 0), &end));
+    }
+}
+
+fn fold(start: usize, end: usize, kind: FoldKind) -> FoldingRange {
+    FoldingRange { start: start, end: end, kind: kind }
+}
+
+#[test]
+fn test_folding_ranges1() {
+    let mut buf = Buffer::with_contents("// {{{\nfn f() {\n  a(\n    b)\n\n}\n// }}}\nif x:\n\ty\n");
+    assert_eq!(buf.folding_ranges(), vec![
+        fold(0, 6, FoldKind::Marker),
+        fold(1, 4, FoldKind::Syntax),
+        fold(1, 3, FoldKind::Indent),
+        fold(2, 3, FoldKind::Indent),
+        fold(7, 8, FoldKind::Indent)
+    ]);
+    buf.set_grammar(Grammar::builtin("rust"));
+    // Adjacent comments fold together
+    buf.insert_at_pt("/*\n*/\n", &Point::new(0, 0)).unwrap();
+    assert_eq!(buf.folding_ranges()[..2].to_vec(), vec![
+        fold(0, 2, FoldKind::Syntax),
+        fold(2, 8, FoldKind::Marker)
+    ]);
+}
+
+#[test]
+fn test_folding_ranges2() {
+    // Brackets that close on the first row don't fold
+    let buf = Buffer::with_contents("(a)\nb\n");
+    assert_eq!(buf.folding_ranges(), vec![]);
+    let buf = Buffer::with_contents("f(x)\n{\n  a\n}\n");
+    assert_eq!(buf.folding_ranges(), vec![
+        fold(1, 2, FoldKind::Syntax)
+    ]);
+    let buf = Buffer::with_contents("a\n  b\n    c\n\n  d\n    e\nf\n  g\n");
+    assert_eq!(buf.folding_ranges(), vec![
+        fold(0, 5, FoldKind::Indent),
+        fold(1, 2, FoldKind::Indent),
+        fold(4, 5, FoldKind::Indent),
+        fold(6, 7, FoldKind::Indent)
+    ]);
+}
+
+#[test]
+fn test_insert_newline1() {
+    let mut buf = Buffer::with_contents("fn f() {}\n");
+    assert_eq!(buf.insert_newline(&Point::new(0, 8)), Ok((vec![
+        "fn f() {".into_line(0),
+        "    ".into_line(1),
+        "}".into_line(2)
+    ], Point::new(1, 4))));
+    assert_eq!(buf.insert_newline(&Point::new(1, 4)), Ok((vec![
+        "".into_line(1),
+        "    ".into_line(2)
+    ], Point::new(2, 4))));
+    buf.insert_at_pt("a(b,   c)", &Point::new(2, 4)).unwrap();
+    assert_eq!(buf.insert_newline(&Point::new(2, 8)), Ok((vec![
+        "    a(b,".into_line(2),
+        "        c)".into_line(3)
+    ], Point::new(3, 8))));
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.to_str(), "fn f() {\n\n    a(b,   c)\n}\n");
+}
+
+#[test]
+fn test_reindent_rows1() {
+    let mut buf = Buffer::with_contents("if x {\nfoo(\n\t1,\n  2) \n  \n  } else {\n// \"{\"\ny\n}\n");
+    buf.options.indent_width = 2;
+    buf.set_grammar(Grammar::builtin("c"));
+    assert_eq!(buf.indent_for_row(1), "  ");
+    let lines = buf.reindent_rows(0, 8).unwrap();
+    assert_eq!(lines.iter().map(|line| line.line.as_str()).collect::<Vec<_>>(), vec![
+        "if x {",
+        "  foo(",
+        "    1,",
+        "    2) ",
+        "",
+        "} else {",
+        "  // \"{\"",
+        "  y",
+        "}"
+    ]);
+    assert!(buf.undo().is_ok());
+    assert_eq!(buf.lines[2], vec!['\t', '1', ',']);
+    assert_eq!(buf.reindent_rows(9, 9), Err(BufErr::InvalidPoint));
+}
+
+#[test]
+fn test_visual_cols1() {
+    let mut buf = Buffer::with_contents("a\tb\n日本x\ne\u{301}f\n\n");
+    assert_eq!(char_width('日'), 2);
+    assert_eq!(char_width('\u{301}'), 0);
+    assert_eq!(buf.visual_col_at(&Point::new(0, 2)), 8);
+    assert_eq!(buf.visual_col_at(&Point::new(1, 2)), 4);
+    assert_eq!(buf.visual_col_at(&Point::new(2, 2)), 1);
+    assert_eq!(buf.point_at_visual_col(0, 5), Point::new(0, 1));
+    assert_eq!(buf.point_at_visual_col(1, 3), Point::new(1, 1));
+    assert_eq!(buf.point_at_visual_col(2, 1), Point::new(2, 2));
+    assert_eq!(buf.point_at_visual_col(2, 9), Point::new(2, 3));
+    buf.options.tab_width = 4;
+    assert_eq!(buf.visual_col_at(&Point::new(0, 3)), 5);
+
+    assert_eq!(buf.move_point(&Point::new(1, 2), Motion::LineUp, 1), Ok(Point::new(0, 2)));
+    assert_eq!(buf.move_point(&Point::new(0, 2), Motion::LineDown, 1), Ok(Point::new(1, 2)));
+    // Short rows in between don't lose the column
+    assert_eq!(buf.move_point(&Point::new(1, 3), Motion::LineDown, 2), Ok(Point::new(3, 0)));
+    assert_eq!(buf.move_point(&Point::new(1, 3), Motion::LineUp, 5), Ok(Point::new(0, 3)));
+    assert_eq!(buf.move_point(&Point::new(0, 2), Motion::LineDown, 9), Ok(Point::new(3, 0)));
+
+    assert_eq!(buf.block_to_strs(&Point::new(0, 1), &Point::new(1, 2)),
+        Ok(vec!["\t".to_string(), "本".to_string()]));
+}
+
+#[test]
+fn test_apply_operator_linewise1() {
+    let mut buf = Buffer::with_contents("a\nb\nc\n");
+    let res = buf.apply_operator(Operator::Delete, &Point::new(1, 1),
+        Target::Motion { motion: Motion::LineDown, count: 1 }).unwrap();
+    assert_eq!(res.text, "\nb\nc\n");
+    assert_eq!(buf.to_str(), "a\n");
+}
+
+fn display_line(row: usize, start: usize, end: usize, indent: usize, text: &str) -> DisplayLine {
+    DisplayLine { row: row, start: start, end: end, indent: indent, text: text.to_string() }
+}
+
+#[test]
+fn test_display_lines1() {
+    let mut buf = Buffer::with_contents("  aaa bbb ccc\n\nabcdefghij\n");
+    assert_eq!(buf.display_lines(8, 0, 10), Ok(vec![
+        display_line(0, 0, 6, 0, "  aaa "),
+        display_line(0, 6, 10, 2, "bbb "),
+        display_line(0, 10, 13, 2, "ccc"),
+        display_line(1, 0, 0, 0, ""),
+        display_line(2, 0, 8, 0, "abcdefgh"),
+        display_line(2, 8, 10, 0, "ij")
+    ]));
+    assert_eq!(buf.display_row_count(8), Ok(6));
+    assert_eq!(buf.display_row_count(0), Err(BufErr::InvalidWidth));
+    assert_eq!(buf.point_to_display(&Point::new(0, 7), 8), Ok((1, 3)));
+    assert_eq!(buf.point_to_display(&Point::new(2, 10), 8), Ok((5, 2)));
+    assert_eq!(buf.point_to_display(&Point::new(3, 0), 8), Ok((6, 0)));
+    assert_eq!(buf.display_to_point(1, 3, 8), Ok(Point::new(0, 7)));
+    assert_eq!(buf.display_to_point(1, 0, 8), Ok(Point::new(0, 6)));
+    assert_eq!(buf.display_to_point(1, 7, 8), Ok(Point::new(0, 9)));
+    assert_eq!(buf.display_to_point(5, 7, 8), Ok(Point::new(2, 10)));
+
+    // Only the edited row is wrapped again
+    buf.insert_at_pt("x\ny ", &Point::new(0, 2)).unwrap();
+    assert_eq!(buf.display_lines(8, 0, 4), Ok(vec![
+        display_line(0, 0, 3, 0, "  x"),
+        display_line(1, 0, 6, 0, "y aaa "),
+        display_line(1, 6, 13, 0, "bbb ccc"),
+        display_line(2, 0, 0, 0, "")
+    ]));
+    assert_eq!(buf.display_lines(8, 4, 7), Ok(vec![
+        display_line(3, 0, 8, 0, "abcdefgh"),
+        display_line(3, 8, 10, 0, "ij")
+    ]));
+}
+
+#[test]
+fn test_join_lines1() {
+    let mut buf = Buffer::with_contents("foo  \n    bar\n\nbaz\nqux\n");
+    assert_eq!(buf.join_lines(0, 0), Ok(vec!["foo bar".into_line(0)]));
+    assert_eq!(buf.join_lines(0, 2), Ok(vec!["foo bar baz".into_line(0)]));
+    assert_eq!(buf.to_str(), "foo bar baz\nqux\n");
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "foo bar\n\nbaz\nqux\n");
+    assert_eq!(buf.join_lines(3, 3), Ok(vec!["qux".into_line(3)]));
+    assert_eq!(buf.join_lines(2, 4).unwrap_err(), BufErr::InvalidPoint);
+}
+
+#[test]
+fn test_duplicate_and_move_lines1() {
+    let mut buf = Buffer::with_contents("a\nb\nc\n");
+    buf.duplicate_lines(0, 1).unwrap();
+    assert_eq!(buf.to_str(), "a\nb\na\nb\nc\n");
+    buf.duplicate_region(&Point::new(4, 0), &Point::new(4, 1)).unwrap();
+    assert_eq!(buf.to_str(), "a\nb\na\nb\ncc\n");
+    assert_eq!(buf.move_lines(3, 4, -2), Ok(vec!["b".into_line(1), "cc".into_line(2),
+        "b".into_line(3), "a".into_line(4)]));
+    assert_eq!(buf.to_str(), "a\nb\ncc\nb\na\n");
+    buf.move_lines(0, 0, 10).unwrap();
+    assert_eq!(buf.to_str(), "b\ncc\nb\na\na\n");
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "a\nb\ncc\nb\na\n");
+}
+
+#[test]
+fn test_move_lines2() {
+    let mut buf = Buffer::with_contents("a\nb\nc\n");
+    buf.move_lines(1, 1, isize::MIN).unwrap();
+    assert_eq!(buf.to_str(), "b\na\nc\n");
+    buf.move_lines(1, 2, isize::MIN + 1).unwrap();
+    assert_eq!(buf.to_str(), "a\nc\nb\n");
+    buf.move_lines(0, 0, isize::MAX).unwrap();
+    assert_eq!(buf.to_str(), "c\nb\na\n");
+    buf.move_lines(2, 2, isize::MAX).unwrap();
+    assert_eq!(buf.to_str(), "c\nb\na\n");
+}
+
+#[test]
+fn test_sort_lines1() {
+    let mut buf = Buffer::with_contents("b10\nB2\na\nb2\n");
+    buf.sort_lines(0, 3, SortKind::Lexical, false).unwrap();
+    assert_eq!(buf.to_str(), "B2\na\nb10\nb2\n");
+    buf.sort_lines(0, 3, SortKind::Numeric, false).unwrap();
+    assert_eq!(buf.to_str(), "a\nB2\nb2\nb10\n");
+    buf.sort_lines(0, 3, SortKind::CaseInsensitive, true).unwrap();
+    assert_eq!(buf.to_str(), "B2\nb2\nb10\na\n");
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "a\nB2\nb2\nb10\n");
+}
+
+#[test]
+fn test_unique_reverse_trim_lines1() {
+    let mut buf = Buffer::with_contents("a \nb\t\na \nc\n");
+    assert_eq!(buf.unique_lines(0, 3), Ok(vec!["a ".into_line(0), "b\t".into_line(1),
+        "c".into_line(2)]));
+    buf.reverse_lines(0, 2).unwrap();
+    assert_eq!(buf.to_str(), "c\nb\t\na \n");
+    assert_eq!(buf.trim_trailing_whitespace(0, 2), Ok(vec!["c".into_line(0),
+        "b".into_line(1), "a".into_line(2)]));
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "c\nb\t\na \n");
+}
+
+#[test]
+fn test_convert_case1() {
+    assert_eq!(convert_case("parse_HTTPRequest(x)", Case::Camel), "parseHttpRequest(x)");
+    assert_eq!(convert_case("_fooBar2Baz qux", Case::Snake), "_foo_bar2_baz qux");
+    assert_eq!(convert_case("hello wORLD-foo", Case::Title), "Hello World-Foo");
+    assert_eq!(convert_case("Straße", Case::Upper), "STRASSE");
+    let mut buf = Buffer::with_contents("let fooBar = baz_qux;\n");
+    assert_eq!(buf.change_case(&Point::new(0, 4), &Point::new(0, 20), Case::Snake),
+        Ok(vec!["let foo_bar = baz_qux;".into_line(0)]));
+    buf.change_case(&Point::new(0, 0), &Point::new(0, 22), Case::Upper).unwrap();
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "let foo_bar = baz_qux;\n");
+}
+
+#[test]
+fn test_transpose1() {
+    let mut buf = Buffer::with_contents("abc\nfoo, bar baz\n");
+    buf.transpose(&Point::new(0, 1), TransposeUnit::Chars).unwrap();
+    assert_eq!(buf.to_str(), "bac\nfoo, bar baz\n");
+    buf.transpose(&Point::new(0, 3), TransposeUnit::Chars).unwrap();
+    assert_eq!(buf.to_str(), "bca\nfoo, bar baz\n");
+    assert_eq!(buf.transpose(&Point::new(0, 0), TransposeUnit::Chars).unwrap_err(),
+        BufErr::NothingToTranspose);
+    buf.transpose(&Point::new(1, 1), TransposeUnit::Words).unwrap();
+    assert_eq!(buf.to_str(), "bca\nbar, foo baz\n");
+    buf.transpose(&Point::new(1, 4), TransposeUnit::Words).unwrap();
+    assert_eq!(buf.to_str(), "bca\nfoo, bar baz\n");
+    buf.transpose(&Point::new(1, 12), TransposeUnit::Words).unwrap();
+    assert_eq!(buf.to_str(), "bca\nfoo, baz bar\n");
+    buf.transpose(&Point::new(1, 0), TransposeUnit::Lines).unwrap();
+    assert_eq!(buf.to_str(), "foo, baz bar\nbca\n");
+    assert_eq!(buf.transpose(&Point::new(0, 0), TransposeUnit::Lines).unwrap_err(),
+        BufErr::NothingToTranspose);
+}
+
+#[test]
+fn test_toggle_comment1() {
+    let mut buf = Buffer::with_contents("fn f() {\n    a();\n\n  b();\n}\n");
+    assert_eq!(buf.toggle_line_comment(1, 3).unwrap_err(), BufErr::NoCommentSyntax);
+    buf.set_grammar(Grammar::builtin("rust"));
+    buf.toggle_line_comment(1, 3).unwrap();
+    assert_eq!(buf.to_str(), "fn f() {\n  //   a();\n\n  // b();\n}\n");
+    buf.toggle_line_comment(1, 3).unwrap();
+    assert_eq!(buf.to_str(), "fn f() {\n    a();\n\n  b();\n}\n");
+    buf.toggle_block_comment(&Point::new(1, 2), &Point::new(1, 8)).unwrap();
+    assert_eq!(buf.to_str(), "fn f() {\n    /* a(); */\n\n  b();\n}\n");
+    buf.toggle_block_comment(&Point::new(1, 0), &Point::new(1, 14)).unwrap();
+    assert_eq!(buf.to_str(), "fn f() {\n    a();\n\n  b();\n}\n");
+    buf.set_grammar(Grammar::builtin("python"));
+    buf.toggle_line_comment(0, 0).unwrap();
+    assert_eq!(buf.to_str(), "# fn f() {\n    a();\n\n  b();\n}\n");
+}
+
+fn completions(start: (usize, usize), candidates: &[&str]) -> Completions {
+    Completions {
+        start: Point::new(start.0, start.1),
+        candidates: candidates.iter().map(|c| c.to_string()).collect()
+    }
+}
+
+#[test]
+fn test_completions1() {
+    let mut buf = Buffer::with_contents("format forward\nFormula flowery\nfor\n");
+    let other = Buffer::with_contents("fortune format\n");
+    assert_eq!(buf.completions(&Point::new(2, 3), &[], 10),
+        Ok(completions((2, 0), &["format", "forward", "Formula", "flowery"])));
+    assert_eq!(buf.completions(&Point::new(2, 3), &[&other], 2),
+        Ok(completions((2, 0), &["format", "fortune"])));
+    assert_eq!(buf.completions(&Point::new(2, 0), &[], 10), Ok(completions((2, 0), &[])));
+    // Words typed most recently rank first among equally good matches
+    buf.insert_at_pt(" fork", &Point::new(0, 14)).unwrap();
+    assert_eq!(buf.completions(&Point::new(2, 3), &[], 3),
+        Ok(completions((2, 0), &["fork", "format", "forward"])));
+    buf.delete_region(&Point::new(0, 0), &Point::new(1, 0)).unwrap();
+    assert_eq!(buf.completions(&Point::new(1, 2), &[], 10),
+        Ok(completions((1, 0), &["Formula", "flowery"])));
+}
+
+fn tab_stop(index: usize, ranges: &[((usize, usize), (usize, usize))], choices: &[&str])
+    -> TabStop {
+    TabStop {
+        index: index,
+        ranges: ranges.iter()
+            .map(|&(s, e)| Selection::new(Point::new(s.0, s.1), Point::new(e.0, e.1)))
+            .collect(),
+        choices: choices.iter().map(|c| c.to_string()).collect()
+    }
+}
+
+#[test]
+fn test_insert_snippet1() {
+    let mut buf = Buffer::with_contents("  x\n");
+    let (_, stop) = buf.insert_snippet("for ${1:i} in ${2:iter} {\n\t$0 // $1\n}",
+                                       &Point::new(0, 3)).unwrap();
+    assert_eq!(buf.to_str(), "  xfor i in iter {\n       // i\n  }\n");
+    assert_eq!(stop, tab_stop(1, &[((0, 7), (0, 8)), ((1, 10), (1, 11))], &[]));
+    // Typing over the placeholder updates its mirror
+    buf.replace_region(&Point::new(0, 7), &Point::new(0, 8), "idx").unwrap();
+    buf.update_snippet_mirrors();
+    assert_eq!(buf.to_str(), "  xfor idx in iter {\n       // idx\n  }\n");
+    assert_eq!(buf.next_tab_stop(), Ok(tab_stop(2, &[((0, 14), (0, 18))], &[])));
+    assert_eq!(buf.prev_tab_stop(), Ok(tab_stop(1, &[((0, 7), (0, 10)), ((1, 10), (1, 13))], &[])));
+    buf.next_tab_stop().unwrap();
+    assert_eq!(buf.next_tab_stop(), Ok(tab_stop(0, &[((1, 6), (1, 6))], &[])));
+    assert_eq!(buf.next_tab_stop().unwrap_err(), BufErr::NoSnippet);
+    // The mirror was copied as part of the same undo step as the typing
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "  xfor i in iter {\n       // i\n  }\n");
+}
+
+#[test]
+fn test_insert_snippet2() {
+    let mut buf = Buffer::new();
+    let (_, stop) = buf.insert_snippet("${1|a,b|}-$2-${2:two} \\$$TM_LINE_NUMBER ${X:y}",
+                                       &Point::new(0, 0)).unwrap();
+    assert_eq!(buf.to_str(), "a-two-two $1 y\n");
+    assert_eq!(stop, tab_stop(1, &[((0, 0), (0, 1))], &["a", "b"]));
+    assert_eq!(buf.next_tab_stop(), Ok(tab_stop(2, &[((0, 6), (0, 9)), ((0, 2), (0, 5))], &[])));
+    buf.cancel_snippet();
+    assert_eq!(buf.current_tab_stop(), None);
+    assert_eq!(buf.insert_snippet("${1:oops", &Point::new(0, 0)).unwrap_err(),
+        BufErr::InvalidSnippet);
+}
+
+#[test]
+fn test_abbreviations1() {
+    let mut buf = Buffer::with_contents("teh\nxteh\n");
+    buf.abbreviations.insert("teh".to_string(), "the".to_string());
+    buf.abbreviations.insert("sig".to_string(), "Regards,\nMe".to_string());
+    assert_eq!(buf.insert_typed_expanding(" ", &Point::new(0, 3)), Ok(vec!["the ".into_line(0)]));
+    buf.insert_typed_expanding(" ", &Point::new(1, 4)).unwrap();
+    assert_eq!(buf.to_str(), "the \nxteh \n");
+    buf.undo().unwrap();
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "teh \nxteh\n");
+    buf.insert_typed_expanding("h", &Point::new(0, 4)).unwrap();
+    assert_eq!(buf.to_str(), "teh h\nxteh\n");
+
+    let mut buf = Buffer::with_contents("sig\nteh\n");
+    buf.abbreviations.insert("teh".to_string(), "the".to_string());
+    buf.abbreviations.insert("sig".to_string(), "Regards,\nMe".to_string());
+    let mut sels = SelectionSet::new(vec![Selection::cursor(Point::new(0, 3)),
+                                          Selection::cursor(Point::new(1, 3))]);
+    buf.insert_at_selections_expanding(".", &mut sels).unwrap();
+    assert_eq!(buf.to_str(), "Regards,\nMe.\nthe.\n");
+    assert_eq!(sels.selections, vec![Selection::cursor(Point::new(1, 3)),
+                                     Selection::cursor(Point::new(2, 4))]);
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "sig.\nteh.\n");
+}
+
+fn change(kind: ChangeKind, old_start: (usize, usize), new_start: (usize, usize), text: &str)
+    -> Change {
+    Change {
+        kind: kind,
+        old_start: Point::new(old_start.0, old_start.1),
+        new_start: Point::new(new_start.0, new_start.1),
+        text: text.to_string()
+    }
+}
+
+#[test]
+fn test_diff1() {
+    let old = Buffer::with_contents("a\nb\nc\nd\n");
+    let new = Buffer::with_contents("a\nbee\nc\ne\nd\n");
+    assert_eq!(old.diff_lines(&new), vec![
+        change(ChangeKind::Equal, (0, 0), (0, 0), "a\n"),
+        change(ChangeKind::Delete, (1, 0), (1, 0), "b\n"),
+        change(ChangeKind::Insert, (2, 0), (1, 0), "bee\n"),
+        change(ChangeKind::Equal, (2, 0), (2, 0), "c\n"),
+        change(ChangeKind::Insert, (3, 0), (3, 0), "e\n"),
+        change(ChangeKind::Equal, (3, 0), (4, 0), "d\n")
+    ]);
+    assert_eq!(old.diff_chars(&new), vec![
+        change(ChangeKind::Equal, (0, 0), (0, 0), "a\nb"),
+        change(ChangeKind::Insert, (1, 1), (1, 1), "ee"),
+        change(ChangeKind::Equal, (1, 1), (1, 3), "\nc\n"),
+        change(ChangeKind::Insert, (3, 0), (3, 0), "e\n"),
+        change(ChangeKind::Equal, (3, 0), (4, 0), "d\n")
+    ]);
+    assert_eq!(old.diff_lines(&Buffer::with_contents("a\nb\nc\nd")), vec![
+        change(ChangeKind::Equal, (0, 0), (0, 0), "a\nb\nc\nd\n")
+    ]);
+}
+
+#[test]
+fn test_unified_diff_and_patch1() {
+    let contents = (1..21).map(|i| i.to_string() + "\n").collect::<String>();
+    let mut old = Buffer::with_contents(&contents);
+    let mut new = Buffer::with_contents(&contents.replace("\n3\n", "\nthree\n")
+                                                 .replace("\n18\n", "\n"));
+    let diff = old.unified_diff(&new, "a/f", "b/f", 2);
+    assert_eq!(diff, "--- a/f\n+++ b/f\n\
+                      @@ -1,5 +1,5 @@\n 1\n 2\n-3\n+three\n 4\n 5\n\
+                      @@ -16,5 +16,4 @@\n 16\n 17\n-18\n 19\n 20\n");
+    assert_eq!(old.unified_diff(&new, "a/f", "b/f", 6).matches("@@ -").count(), 2);
+    assert_eq!(old.unified_diff(&new, "a/f", "b/f", 7).matches("@@ -").count(), 1);
+    assert_eq!(old.unified_diff(&old, "a/f", "b/f", 3), "");
+
+    assert_eq!(old.apply_patch(&diff).map(|lines| lines.len()), Ok(19));
+    assert_eq!(old.to_str(), new.to_str());
+    old.undo().unwrap();
+    assert_eq!(old.to_str(), contents);
+    assert_eq!(old.apply_patch("@@ -0,0 +1 @@\n+0\n@@ -20,0 +22 @@\n+21\n").unwrap().len(), 22);
+    assert_eq!(old.line_len(0), 1);
+    assert_eq!(old.to_str().lines().last(), Some("21"));
+
+    let mut buf = Buffer::with_contents("x\ny\n");
+    assert_eq!(buf.apply_patch("@@ -1 +1 @@\n-x\n+z\n@@ -2 +2 @@\n-q\n+w\n"),
+               Err(BufErr::PatchConflict(2)));
+    assert_eq!(buf.to_str(), "x\ny\n");
+    assert_eq!(buf.apply_patch("@@ -1,2 +1 @@\n-x\n"), Err(BufErr::InvalidPatch));
+    assert_eq!(buf.apply_patch("no hunks"), Err(BufErr::InvalidPatch));
+    buf.apply_patch("--- a/f\n+++ b/f\n@@ -2 +1,0 @@\n-y\n").unwrap();
+    assert_eq!(buf.to_str(), "x\n");
+}
+
+#[test]
+fn test_merge1() {
+    let base = Buffer::with_contents("a\nb\nc\nd\ne\n");
+    let theirs = Buffer::with_contents("a\nB\nc\nd\ne\nf\n");
+    let mut buf = Buffer::with_contents("a\nb\nc\nD\ne\n");
+    let (_, conflicts) = buf.merge(&base, &theirs).unwrap();
+    assert_eq!(conflicts, vec![]);
+    assert_eq!(buf.to_str(), "a\nB\nc\nD\ne\nf\n");
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "a\nb\nc\nD\ne\n");
+
+    let theirs = Buffer::with_contents("a\nb\nc\nx\ne\n");
+    let (_, conflicts) = buf.merge(&base, &theirs).unwrap();
+    assert_eq!(conflicts, vec![3]);
+    assert_eq!(buf.to_str(), "a\nb\nc\n<<<<<<< buffer\nD\n=======\nx\n>>>>>>> file\ne\n");
+
+    let mut buf = Buffer::with_contents("a\nb\nc\nD\ne\n");
+    let theirs = Buffer::with_contents("a\nb\nc\nD\ne\n");
+    assert_eq!(buf.merge(&base, &theirs), Ok((vec![], vec![])));
+
+    let mut buf = Buffer::with_contents("a\nb\n");
+    assert_eq!(buf.replace_contents(&Buffer::with_contents("a\nc\nd\n")).unwrap().len(), 2);
+    assert_eq!(buf.to_str(), "a\nc\nd\n");
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "a\nb\n");
+}
+
+struct Rows(Vec<&'static str>);
+
+impl LineSource for Rows {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn line(&self, r: usize) -> Vec<char> {
+        self.0[r].chars().collect()
+    }
+}
+
+#[test]
+fn test_lazy_buffer1() {
+    let mut lazy = LazyBuffer::new(Rows(vec!["one", "two", "three", "four"]));
+    assert_eq!(lazy.get_lines(2, 10), vec!["three".into_line(2), "four".into_line(3)]);
+    assert_eq!(lazy.insert_at_pt("x\ny", &Point::new(1, 1)),
+               Ok(vec!["tx".into_line(1), "ywo".into_line(2)]));
+    assert_eq!(lazy.delete_region(&Point::new(3, 2), &Point::new(4, 1)), Ok("ree\nf".to_string()));
+    assert_eq!(lazy.insert_at_pt("five\n", &Point::new(4, 0)), Ok(vec!["five".into_line(4)]));
+    assert_eq!(lazy.insert_at_pt("!", &Point::new(5, 1)), Err(BufErr::InvalidPoint));
+    assert_eq!(lazy.len(), 5);
+    assert_eq!(lazy.get_lines(0, 5), vec!["one".into_line(0), "tx".into_line(1),
+                                          "ywo".into_line(2), "thour".into_line(3),
+                                          "five".into_line(4)]);
+    let mut buf = lazy.to_buffer().unwrap();
+    assert_eq!(buf.to_str(), "one\ntx\nywo\nthour\nfive\n");
+    buf.undo().unwrap();
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "one\ntx\nywo\nthree\nfour\n");
+}
+
+#[test]
+fn test_lazy_buffer2() {
+    let mut lazy = LazyBuffer::new(Rows(vec!["fn f", ""]));
+    lazy.options.auto_pair = true;
+    lazy.abbreviations.insert("teh".to_string(), "the".to_string());
+    let changes = lazy.observe();
+    assert_eq!(lazy.insert_typed_expanding("(", &Point::new(0, 4)),
+               Ok(vec!["fn f()".into_line(0)]));
+    assert!(lazy.insert_at_pt("teh", &Point::new(1, 0)).is_ok());
+    assert_eq!(lazy.insert_typed_expanding(" ", &Point::new(1, 3)), Ok(vec!["the ".into_line(1)]));
+    let changes = changes.try_iter()
+        .map(|event| (event.start, event.old_text, event.new_text, event.version))
+        .collect::<Vec<_>>();
+    assert_eq!(changes, vec![
+        (Point::new(0, 4), "".to_string(), "()".to_string(), 1),
+        (Point::new(1, 0), "".to_string(), "teh".to_string(), 2),
+        (Point::new(1, 3), "".to_string(), " ".to_string(), 3),
+        (Point::new(1, 0), "teh".to_string(), "".to_string(), 4),
+        (Point::new(1, 0), "".to_string(), "the".to_string(), 5)
+    ]);
+    let mut buf = lazy.to_buffer().unwrap();
+    assert_eq!(buf.version(), 5);
+    // The expansion is still its own undo step
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "fn f()\nteh \n");
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "fn f()\nteh\n");
+}
+
+// Rows that are cut short once `cut` is set, as a file truncated on disk
+// would be, either saying so or not
+struct CutRows {
+    rows: Vec<&'static str>,
+    cut: Cell<bool>,
+    says_changed: bool
+}
+
+impl LineSource for CutRows {
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn line(&self, r: usize) -> Vec<char> {
+        if self.cut.get() { vec![] } else { self.rows[r].chars().collect() }
+    }
+
+    fn changed(&self) -> bool {
+        self.says_changed && self.cut.get()
+    }
+}
+
+#[test]
+fn test_lazy_buffer3() {
+    for &says_changed in &[true, false] {
+        let mut lazy = LazyBuffer::new(CutRows {
+            rows: vec!["abc", "def"],
+            cut: Cell::new(false),
+            says_changed: says_changed
+        });
+        assert!(lazy.insert_at_pt("x", &Point::new(0, 3)).is_ok());
+        lazy.source().cut.set(true);
+        assert_eq!(lazy.to_buffer().err(), Some(BufErr::SourceChanged));
+        lazy.source().cut.set(false);
+        assert_eq!(lazy.to_buffer().unwrap().to_str(), "abcx\ndef\n");
+    }
+}
+
+#[test]
+fn test_lazy_buffer_read_only1() {
+    let mut lazy = LazyBuffer::new(Rows(vec!["one", "two"]));
+    lazy.options.read_only = true;
+    assert_eq!(lazy.insert_at_pt("x", &Point::new(0, 0)), Err(BufErr::ReadOnly));
+    assert_eq!(lazy.delete_region(&Point::new(0, 0), &Point::new(1, 0)), Err(BufErr::ReadOnly));
+    assert_eq!(lazy.get_lines(0, 2), vec!["one".into_line(0), "two".into_line(1)]);
+}
+
+#[test]
+fn test_read_only1() {
+    let mut buf = Buffer::with_contents("abc\ndef\n");
+    buf.insert_at_pt("x", &Point::new(0, 0)).unwrap();
+    buf.options.read_only = true;
+    assert_eq!(buf.insert_at_pt("y", &Point::new(0, 0)), Err(BufErr::ReadOnly));
+    assert_eq!(buf.delete_region(&Point::new(0, 0), &Point::new(0, 1)), Err(BufErr::ReadOnly));
+    assert_eq!(buf.undo(), Err(BufErr::ReadOnly));
+    buf.options.read_only = false;
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "abc\ndef\n");
+}
+
+#[test]
+fn test_protect_region1() {
+    let mut buf = Buffer::with_contents("abc\ndef\n");
+    buf.protect_region(&Point::new(0, 1), &Point::new(1, 1)).unwrap();
+    assert_eq!(buf.insert_at_pt("x", &Point::new(0, 2)), Err(BufErr::ReadOnly));
+    assert_eq!(buf.delete_region(&Point::new(1, 0), &Point::new(1, 2)), Err(BufErr::ReadOnly));
+    assert_eq!(buf.replace_region(&Point::new(0, 0), &Point::new(0, 2), "z"),
+               Err(BufErr::ReadOnly));
+    buf.insert_at_pt("x", &Point::new(0, 1)).unwrap();
+    buf.insert_at_pt("y", &Point::new(1, 1)).unwrap();
+    buf.delete_region(&Point::new(0, 0), &Point::new(0, 1)).unwrap();
+    assert_eq!(buf.to_str(), "xbc\ndyef\n");
+    assert_eq!(buf.protected_regions(), vec![(Point::new(0, 1), Point::new(1, 1))]);
+
+    buf.unprotect_region(&Point::new(0, 2), &Point::new(0, 3)).unwrap();
+    assert_eq!(buf.protected_regions(), vec![(Point::new(0, 1), Point::new(0, 2)),
+                                             (Point::new(0, 3), Point::new(1, 1))]);
+    buf.insert_at_pt("!", &Point::new(0, 3)).unwrap();
+    assert_eq!(buf.to_str(), "xbc!\ndyef\n");
+    buf.undo().unwrap();
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "axbc\ndyef\n");
+}
+
+#[test]
+fn test_protect_region2() {
+    let mut buf = Buffer::with_contents("ab  \ncd  \nef  \n");
+    buf.protect_region(&Point::new(1, 1), &Point::new(1, 3)).unwrap();
+    buf.insert_at_pt("!", &Point::new(2, 0)).unwrap();
+    // Commands that reach the protected text partway through change nothing
+    let mut sels = SelectionSet::new(vec![Selection::cursor(Point::new(1, 2)),
+                                          Selection::cursor(Point::new(2, 1))]);
+    assert_eq!(buf.insert_at_selections("x", &mut sels), Err(BufErr::ReadOnly));
+    assert_eq!(buf.delete_block(&Point::new(0, 1), &Point::new(2, 2)), Err(BufErr::ReadOnly));
+    assert_eq!(buf.insert_block(&["x".to_string(), "y".to_string()], &Point::new(0, 2)),
+               Err(BufErr::ReadOnly));
+    assert_eq!(buf.trim_trailing_whitespace(0, 2), Err(BufErr::ReadOnly));
+    assert_eq!(buf.to_str(), "ab  \ncd  \n!ef  \n");
+    assert_eq!(buf.protected_regions(), vec![(Point::new(1, 1), Point::new(1, 3))]);
+    // and leave the undo history as it was
+    buf.undo().unwrap();
+    assert_eq!(buf.to_str(), "ab  \ncd  \nef  \n");
+    assert_eq!(buf.undo(), Err(BufErr::NothingToUndo));
+
+    let mut buf = Buffer::with_contents("  a\n  b\n");
+    buf.protect_region(&Point::new(1, 1), &Point::new(1, 2)).unwrap();
+    assert_eq!(buf.indent_rows(0, 1, false), Err(BufErr::ReadOnly));
+    assert_eq!(buf.to_str(), "  a\n  b\n");
+}
+
+fn props(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+#[test]
+fn test_text_properties1() {
+    let mut buf = Buffer::with_contents("let x = 1;\nlet y = x;\n");
+    let error = props(&[("kind", "error"), ("message", "unused")]);
+    let id1 = buf.add_property(&Point::new(0, 4), &Point::new(1, 3), error.clone()).unwrap();
+    let id2 = buf.add_property(&Point::new(1, 10), &Point::new(1, 10),
+                               props(&[("hint", ": i32")])).unwrap();
+    assert!(id1 != id2);
+    assert_eq!(buf.add_property(&Point::new(5, 0), &Point::new(5, 1), props(&[])),
+               Err(BufErr::InvalidStartPoint));
+
+    buf.insert_at_pt("mut ", &Point::new(0, 4)).unwrap();
+    buf.insert_at_pt("\n", &Point::new(0, 0)).unwrap();
+    assert_eq!(buf.properties_on_line(1), vec![LineProperty {
+        id: id1,
+        start: 4,
+        end: 14,
+        properties: error.clone()
+    }]);
+    let lines = buf.get_lines(2, 3);
+    assert_eq!(lines[0].properties, vec![
+        LineProperty { id: id1, start: 0, end: 3, properties: error.clone() },
+        LineProperty { id: id2, start: 10, end: 10, properties: props(&[("hint", ": i32")]) }
+    ]);
+    assert_eq!(buf.get_lines(0, 1)[0].properties, vec![]);
+
+    // Deleting all of a property's text drops it, but empty ones stay
+    buf.delete_region(&Point::new(1, 0), &Point::new(2, 3)).unwrap();
+    buf.delete_region(&Point::new(1, 5), &Point::new(1, 7)).unwrap();
+    assert_eq!(buf.properties(), vec![TextProperty {
+        id: id2,
+        start: Point::new(1, 5),
+        end: Point::new(1, 5),
+        properties: props(&[("hint", ": i32")])
+    }]);
+    assert!(buf.remove_property(id2).is_ok());
+    assert_eq!(buf.remove_property(id2), Err(BufErr::NoProperty));
+    assert_eq!(buf.properties(), vec![]);
+}
+
+fn assert_snapshot_matches(snapshot: &Snapshot, buf: &mut Buffer) {
+    assert_eq!(snapshot.len(), buf.lines.len());
+    assert_eq!(snapshot.to_str(), buf.to_str());
+    assert_eq!(snapshot.get_lines(0, buf.lines.len()), buf.get_lines(0, buf.lines.len()));
+}
+
+#[test]
+fn test_snapshot1() {
+    let contents = (0..200).map(|i| format!("line {}\n", i)).collect::<String>();
+    let mut buf = Buffer::with_contents(&contents);
+    let before = buf.snapshot();
+    let copy = before.clone();
+
+    buf.insert_at_pt("new\nrows\n", &Point::new(10, 2)).unwrap();
+    buf.delete_region(&Point::new(60, 3), &Point::new(130, 1)).unwrap();
+    buf.add_property(&Point::new(61, 0), &Point::new(62, 2), props(&[("link", "x")])).unwrap();
+    buf.insert_at_pt("end", &Point::new(buf.lines.len(), 0)).unwrap();
+    let after = buf.snapshot();
+    assert_snapshot_matches(&after, &mut buf);
+    assert_eq!(after.get_lines(61, 62)[0].properties.len(), 1);
+
+    buf.delete_region(&Point::new(100, 0), &Point::new(buf.lines.len(), 0)).unwrap();
+    buf.undo().unwrap();
+    buf.undo().unwrap();
+    buf.delete_region(&Point::new(0, 0), &Point::new(50, 0)).unwrap();
+    assert_snapshot_matches(&buf.snapshot(), &mut buf);
+    while buf.undo().is_ok() {}
+    assert_snapshot_matches(&buf.snapshot(), &mut buf);
+
+    let mut small = Buffer::with_contents("a\nb\n");
+    small.snapshot();
+    small.delete_region(&Point::new(0, 0), &Point::new(2, 0)).unwrap();
+    assert_snapshot_matches(&small.snapshot(), &mut small);
+    small.insert_at_pt("c", &Point::new(0, 0)).unwrap();
+    small.insert_at_pt("d", &Point::new(1, 0)).unwrap();
+    assert_snapshot_matches(&small.snapshot(), &mut small);
+
+    // Earlier snapshots aren't changed by the edits, and can be read from
+    // another thread
+    let handle = std::thread::spawn(move || (copy.to_str(), copy.line(10).to_vec()));
+    let (string, line) = handle.join().unwrap();
+    assert_eq!(string, contents);
+    assert_eq!(line, "line 10".chars().collect::<Vec<_>>());
+    assert_eq!(before.to_str(), contents);
+    assert_eq!(after.line(11), &['r', 'o', 'w', 's']);
+}
+
+#[test]
+fn test_observe_changes1() {
+    let mut buf = Buffer::with_contents("abc\ndef\n");
+    let changes = buf.observe();
+    let dropped = buf.observe();
+    drop(dropped);
+    buf.replace_region(&Point::new(0, 1), &Point::new(1, 1), "X\nY").unwrap();
+    buf.undo().unwrap();
+    assert_eq!(buf.version(), 4);
+    assert_eq!(buf.snapshot().version(), 4);
+    let events = changes.try_iter().collect::<Vec<_>>();
+    assert_eq!(events, vec![
+        ChangeEvent {
+            start: Point::new(0, 1),
+            old_end: Point::new(1, 1),
+            new_end: Point::new(0, 1),
+            old_text: "bc\nd".to_string(),
+            new_text: "".to_string(),
+            version: 1
+        },
+        ChangeEvent {
+            start: Point::new(0, 1),
+            old_end: Point::new(0, 1),
+            new_end: Point::new(1, 1),
+            old_text: "".to_string(),
+            new_text: "X\nY".to_string(),
+            version: 2
+        },
+        ChangeEvent {
+            start: Point::new(0, 1),
+            old_end: Point::new(1, 1),
+            new_end: Point::new(0, 1),
+            old_text: "X\nY".to_string(),
+            new_text: "".to_string(),
+            version: 3
+        },
+        ChangeEvent {
+            start: Point::new(0, 1),
+            old_end: Point::new(0, 1),
+            new_end: Point::new(1, 1),
+            old_text: "".to_string(),
+            new_text: "bc\nd".to_string(),
+            version: 4
+        }
+    ]);
+    assert_eq!(buf.to_str(), "abc\ndef\n");
+}
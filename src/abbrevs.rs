@@ -0,0 +1,91 @@
+extern crate serde_json;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+
+// The table whose abbreviations apply whatever the language
+const GLOBAL: &str = "*";
+
+#[derive(PartialEq, Debug)]
+pub enum AbbrevErr {
+    Io(String)
+}
+
+impl fmt::Display for AbbrevErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AbbrevErr::Io(ref err) => { write!(f, "i/o error: {}", err) }
+        }
+    }
+}
+
+/// Abbreviations and what they expand to, keyed by the name of the grammar
+/// they're for, or "*" for every grammar.
+pub struct Abbreviations {
+    tables: BTreeMap<String, BTreeMap<String, String>>
+}
+
+impl Abbreviations {
+    pub fn new() -> Abbreviations {
+        Abbreviations {
+            tables: BTreeMap::new()
+        }
+    }
+
+    /// Sets what `trigger` expands to in `language`, or in every language if
+    /// it's None. An expansion of None removes the abbreviation.
+    pub fn set(&mut self, language: Option<&str>, trigger: &str, expansion: Option<&str>) {
+        let language = language.unwrap_or(GLOBAL).to_string();
+        match expansion {
+            Some(expansion) => {
+                self.tables.entry(language).or_default()
+                    .insert(trigger.to_string(), expansion.to_string());
+            }
+            None => {
+                let now_empty = match self.tables.get_mut(&language) {
+                    Some(table) => {
+                        table.remove(trigger);
+                        table.is_empty()
+                    }
+                    None => false
+                };
+                if now_empty {
+                    self.tables.remove(&language);
+                }
+            }
+        }
+    }
+
+    /// Returns the abbreviations that apply in `language`. Ones for the
+    /// language override those for every language.
+    pub fn table_for(&self, language: Option<&str>) -> HashMap<String, String> {
+        let mut table = HashMap::new();
+        let languages = Some(GLOBAL).into_iter().chain(language);
+        for language in languages {
+            if let Some(abbreviations) = self.tables.get(language) {
+                table.extend(abbreviations.iter().map(|(k, v)| (k.clone(), v.clone())));
+            }
+        }
+        table
+    }
+
+    /// Reads tables from `path`, a JSON object mapping grammar names (or "*")
+    /// to objects mapping abbreviations to expansions. Abbreviations already
+    /// defined are replaced. Returns how many were read.
+    pub fn load(&mut self, path: &str) -> Result<usize, AbbrevErr> {
+        let mut json = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut json))
+            .map_err(|err| AbbrevErr::Io(err.to_string()))?;
+        let tables: BTreeMap<String, BTreeMap<String, String>> = serde_json::from_str(&json)
+            .map_err(|err| AbbrevErr::Io(err.to_string()))?;
+        let mut n = 0;
+        for (language, abbreviations) in tables {
+            n += abbreviations.len();
+            self.tables.entry(language).or_default().extend(abbreviations);
+        }
+        Ok(n)
+    }
+}
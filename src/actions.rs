@@ -2,12 +2,40 @@ extern crate serde;
 extern crate serde_json;
 extern crate buffer;
 
+use abbrevs::AbbrevErr;
 use editor::Editor;
+use files::{read_file, set_file, write_file, ReloadStrategy};
+use grammars::GrammarErr;
+use indexed::IndexedFile;
+use macros::{is_recordable, Macro, MacroErr};
+use registers::{parse_register, RegErr};
 use serde_json::{Value};
+use self::serde::Deserialize;
 use self::serde::ser::{Serializer, Serialize, SerializeMap};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs;
 use std::sync::{Arc, Mutex};
 use std::fmt;
-use buffer::{Point, Line, BufErr};
+use buffer::{Buffer, Point, Line, BufErr, Selection, SelectionSet, Motion, Options, TextObject,
+             Operator, OpResult, Target, Grammar, Node, NodeKind, Direction, FoldingRange,
+             DisplayLine, SortKind, Case, TransposeUnit, Completions, TabStop, Change,
+             LazyBuffer, TextProperty};
+
+// Number of completion candidates returned when the client doesn't say
+const DEFAULT_COMPLETIONS: usize = 20;
+// Unchanged rows shown around each hunk of a diff, as diff -u does
+const DEFAULT_DIFF_CONTEXT: usize = 3;
+// Files bigger than this are opened lazily unless the client says otherwise
+const LAZY_THRESHOLD: u64 = 8 * 1024 * 1024;
+// Methods that work on a lazily opened file without reading all of it
+const LAZY_METHODS: [&'static str; 5] = [
+    "connect",
+    "getLines",
+    "insertAtPt",
+    "deleteRegion",
+    "openFile"
+];
 
 #[derive(Deserialize, Debug)]
 pub enum Method {
@@ -16,67 +44,2083 @@ pub enum Method {
     #[serde(rename = "insertAtPt")]
     InsertAtPt,
     #[serde(rename = "getLines")]
-    GetLines
+    GetLines,
+    #[serde(rename = "setSelections")]
+    SetSelections,
+    #[serde(rename = "getSelections")]
+    GetSelections,
+    #[serde(rename = "insertAtSelections")]
+    InsertAtSelections,
+    #[serde(rename = "deleteSelections")]
+    DeleteSelections,
+    #[serde(rename = "undo")]
+    Undo,
+    #[serde(rename = "getBlock")]
+    GetBlock,
+    #[serde(rename = "deleteBlock")]
+    DeleteBlock,
+    #[serde(rename = "insertBlock")]
+    InsertBlock,
+    #[serde(rename = "insertAtBlock")]
+    InsertAtBlock,
+    #[serde(rename = "move")]
+    Move,
+    #[serde(rename = "getOptions")]
+    GetOptions,
+    #[serde(rename = "setOptions")]
+    SetOptions,
+    #[serde(rename = "textObject")]
+    TextObject,
+    #[serde(rename = "operator")]
+    Operator,
+    #[serde(rename = "deleteRegion")]
+    DeleteRegion,
+    #[serde(rename = "yank")]
+    Yank,
+    #[serde(rename = "paste")]
+    Paste,
+    #[serde(rename = "pastePop")]
+    PastePop,
+    #[serde(rename = "getRegisters")]
+    GetRegisters,
+    #[serde(rename = "startRecording")]
+    StartRecording,
+    #[serde(rename = "stopRecording")]
+    StopRecording,
+    #[serde(rename = "playMacro")]
+    PlayMacro,
+    #[serde(rename = "getMacros")]
+    GetMacros,
+    #[serde(rename = "saveMacros")]
+    SaveMacros,
+    #[serde(rename = "loadMacros")]
+    LoadMacros,
+    #[serde(rename = "setGrammar")]
+    SetGrammar,
+    #[serde(rename = "loadGrammar")]
+    LoadGrammar,
+    #[serde(rename = "syntaxNode")]
+    SyntaxNode,
+    #[serde(rename = "navigateSyntax")]
+    NavigateSyntax,
+    #[serde(rename = "expandSelection")]
+    ExpandSelection,
+    #[serde(rename = "shrinkSelection")]
+    ShrinkSelection,
+    #[serde(rename = "querySyntax")]
+    QuerySyntax,
+    #[serde(rename = "getFoldingRanges")]
+    GetFoldingRanges,
+    #[serde(rename = "newline")]
+    Newline,
+    #[serde(rename = "reindentRegion")]
+    ReindentRegion,
+    #[serde(rename = "getVisualCol")]
+    GetVisualCol,
+    #[serde(rename = "pointAtVisualCol")]
+    PointAtVisualCol,
+    #[serde(rename = "getDisplayLines")]
+    GetDisplayLines,
+    #[serde(rename = "pointToDisplay")]
+    PointToDisplay,
+    #[serde(rename = "displayToPoint")]
+    DisplayToPoint,
+    #[serde(rename = "joinLines")]
+    JoinLines,
+    #[serde(rename = "duplicateLines")]
+    DuplicateLines,
+    #[serde(rename = "duplicateRegion")]
+    DuplicateRegion,
+    #[serde(rename = "moveLines")]
+    MoveLines,
+    #[serde(rename = "sortLines")]
+    SortLines,
+    #[serde(rename = "uniqueLines")]
+    UniqueLines,
+    #[serde(rename = "reverseLines")]
+    ReverseLines,
+    #[serde(rename = "trimTrailingWhitespace")]
+    TrimTrailingWhitespace,
+    #[serde(rename = "changeCase")]
+    ChangeCase,
+    #[serde(rename = "transpose")]
+    Transpose,
+    #[serde(rename = "toggleLineComment")]
+    ToggleLineComment,
+    #[serde(rename = "toggleBlockComment")]
+    ToggleBlockComment,
+    #[serde(rename = "getCompletions")]
+    GetCompletions,
+    #[serde(rename = "insertSnippet")]
+    InsertSnippet,
+    #[serde(rename = "nextTabStop")]
+    NextTabStop,
+    #[serde(rename = "prevTabStop")]
+    PrevTabStop,
+    #[serde(rename = "cancelSnippet")]
+    CancelSnippet,
+    #[serde(rename = "loadAbbreviations")]
+    LoadAbbreviations,
+    #[serde(rename = "setAbbreviation")]
+    SetAbbreviation,
+    #[serde(rename = "getAbbreviations")]
+    GetAbbreviations,
+    #[serde(rename = "diffFile")]
+    DiffFile,
+    #[serde(rename = "applyPatch")]
+    ApplyPatch,
+    #[serde(rename = "openFile")]
+    OpenFile,
+    #[serde(rename = "saveFile")]
+    SaveFile,
+    #[serde(rename = "reloadFile")]
+    ReloadFile,
+    #[serde(rename = "protectRegion")]
+    ProtectRegion,
+    #[serde(rename = "unprotectRegion")]
+    UnprotectRegion,
+    #[serde(rename = "getProtectedRegions")]
+    GetProtectedRegions,
+    #[serde(rename = "addProperty")]
+    AddProperty,
+    #[serde(rename = "removeProperty")]
+    RemoveProperty,
+    #[serde(rename = "getProperties")]
+    GetProperties,
+    #[serde(rename = "watchChanges")]
+    WatchChanges
 }
 
 /* === Requests === */
 
-#[derive(Deserialize, Debug)]
-pub struct ConnectReq {
-    #[serde(rename = "clientId")]
-    pub client_id: String,
-    pub method: Method
+#[derive(Deserialize, Debug)]
+pub struct ConnectReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InsertAtPtReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    pub string: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetLinesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    // Rows to return, from start up to but not including end. Defaults to
+    // the whole buffer.
+    pub start: Option<usize>,
+    pub end: Option<usize>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetSelectionsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub selections: Vec<Selection>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetSelectionsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InsertAtSelectionsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub string: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeleteSelectionsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UndoReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetBlockReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeleteBlockReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InsertBlockReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    pub strings: Vec<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InsertAtBlockReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point,
+    pub string: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MoveReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    pub motion: Motion,
+    pub count: Option<usize>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetOptionsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetOptionsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub options: Options
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetGrammarReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    // Either the name of a builtin or loaded grammar or a grammar of the
+    // client's own. Highlighting is turned off if neither is given.
+    pub name: Option<String>,
+    pub grammar: Option<Grammar>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoadGrammarReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    /// A TextMate grammar in JSON
+    pub path: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SyntaxNodeReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NavigateSyntaxReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point,
+    pub direction: Direction
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExpandSelectionReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ShrinkSelectionReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QuerySyntaxReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    // Every kind of node if not given
+    pub kind: Option<NodeKind>,
+    // Nodes in any scope, or none, if not given
+    pub scope: Option<String>,
+    // The whole buffer if not given
+    pub start: Option<Point>,
+    pub end: Option<Point>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetFoldingRangesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewlineReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReindentRegionReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetVisualColReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PointAtVisualColReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub row: usize,
+    pub col: usize
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetDisplayLinesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub width: usize,
+    // Display rows to return, from start up to but not including end.
+    // Defaults to all of them.
+    pub start: Option<usize>,
+    pub end: Option<usize>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PointToDisplayReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    pub width: usize
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DisplayToPointReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub row: usize,
+    pub col: usize,
+    pub width: usize
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JoinLinesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DuplicateLinesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DuplicateRegionReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UniqueLinesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReverseLinesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TrimTrailingWhitespaceReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MoveLinesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point,
+    // Rows to move down by, or up if negative
+    pub by: isize
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SortLinesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point,
+    // Defaults to lexical
+    pub kind: Option<SortKind>,
+    #[serde(default)]
+    pub reverse: bool
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangeCaseReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point,
+    pub case: Case
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransposeReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    pub unit: TransposeUnit
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ToggleLineCommentReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ToggleBlockCommentReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetCompletionsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    // Most candidates to return. Defaults to DEFAULT_COMPLETIONS.
+    pub limit: Option<usize>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InsertSnippetReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    pub snippet: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NextTabStopReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PrevTabStopReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CancelSnippetReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoadAbbreviationsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub path: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetAbbreviationReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    /// The grammar the abbreviation is for. Defaults to every grammar.
+    pub language: Option<String>,
+    pub trigger: String,
+    /// Removes the abbreviation if missing
+    pub expansion: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetAbbreviationsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    /// Defaults to the buffer's grammar
+    pub language: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DiffFileReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    /// Defaults to the buffer's file
+    pub path: Option<String>,
+    /// Unchanged rows around each hunk. Defaults to 3.
+    pub context: Option<usize>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApplyPatchReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub patch: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OpenFileReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub path: String,
+    /// Whether to map the file into memory and only read rows as they're
+    /// needed. Defaults to whether the file is bigger than 8 MiB. Requests
+    /// that need the whole file fail if it changes on disk before then,
+    /// until it's opened again.
+    pub lazy: Option<bool>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SaveFileReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    /// Saves to here and makes it the buffer's file instead. Defaults to the
+    /// buffer's file.
+    pub path: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReloadFileReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub strategy: ReloadStrategy
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ProtectRegionReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UnprotectRegionReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetProtectedRegionsReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AddPropertyReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point,
+    pub properties: BTreeMap<String, String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RemovePropertyReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub id: usize
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetPropertiesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WatchChangesReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    /// Whether to start or stop sending the client a bufferChanged
+    /// notification for every change made to the buffer
+    pub watch: bool
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TextObjectReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    pub object: TextObject,
+    pub inner: bool
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OperatorReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    pub operator: Operator,
+    pub target: Target,
+    pub register: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeleteRegionReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point,
+    pub register: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct YankReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub start: Point,
+    pub end: Point,
+    pub register: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasteReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point,
+    pub register: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PastePopReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetRegistersReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StartRecordingReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub name: String,
+    pub point: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StopRecordingReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub point: Point
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlayMacroReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub name: String,
+    pub point: Point,
+    pub count: Option<usize>,
+    /// Plays the macro once on every line from `point` to here instead
+    pub end: Option<Point>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetMacrosReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SaveMacrosReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub path: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoadMacrosReq {
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    pub method: Method,
+    pub path: String
+}
+
+pub trait Req {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp;
+}
+
+/// A request that edits the buffer or moves selections without needing the
+/// editor unlocked, so that a macro can replay several under one lock.
+pub trait EditReq {
+    fn exec(&self, ed: &mut Editor) -> Resp;
+}
+
+/// Executes the request in `input`, recording it if its client is recording
+/// a macro.
+pub fn dispatch(input: Value, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+    let recording = match (input["method"].as_str(), input["clientId"].as_str()) {
+        (Some(method), Some(client_id)) if is_recordable(method) => {
+            if editor.lock().unwrap().macros.is_recording(client_id) {
+                Some((client_id.to_string(), input.clone()))
+            } else {
+                None
+            }
+        }
+        _ => None
+    };
+    if !input["method"].as_str().map_or(false, |method| LAZY_METHODS.contains(&method)) {
+        if let Err(err) = editor.lock().unwrap().load_lazy() {
+            return Resp(Err(RespErr::LoadErr(err)));
+        }
+    }
+    let resp = exec_method(input, editor);
+    if let Some((client_id, input)) = recording {
+        if resp.0.is_ok() {
+            editor.lock().unwrap().macros.record(&client_id, input);
+        }
+    }
+    resp
+}
+
+fn exec_method(input: Value, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+    match input["method"].as_str() {
+        Some("connect") => exec_value::<ConnectReq>(input, editor),
+        Some("getLines") => exec_value::<GetLinesReq>(input, editor),
+        Some("getSelections") => exec_value::<GetSelectionsReq>(input, editor),
+        Some("getBlock") => exec_value::<GetBlockReq>(input, editor),
+        Some("getOptions") => exec_value::<GetOptionsReq>(input, editor),
+        Some("setOptions") => exec_value::<SetOptionsReq>(input, editor),
+        Some("setGrammar") => exec_value::<SetGrammarReq>(input, editor),
+        Some("loadGrammar") => exec_value::<LoadGrammarReq>(input, editor),
+        Some("syntaxNode") => exec_value::<SyntaxNodeReq>(input, editor),
+        Some("navigateSyntax") => exec_value::<NavigateSyntaxReq>(input, editor),
+        Some("expandSelection") => exec_value::<ExpandSelectionReq>(input, editor),
+        Some("shrinkSelection") => exec_value::<ShrinkSelectionReq>(input, editor),
+        Some("querySyntax") => exec_value::<QuerySyntaxReq>(input, editor),
+        Some("getFoldingRanges") => exec_value::<GetFoldingRangesReq>(input, editor),
+        Some("getVisualCol") => exec_value::<GetVisualColReq>(input, editor),
+        Some("pointAtVisualCol") => exec_value::<PointAtVisualColReq>(input, editor),
+        Some("getDisplayLines") => exec_value::<GetDisplayLinesReq>(input, editor),
+        Some("pointToDisplay") => exec_value::<PointToDisplayReq>(input, editor),
+        Some("displayToPoint") => exec_value::<DisplayToPointReq>(input, editor),
+        Some("getCompletions") => exec_value::<GetCompletionsReq>(input, editor),
+        Some("loadAbbreviations") => exec_value::<LoadAbbreviationsReq>(input, editor),
+        Some("setAbbreviation") => exec_value::<SetAbbreviationReq>(input, editor),
+        Some("getAbbreviations") => exec_value::<GetAbbreviationsReq>(input, editor),
+        Some("diffFile") => exec_value::<DiffFileReq>(input, editor),
+        Some("openFile") => exec_value::<OpenFileReq>(input, editor),
+        Some("saveFile") => exec_value::<SaveFileReq>(input, editor),
+        Some("reloadFile") => exec_value::<ReloadFileReq>(input, editor),
+        Some("getProtectedRegions") => exec_value::<GetProtectedRegionsReq>(input, editor),
+        Some("getProperties") => exec_value::<GetPropertiesReq>(input, editor),
+        Some("watchChanges") => exec_value::<WatchChangesReq>(input, editor),
+        Some("textObject") => exec_value::<TextObjectReq>(input, editor),
+        Some("getRegisters") => exec_value::<GetRegistersReq>(input, editor),
+        Some("startRecording") => exec_value::<StartRecordingReq>(input, editor),
+        Some("stopRecording") => exec_value::<StopRecordingReq>(input, editor),
+        Some("playMacro") => exec_value::<PlayMacroReq>(input, editor),
+        Some("getMacros") => exec_value::<GetMacrosReq>(input, editor),
+        Some("saveMacros") => exec_value::<SaveMacrosReq>(input, editor),
+        Some("loadMacros") => exec_value::<LoadMacrosReq>(input, editor),
+        _ => exec_edit_method(input, &mut editor.lock().unwrap())
+    }
+}
+
+/// Executes the request in `input` against the locked editor, if it's one
+/// that edits the buffer or moves selections.
+fn exec_edit_method(input: Value, ed: &mut Editor) -> Resp {
+    match input["method"].as_str() {
+        Some("insertAtPt") => exec_edit_value::<InsertAtPtReq>(input, ed),
+        Some("deleteRegion") => exec_edit_value::<DeleteRegionReq>(input, ed),
+        Some("setSelections") => exec_edit_value::<SetSelectionsReq>(input, ed),
+        Some("insertAtSelections") => exec_edit_value::<InsertAtSelectionsReq>(input, ed),
+        Some("deleteSelections") => exec_edit_value::<DeleteSelectionsReq>(input, ed),
+        Some("deleteBlock") => exec_edit_value::<DeleteBlockReq>(input, ed),
+        Some("insertBlock") => exec_edit_value::<InsertBlockReq>(input, ed),
+        Some("insertAtBlock") => exec_edit_value::<InsertAtBlockReq>(input, ed),
+        Some("move") => exec_edit_value::<MoveReq>(input, ed),
+        Some("operator") => exec_edit_value::<OperatorReq>(input, ed),
+        Some("yank") => exec_edit_value::<YankReq>(input, ed),
+        Some("paste") => exec_edit_value::<PasteReq>(input, ed),
+        Some("pastePop") => exec_edit_value::<PastePopReq>(input, ed),
+        Some("undo") => exec_edit_value::<UndoReq>(input, ed),
+        Some("newline") => exec_edit_value::<NewlineReq>(input, ed),
+        Some("reindentRegion") => exec_edit_value::<ReindentRegionReq>(input, ed),
+        Some("joinLines") => exec_edit_value::<JoinLinesReq>(input, ed),
+        Some("duplicateLines") => exec_edit_value::<DuplicateLinesReq>(input, ed),
+        Some("duplicateRegion") => exec_edit_value::<DuplicateRegionReq>(input, ed),
+        Some("moveLines") => exec_edit_value::<MoveLinesReq>(input, ed),
+        Some("sortLines") => exec_edit_value::<SortLinesReq>(input, ed),
+        Some("uniqueLines") => exec_edit_value::<UniqueLinesReq>(input, ed),
+        Some("reverseLines") => exec_edit_value::<ReverseLinesReq>(input, ed),
+        Some("trimTrailingWhitespace") => exec_edit_value::<TrimTrailingWhitespaceReq>(input, ed),
+        Some("changeCase") => exec_edit_value::<ChangeCaseReq>(input, ed),
+        Some("transpose") => exec_edit_value::<TransposeReq>(input, ed),
+        Some("toggleLineComment") => exec_edit_value::<ToggleLineCommentReq>(input, ed),
+        Some("toggleBlockComment") => exec_edit_value::<ToggleBlockCommentReq>(input, ed),
+        Some("insertSnippet") => exec_edit_value::<InsertSnippetReq>(input, ed),
+        Some("nextTabStop") => exec_edit_value::<NextTabStopReq>(input, ed),
+        Some("prevTabStop") => exec_edit_value::<PrevTabStopReq>(input, ed),
+        Some("cancelSnippet") => exec_edit_value::<CancelSnippetReq>(input, ed),
+        Some("applyPatch") => exec_edit_value::<ApplyPatchReq>(input, ed),
+        Some("protectRegion") => exec_edit_value::<ProtectRegionReq>(input, ed),
+        Some("unprotectRegion") => exec_edit_value::<UnprotectRegionReq>(input, ed),
+        Some("addProperty") => exec_edit_value::<AddPropertyReq>(input, ed),
+        Some("removeProperty") => exec_edit_value::<RemovePropertyReq>(input, ed),
+        Some(&_) => {
+            warn!("Invalid method: {}", input);
+            Resp(Err(RespErr::InvalidMethod))
+        }
+        None => {
+            error!("Missing method: {}", input);
+            Resp(Err(RespErr::MissingMethod))
+        }
+    }
+}
+
+/// Deserializes `input` as a `T` and executes it.
+pub fn exec_value<T>(input: Value, editor: &mut Arc<Mutex<Editor>>) -> Resp
+    where T: Req + Deserialize {
+    match serde_json::from_value::<T>(input) {
+        Ok(req) => {
+            editor.lock().unwrap().command += 1;
+            req.exec(editor)
+        }
+        Err(_) => Resp(Err(RespErr::DeserializationError))
+    }
+}
+
+/// Deserializes `input` as a `T` and executes it against the locked editor.
+pub fn exec_edit_value<T>(input: Value, ed: &mut Editor) -> Resp
+    where T: EditReq + Deserialize {
+    match serde_json::from_value::<T>(input) {
+        Ok(req) => {
+            ed.command += 1;
+            req.exec(ed)
+        }
+        Err(_) => Resp(Err(RespErr::DeserializationError))
+    }
+}
+
+impl Req for ConnectReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for ConnectReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        match ed.client_id {
+            Some(_) => {
+                Resp(Err(RespErr::ClientAlreadyConnected))
+            }
+            None => {
+                ed.client_id = Some(self.client_id.clone());
+                Resp(Ok(RespOk::ConnectResp(ConnRespStruct {
+                    server_id: ed.server_id.to_string()
+                })))
+            }
+        }
+    }
+}
+
+impl EditReq for InsertAtPtReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for InsertAtPtReq {:?}", self);
+        let result = match ed.lazy {
+            Some(ref mut lazy) => lazy.insert_typed_expanding(&self.string, &self.point),
+            None => ed.buffer.insert_typed_expanding(&self.string, &self.point)
+        };
+        ed.sync_buffer(None);
+        match result {
+            Ok(lines_changed) => {
+                Resp(Ok(RespOk::InsertAtPtOk(lines_changed)))
+            }
+            Err(err) => {
+                Resp(Err(RespErr::InsertAtPtErr(err)))
+            }
+        }
+    }
+}
+
+impl EditReq for SetSelectionsReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for SetSelectionsReq {:?}", self);
+        for sel in &self.selections {
+            if !ed.buffer.is_valid_point(&sel.anchor) || !ed.buffer.is_valid_point(&sel.head) {
+                return Resp(Err(RespErr::SelectionErr(BufErr::InvalidPoint)));
+            }
+        }
+        let sels = SelectionSet::new(self.selections.clone());
+        let selections = sels.selections.clone();
+        ed.selections.insert(self.client_id.clone(), sels);
+        Resp(Ok(RespOk::SelectionsOk(selections)))
+    }
+}
+
+impl Req for GetSelectionsReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetSelectionsReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        let selections = match ed.selections.get(&self.client_id) {
+            Some(sels) => sels.selections.clone(),
+            None => vec![]
+        };
+        Resp(Ok(RespOk::SelectionsOk(selections)))
+    }
+}
+
+/// Runs `f` on the buffer and `client_id`'s selections, then shifts every
+/// other client's selections to match.
+fn edit_selections<F>(ed: &mut Editor, client_id: &str, f: F) -> Resp
+    where F: FnOnce(&mut Buffer, &mut SelectionSet) -> Result<Vec<Line>, BufErr> {
+    let mut sels = ed.selections.remove(client_id).unwrap_or_default();
+    let result = f(&mut ed.buffer, &mut sels);
+    let selections = sels.selections.clone();
+    ed.selections.insert(client_id.to_string(), sels);
+    ed.sync_buffer(Some(client_id));
+    match result {
+        Ok(lines) => {
+            Resp(Ok(RespOk::SelectionEditOk(SelEditRespStruct {
+                lines: lines,
+                selections: selections
+            })))
+        }
+        Err(err) => {
+            Resp(Err(RespErr::SelectionErr(err)))
+        }
+    }
+}
+
+impl EditReq for InsertAtSelectionsReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for InsertAtSelectionsReq {:?}", self);
+        edit_selections(ed, &self.client_id, |buffer, sels| {
+            buffer.insert_at_selections_expanding(&self.string, sels)
+        })
+    }
+}
+
+impl EditReq for DeleteSelectionsReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for DeleteSelectionsReq {:?}", self);
+        edit_selections(ed, &self.client_id, |buffer, sels| buffer.delete_selections(sels))
+    }
+}
+
+/// Runs `f` on the buffer and shifts every client's selections to match,
+/// wrapping any error with `err`.
+fn edit_buffer<F, E>(ed: &mut Editor, f: F, err: E) -> Resp
+    where F: FnOnce(&mut Buffer) -> Result<Vec<Line>, BufErr>, E: FnOnce(BufErr) -> RespErr {
+    let result = f(&mut ed.buffer);
+    ed.sync_buffer(None);
+    match result {
+        Ok(lines_changed) => Resp(Ok(RespOk::LinesOk(lines_changed))),
+        Err(buf_err) => Resp(Err(err(buf_err)))
+    }
+}
+
+impl EditReq for UndoReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for UndoReq {:?}", self);
+        edit_buffer(ed, |buffer| buffer.undo(), RespErr::UndoErr)
+    }
+}
+
+impl Req for GetBlockReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetBlockReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        match ed.buffer.block_to_strs(&self.start, &self.end) {
+            Ok(strings) => Resp(Ok(RespOk::BlockOk(strings))),
+            Err(err) => Resp(Err(RespErr::BlockErr(err)))
+        }
+    }
+}
+
+impl EditReq for DeleteBlockReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for DeleteBlockReq {:?}", self);
+        edit_buffer(ed, |buffer| buffer.delete_block(&self.start, &self.end),
+                    RespErr::BlockErr)
+    }
+}
+
+impl EditReq for InsertBlockReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for InsertBlockReq {:?}", self);
+        edit_buffer(ed, |buffer| buffer.insert_block(&self.strings, &self.point),
+                    RespErr::BlockErr)
+    }
+}
+
+impl EditReq for InsertAtBlockReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for InsertAtBlockReq {:?}", self);
+        edit_buffer(ed, |buffer| buffer.insert_at_block(&self.string, &self.start, &self.end),
+                    RespErr::BlockErr)
+    }
+}
+
+impl EditReq for MoveReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for MoveReq {:?}", self);
+        match ed.buffer.move_point(&self.point, self.motion, self.count.unwrap_or(1)) {
+            Ok(pt) => Resp(Ok(RespOk::PointOk(pt))),
+            Err(err) => Resp(Err(RespErr::MoveErr(err)))
+        }
+    }
+}
+
+impl Req for GetOptionsReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetOptionsReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        Resp(Ok(RespOk::OptionsOk(ed.buffer.options.clone())))
+    }
+}
+
+impl Req for SetOptionsReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for SetOptionsReq {:?}", self);
+        if let Err(err) = self.options.validate() {
+            return Resp(Err(RespErr::OptionsErr(err)));
+        }
+        let mut ed = editor.lock().unwrap();
+        ed.buffer.options = self.options.clone();
+        Resp(Ok(RespOk::Ok))
+    }
+}
+
+impl Req for GetLinesReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetLinesReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        let start = self.start.unwrap_or(0);
+        if let Some(ref lazy) = ed.lazy {
+            let end = self.end.unwrap_or(lazy.len());
+            return Resp(Ok(RespOk::LinesOk(lazy.get_lines(start, end))));
+        }
+        let end = self.end.unwrap_or(ed.buffer.lines.len());
+        Resp(Ok(RespOk::LinesOk(ed.buffer.get_lines(start, end))))
+    }
+}
+
+impl Req for SetGrammarReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for SetGrammarReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        let grammar = match (&self.name, &self.grammar) {
+            (&Some(ref name), _) => match ed.grammars.get(name) {
+                Some(grammar) => Some(grammar),
+                None => return Resp(Err(RespErr::UnknownGrammar))
+            },
+            (&None, grammar) => grammar.clone()
+        };
+        ed.buffer.set_grammar(grammar);
+        ed.update_abbreviations();
+        Resp(Ok(RespOk::Ok))
+    }
+}
+
+impl Req for LoadGrammarReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for LoadGrammarReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        match ed.grammars.load(&self.path) {
+            Ok(name) => Resp(Ok(RespOk::GrammarOk(name))),
+            Err(err) => Resp(Err(RespErr::GrammarErr(err)))
+        }
+    }
+}
+
+impl Req for SyntaxNodeReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for SyntaxNodeReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        match ed.buffer.node_at(&self.point) {
+            Ok(node) => Resp(Ok(RespOk::NodeOk(node))),
+            Err(err) => Resp(Err(RespErr::SyntaxErr(err)))
+        }
+    }
+}
+
+impl Req for NavigateSyntaxReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for NavigateSyntaxReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        match ed.buffer.navigate_syntax(&self.start, &self.end, self.direction) {
+            Ok(node) => Resp(Ok(RespOk::NodeOk(node))),
+            Err(err) => Resp(Err(RespErr::SyntaxErr(err)))
+        }
+    }
+}
+
+impl Req for ExpandSelectionReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for ExpandSelectionReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        match ed.buffer.expand_selection(&self.start, &self.end) {
+            Ok(node) => Resp(Ok(RespOk::NodeOk(node))),
+            Err(err) => Resp(Err(RespErr::SyntaxErr(err)))
+        }
+    }
+}
+
+impl Req for ShrinkSelectionReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for ShrinkSelectionReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        match ed.buffer.shrink_selection(&self.start, &self.end) {
+            Ok(node) => Resp(Ok(RespOk::NodeOk(node))),
+            Err(err) => Resp(Err(RespErr::SyntaxErr(err)))
+        }
+    }
+}
+
+impl Req for QuerySyntaxReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for QuerySyntaxReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        let start = self.start.unwrap_or(Point::new(0, 0));
+        let end = self.end.unwrap_or(Point::new(ed.buffer.lines.len(), 0));
+        let scope = self.scope.as_ref().map(|scope| &scope[..]);
+        match ed.buffer.query_syntax(self.kind, scope, &start, &end) {
+            Ok(nodes) => Resp(Ok(RespOk::NodesOk(nodes))),
+            Err(err) => Resp(Err(RespErr::SyntaxErr(err)))
+        }
+    }
+}
+
+impl Req for GetFoldingRangesReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetFoldingRangesReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        let ranges = ed.buffer.folding_ranges();
+        // The client is sent a foldingRanges notification whenever they change
+        // from now on
+        ed.folding_ranges.insert(self.client_id.clone(), ranges.clone());
+        Resp(Ok(RespOk::FoldingRangesOk(ranges)))
+    }
+}
+
+impl EditReq for NewlineReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for NewlineReq {:?}", self);
+        let result = ed.buffer.insert_newline(&self.point);
+        ed.sync_buffer(None);
+        match result {
+            Ok((lines, point)) => {
+                Resp(Ok(RespOk::NewlineOk(NewlineRespStruct {
+                    lines: lines,
+                    point: point
+                })))
+            }
+            Err(err) => Resp(Err(RespErr::IndentErr(err)))
+        }
+    }
+}
+
+impl EditReq for ReindentRegionReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for ReindentRegionReq {:?}", self);
+        edit_buffer(ed, |buffer| buffer.reindent_rows(self.start.row(), self.end.row()),
+                    RespErr::IndentErr)
+    }
+}
+
+impl Req for GetVisualColReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetVisualColReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        if !ed.buffer.is_valid_point(&self.point) {
+            return Resp(Err(RespErr::ColumnErr(BufErr::InvalidPoint)));
+        }
+        Resp(Ok(RespOk::ColOk(ed.buffer.visual_col_at(&self.point))))
+    }
+}
+
+impl Req for PointAtVisualColReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for PointAtVisualColReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        if self.row > ed.buffer.lines.len() {
+            return Resp(Err(RespErr::ColumnErr(BufErr::InvalidPoint)));
+        }
+        Resp(Ok(RespOk::PointOk(ed.buffer.point_at_visual_col(self.row, self.col))))
+    }
+}
+
+impl Req for GetDisplayLinesReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetDisplayLinesReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        let start = self.start.unwrap_or(0);
+        let end = self.end.unwrap_or(usize::MAX);
+        match ed.buffer.display_lines(self.width, start, end) {
+            Ok(lines) => Resp(Ok(RespOk::DisplayLinesOk(lines))),
+            Err(err) => Resp(Err(RespErr::WrapErr(err)))
+        }
+    }
+}
+
+impl Req for PointToDisplayReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for PointToDisplayReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        match ed.buffer.point_to_display(&self.point, self.width) {
+            Ok((row, col)) => {
+                Resp(Ok(RespOk::DisplayPointOk(DisplayPointRespStruct {
+                    row: row,
+                    col: col
+                })))
+            }
+            Err(err) => Resp(Err(RespErr::WrapErr(err)))
+        }
+    }
+}
+
+impl Req for DisplayToPointReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for DisplayToPointReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        match ed.buffer.display_to_point(self.row, self.col, self.width) {
+            Ok(pt) => Resp(Ok(RespOk::PointOk(pt))),
+            Err(err) => Resp(Err(RespErr::WrapErr(err)))
+        }
+    }
+}
+
+impl EditReq for JoinLinesReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for JoinLinesReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.join_lines(self.start.row(), self.end.row())
+        }, RespErr::LineErr)
+    }
+}
+
+impl EditReq for DuplicateLinesReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for DuplicateLinesReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.duplicate_lines(self.start.row(), self.end.row())
+        }, RespErr::LineErr)
+    }
+}
+
+impl EditReq for DuplicateRegionReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for DuplicateRegionReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.duplicate_region(&self.start, &self.end)
+        }, RespErr::LineErr)
+    }
+}
+
+impl EditReq for MoveLinesReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for MoveLinesReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.move_lines(self.start.row(), self.end.row(), self.by)
+        }, RespErr::LineErr)
+    }
+}
+
+impl EditReq for SortLinesReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for SortLinesReq {:?}", self);
+        let kind = self.kind.unwrap_or(SortKind::Lexical);
+        edit_buffer(ed, |buffer| {
+            buffer.sort_lines(self.start.row(), self.end.row(), kind, self.reverse)
+        }, RespErr::LineErr)
+    }
+}
+
+impl EditReq for UniqueLinesReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for UniqueLinesReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.unique_lines(self.start.row(), self.end.row())
+        }, RespErr::LineErr)
+    }
+}
+
+impl EditReq for ReverseLinesReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for ReverseLinesReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.reverse_lines(self.start.row(), self.end.row())
+        }, RespErr::LineErr)
+    }
+}
+
+impl EditReq for TrimTrailingWhitespaceReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for TrimTrailingWhitespaceReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.trim_trailing_whitespace(self.start.row(), self.end.row())
+        }, RespErr::LineErr)
+    }
+}
+
+impl EditReq for ChangeCaseReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for ChangeCaseReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.change_case(&self.start, &self.end, self.case)
+        }, RespErr::TransformErr)
+    }
+}
+
+impl EditReq for TransposeReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for TransposeReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.transpose(&self.point, self.unit)
+        }, RespErr::TransformErr)
+    }
+}
+
+impl EditReq for ToggleLineCommentReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for ToggleLineCommentReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.toggle_line_comment(self.start.row(), self.end.row())
+        }, RespErr::TransformErr)
+    }
+}
+
+impl EditReq for ToggleBlockCommentReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for ToggleBlockCommentReq {:?}", self);
+        edit_buffer(ed, |buffer| {
+            buffer.toggle_block_comment(&self.start, &self.end)
+        }, RespErr::TransformErr)
+    }
+}
+
+impl Req for GetCompletionsReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetCompletionsReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        let limit = self.limit.unwrap_or(DEFAULT_COMPLETIONS);
+        match ed.buffer.completions(&self.point, &[], limit) {
+            Ok(completions) => Resp(Ok(RespOk::CompletionsOk(completions))),
+            Err(err) => Resp(Err(RespErr::CompletionErr(err)))
+        }
+    }
+}
+
+/// Selects every range of `stop` for `client_id`, so that typing replaces
+/// the placeholder and its mirrors at once.
+fn select_tab_stop(ed: &mut Editor, client_id: &str, stop: &TabStop) {
+    ed.selections.insert(client_id.to_string(), SelectionSet::new(stop.ranges.clone()));
+}
+
+impl EditReq for InsertSnippetReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for InsertSnippetReq {:?}", self);
+        let result = ed.buffer.insert_snippet(&self.snippet, &self.point);
+        ed.sync_buffer(None);
+        match result {
+            Ok((lines, stop)) => {
+                select_tab_stop(ed, &self.client_id, &stop);
+                Resp(Ok(RespOk::SnippetOk(SnippetRespStruct {
+                    lines: lines,
+                    stop: stop
+                })))
+            }
+            Err(err) => Resp(Err(RespErr::SnippetErr(err)))
+        }
+    }
+}
+
+impl EditReq for NextTabStopReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for NextTabStopReq {:?}", self);
+        let result = ed.buffer.next_tab_stop();
+        ed.sync_buffer(None);
+        match result {
+            Ok(stop) => {
+                select_tab_stop(ed, &self.client_id, &stop);
+                Resp(Ok(RespOk::TabStopOk(stop)))
+            }
+            Err(err) => Resp(Err(RespErr::SnippetErr(err)))
+        }
+    }
+}
+
+impl EditReq for PrevTabStopReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for PrevTabStopReq {:?}", self);
+        let result = ed.buffer.prev_tab_stop();
+        ed.sync_buffer(None);
+        match result {
+            Ok(stop) => {
+                select_tab_stop(ed, &self.client_id, &stop);
+                Resp(Ok(RespOk::TabStopOk(stop)))
+            }
+            Err(err) => Resp(Err(RespErr::SnippetErr(err)))
+        }
+    }
+}
+
+impl EditReq for CancelSnippetReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for CancelSnippetReq {:?}", self);
+        ed.buffer.cancel_snippet();
+        Resp(Ok(RespOk::Ok))
+    }
+}
+
+impl Req for LoadAbbreviationsReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for LoadAbbreviationsReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        match ed.abbreviations.load(&self.path) {
+            Ok(n) => {
+                debug!("Loaded {} abbreviations", n);
+                ed.update_abbreviations();
+                Resp(Ok(RespOk::Ok))
+            }
+            Err(err) => Resp(Err(RespErr::AbbrevErr(err)))
+        }
+    }
+}
+
+impl Req for SetAbbreviationReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for SetAbbreviationReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        ed.abbreviations.set(self.language.as_ref().map(|l| &l[..]), &self.trigger,
+                             self.expansion.as_ref().map(|e| &e[..]));
+        ed.update_abbreviations();
+        Resp(Ok(RespOk::Ok))
+    }
+}
+
+impl Req for GetAbbreviationsReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetAbbreviationsReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        let language = self.language.clone()
+            .or_else(|| ed.buffer.grammar().map(|grammar| grammar.name));
+        let table = ed.abbreviations.table_for(language.as_ref().map(|l| &l[..]));
+        Resp(Ok(RespOk::AbbreviationsOk(table.into_iter().collect())))
+    }
+}
+
+impl Req for DiffFileReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for DiffFileReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        let path = match (self.path.as_ref(), ed.file.as_ref()) {
+            (Some(path), _) => path.clone(),
+            (None, Some(file)) => file.path.clone(),
+            (None, None) => return Resp(Err(RespErr::NoFile))
+        };
+        let file = match read_file(&path) {
+            Ok(contents) => Buffer::with_contents(&contents),
+            Err(err) => return Resp(Err(RespErr::FileErr(err.to_string())))
+        };
+        let context = self.context.unwrap_or(DEFAULT_DIFF_CONTEXT);
+        Resp(Ok(RespOk::DiffOk(DiffRespStruct {
+            diff: file.unified_diff(&ed.buffer, &path, &path, context),
+            changes: file.diff_chars(&ed.buffer)
+        })))
+    }
+}
+
+impl EditReq for ApplyPatchReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for ApplyPatchReq {:?}", self);
+        edit_buffer(ed, |buffer| buffer.apply_patch(&self.patch), RespErr::PatchErr)
+    }
+}
+
+impl Req for OpenFileReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for OpenFileReq {:?}", self);
+        let lazy = match self.lazy {
+            Some(lazy) => lazy,
+            None => fs::metadata(&self.path).map_or(false, |meta| meta.len() > LAZY_THRESHOLD)
+        };
+        if lazy {
+            let indexed = match IndexedFile::open(&self.path) {
+                Ok(indexed) => indexed,
+                Err(err) => return Resp(Err(RespErr::FileErr(err.to_string())))
+            };
+            {
+                let mut ed = editor.lock().unwrap();
+                ed.replace_buffer(Buffer::new());
+                let mut lazy = LazyBuffer::new(indexed);
+                lazy.options = ed.buffer.options.clone();
+                lazy.abbreviations = ed.buffer.abbreviations.clone();
+                lazy.grammar = ed.buffer.grammar();
+                ed.changes = lazy.observe();
+                ed.lazy = Some(lazy);
+            }
+            set_file(editor, &self.path, None);
+            return Resp(Ok(RespOk::Ok));
+        }
+        let contents = match read_file(&self.path) {
+            Ok(contents) => contents,
+            Err(err) => return Resp(Err(RespErr::FileErr(err.to_string())))
+        };
+        {
+            let mut ed = editor.lock().unwrap();
+            ed.replace_buffer(Buffer::with_contents(&contents));
+            ed.lazy = None;
+        }
+        set_file(editor, &self.path, Some(contents));
+        Resp(Ok(RespOk::Ok))
+    }
+}
+
+impl Req for SaveFileReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for SaveFileReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        let current = ed.file.as_ref().map(|file| file.path.clone());
+        let path = match (self.path.as_ref(), current) {
+            (Some(path), _) => path.clone(),
+            (None, Some(path)) => path,
+            (None, None) => return Resp(Err(RespErr::NoFile))
+        };
+        // Build the text from a snapshot so that the buffer can go on being
+        // edited meanwhile
+        let snapshot = ed.buffer.snapshot();
+        drop(ed);
+        let contents = snapshot.to_str();
+        // but write it with the editor locked, so that the file's watcher
+        // doesn't see the write until the file's base has caught up with it
+        let mut ed = editor.lock().unwrap();
+        if let Err(err) = write_file(&path, &contents) {
+            return Resp(Err(RespErr::FileErr(err.to_string())));
+        }
+        match ed.file {
+            Some(ref mut file) if file.path == path => {
+                file.base = Some(contents);
+                return Resp(Ok(RespOk::Ok));
+            }
+            _ => {}
+        }
+        drop(ed);
+        set_file(editor, &path, Some(contents));
+        Resp(Ok(RespOk::Ok))
+    }
+}
+
+impl Req for ReloadFileReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for ReloadFileReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        let (path, base) = match ed.file {
+            Some(ref file) => (file.path.clone(), file.base.clone()),
+            None => return Resp(Err(RespErr::NoFile))
+        };
+        let contents = match read_file(&path) {
+            Ok(contents) => contents,
+            Err(err) => return Resp(Err(RespErr::FileErr(err.to_string())))
+        };
+        let disk = Buffer::with_contents(&contents);
+        let result = match self.strategy {
+            ReloadStrategy::Discard => {
+                ed.buffer.replace_contents(&disk).map(|lines| (lines, vec![]))
+            }
+            ReloadStrategy::Keep => Ok((vec![], vec![])),
+            ReloadStrategy::Merge => {
+                let base = Buffer::with_contents(base.as_ref().map_or("", |base| &base[..]));
+                ed.buffer.merge(&base, &disk)
+            }
+        };
+        ed.sync_buffer(None);
+        match result {
+            Ok((lines, conflicts)) => {
+                if let Some(ref mut file) = ed.file {
+                    file.base = Some(contents);
+                }
+                Resp(Ok(RespOk::ReloadOk(ReloadRespStruct {
+                    lines: lines,
+                    conflicts: conflicts
+                })))
+            }
+            Err(err) => Resp(Err(RespErr::ReloadErr(err)))
+        }
+    }
+}
+
+impl EditReq for ProtectRegionReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for ProtectRegionReq {:?}", self);
+        match ed.buffer.protect_region(&self.start, &self.end) {
+            Ok(_) => Resp(Ok(RespOk::Ok)),
+            Err(err) => Resp(Err(RespErr::ProtectErr(err)))
+        }
+    }
+}
+
+impl EditReq for UnprotectRegionReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for UnprotectRegionReq {:?}", self);
+        match ed.buffer.unprotect_region(&self.start, &self.end) {
+            Ok(_) => Resp(Ok(RespOk::Ok)),
+            Err(err) => Resp(Err(RespErr::ProtectErr(err)))
+        }
+    }
+}
+
+impl Req for GetProtectedRegionsReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetProtectedRegionsReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        let regions = ed.buffer.protected_regions().into_iter().map(|(start, end)| {
+            RangeRespStruct {
+                start: start,
+                end: end
+            }
+        });
+        Resp(Ok(RespOk::RangesOk(regions.collect())))
+    }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct InsertAtPtReq {
-    #[serde(rename = "clientId")]
-    pub client_id: String,
-    pub method: Method,
-    pub point: Point,
-    pub string: String
+impl EditReq for AddPropertyReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for AddPropertyReq {:?}", self);
+        match ed.buffer.add_property(&self.start, &self.end, self.properties.clone()) {
+            Ok(id) => Resp(Ok(RespOk::PropertyIdOk(id))),
+            Err(err) => Resp(Err(RespErr::PropertyErr(err)))
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct GetLinesReq {
-    #[serde(rename = "clientId")]
-    pub client_id: String,
-    pub method: Method,
+impl EditReq for RemovePropertyReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for RemovePropertyReq {:?}", self);
+        match ed.buffer.remove_property(self.id) {
+            Ok(_) => Resp(Ok(RespOk::Ok)),
+            Err(err) => Resp(Err(RespErr::PropertyErr(err)))
+        }
+    }
 }
 
-pub trait Req {
-    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp;
+impl Req for GetPropertiesReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetPropertiesReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        Resp(Ok(RespOk::PropertiesOk(ed.buffer.properties())))
+    }
 }
 
-impl Req for ConnectReq {
+impl Req for WatchChangesReq {
     fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
-        debug!("Calling Message exec() for ConnectReq {:?}", self);
+        debug!("Calling Message exec() for WatchChangesReq {:?}", self);
         let mut ed = editor.lock().unwrap();
-        match ed.client_id {
-            Some(_) => {
-                Resp(Err(RespErr::ClientAlreadyConnected))
+        if self.watch {
+            ed.change_watchers.insert(self.client_id.clone());
+        } else {
+            ed.change_watchers.remove(&self.client_id);
+        }
+        Resp(Ok(RespOk::Ok))
+    }
+}
+
+impl Req for TextObjectReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for TextObjectReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        match ed.buffer.text_object(&self.point, self.object, self.inner) {
+            Ok((start, end)) => {
+                Resp(Ok(RespOk::RangeOk(RangeRespStruct {
+                    start: start,
+                    end: end
+                })))
             }
-            None => {
-                ed.client_id = Some(self.client_id.clone());
-                Resp(Ok(RespOk::ConnectResp(ConnRespStruct {
-                    server_id: ed.server_id.to_string()
+            Err(err) => Resp(Err(RespErr::TextObjectErr(err)))
+        }
+    }
+}
+
+impl EditReq for OperatorReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for OperatorReq {:?}", self);
+        let register = match parse_register(&self.register) {
+            Ok(register) => register,
+            Err(err) => return Resp(Err(RespErr::RegisterErr(err)))
+        };
+        if let Err(err) = ed.registers.check_writable(register) {
+            return Resp(Err(RespErr::RegisterErr(err)));
+        }
+        let result = ed.buffer.apply_operator(self.operator, &self.point, self.target);
+        ed.sync_buffer(None);
+        let op_result = match result {
+            Ok(op_result) => op_result,
+            Err(err) => return Resp(Err(RespErr::OperatorErr(err)))
+        };
+        let command = ed.command;
+        let stored = match self.operator {
+            Operator::Delete | Operator::Change => {
+                let end = op_result.point.advanced_by(&op_result.text);
+                ed.registers.kill(register, op_result.text.clone(), op_result.point, end, command)
+            }
+            Operator::Yank => ed.registers.store(register, op_result.text.clone()),
+            _ => Ok(())
+        };
+        match stored {
+            Ok(()) => Resp(Ok(RespOk::OperatorOk(op_result))),
+            Err(err) => Resp(Err(RespErr::RegisterErr(err)))
+        }
+    }
+}
+
+impl EditReq for DeleteRegionReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for DeleteRegionReq {:?}", self);
+        let register = match parse_register(&self.register) {
+            Ok(register) => register,
+            Err(err) => return Resp(Err(RespErr::RegisterErr(err)))
+        };
+        if let Err(err) = ed.registers.check_writable(register) {
+            return Resp(Err(RespErr::RegisterErr(err)));
+        }
+        let result = match ed.lazy {
+            Some(ref mut lazy) => lazy.delete_region(&self.start, &self.end),
+            None => ed.buffer.delete_region(&self.start, &self.end)
+        };
+        ed.sync_buffer(None);
+        let text = match result {
+            Ok(text) => text,
+            Err(err) => return Resp(Err(RespErr::DeleteRegionErr(err)))
+        };
+        let command = ed.command;
+        match ed.registers.kill(register, text, self.start, self.end, command) {
+            Ok(()) => {
+                let (first, last) = (self.start.row(), self.start.row() + 1);
+                Resp(Ok(RespOk::LinesOk(match ed.lazy {
+                    Some(ref lazy) => lazy.get_lines(first, last),
+                    None => ed.buffer.get_lines(first, last)
                 })))
             }
+            Err(err) => Resp(Err(RespErr::RegisterErr(err)))
+        }
+    }
+}
+
+impl EditReq for YankReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for YankReq {:?}", self);
+        let register = match parse_register(&self.register) {
+            Ok(register) => register,
+            Err(err) => return Resp(Err(RespErr::RegisterErr(err)))
+        };
+        let text = match ed.buffer.region_to_str(&self.start, &self.end) {
+            Ok(text) => text,
+            Err(err) => return Resp(Err(RespErr::YankErr(err)))
+        };
+        match ed.registers.store(register, text) {
+            Ok(()) => Resp(Ok(RespOk::Ok)),
+            Err(err) => Resp(Err(RespErr::RegisterErr(err)))
+        }
+    }
+}
+
+/// Inserts `text` from `register` in place of the region between `start` and
+/// `end` and remembers it as the last paste.
+fn paste(ed: &mut Editor, register: Option<char>, start: &Point, end: &Point, text: &str)
+    -> Resp {
+    let result = ed.buffer.replace_region(start, end, text);
+    ed.sync_buffer(None);
+    match result {
+        Ok(lines) => {
+            let new_end = start.advanced_by(text);
+            let command = ed.command;
+            ed.registers.pasted(register, *start, new_end, command);
+            Resp(Ok(RespOk::PasteOk(PasteRespStruct {
+                lines: lines,
+                start: *start,
+                end: new_end
+            })))
+        }
+        Err(err) => Resp(Err(RespErr::PasteErr(err)))
+    }
+}
+
+impl EditReq for PasteReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for PasteReq {:?}", self);
+        let register = match parse_register(&self.register) {
+            Ok(register) => register,
+            Err(err) => return Resp(Err(RespErr::RegisterErr(err)))
+        };
+        let text = match ed.registers.get(register) {
+            Ok(text) => text,
+            Err(err) => return Resp(Err(RespErr::RegisterErr(err)))
+        };
+        paste(ed, register, &self.point, &self.point, &text)
+    }
+}
+
+impl EditReq for PastePopReq {
+    fn exec(&self, ed: &mut Editor) -> Resp {
+        debug!("Calling Message exec() for PastePopReq {:?}", self);
+        let command = ed.command;
+        match ed.registers.paste_pop(command) {
+            Ok((start, end, text)) => paste(ed, None, &start, &end, &text),
+            Err(err) => Resp(Err(RespErr::RegisterErr(err)))
+        }
+    }
+}
+
+impl Req for GetRegistersReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetRegistersReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        Resp(Ok(RespOk::RegistersOk(RegistersRespStruct {
+            kill_ring: ed.registers.kill_ring.entries(),
+            registers: ed.registers.named()
+        })))
+    }
+}
+
+impl Req for StartRecordingReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for StartRecordingReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        match ed.macros.start_recording(&self.client_id, &self.name, self.point) {
+            Ok(()) => Resp(Ok(RespOk::Ok)),
+            Err(err) => Resp(Err(RespErr::MacroErr(err)))
+        }
+    }
+}
+
+impl Req for StopRecordingReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for StopRecordingReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        match ed.macros.stop_recording(&self.client_id, self.point) {
+            Ok(name) => {
+                debug!("Recorded macro {}", name);
+                Resp(Ok(RespOk::Ok))
+            }
+            Err(err) => Resp(Err(RespErr::MacroErr(err)))
         }
     }
 }
 
-impl Req for InsertAtPtReq {
+/// Plays `mac` starting at `start`, collecting the result of each request in
+/// `results`.
+fn play_macro(mac: &Macro, start: &Point, client_id: &str, ed: &mut Editor,
+              results: &mut Vec<RespOk>) -> Result<(), RespErr> {
+    for req in mac.requests_at(start, client_id) {
+        results.push(exec_edit_method(req, ed).0?);
+    }
+    Ok(())
+}
+
+impl Req for PlayMacroReq {
     fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
-        debug!("Calling Message exec() for InsertAtPtReq {:?}", self);
-        let ref mut buffer = editor.lock().unwrap().buffer;
-        match buffer.insert_at_pt(&self.string, &self.point) {
-            Ok(lines_changed) => {
-                Resp(Ok(RespOk::InsertAtPtOk(lines_changed)))
+        debug!("Calling Message exec() for PlayMacroReq {:?}", self);
+        // Held for the whole playback, so that no other client's edits end
+        // up in the macro's undo step
+        let mut ed = editor.lock().unwrap();
+        let ed = &mut *ed;
+        let mac = match ed.macros.get(&self.name) {
+            Ok(mac) => mac,
+            Err(err) => return Resp(Err(RespErr::MacroErr(err)))
+        };
+        let mut results = vec![];
+        ed.buffer.begin_transaction();
+        let played = match self.end {
+            Some(end) => {
+                // Start from the last line so that lines the macro adds or
+                // removes don't move the ones still to come
+                let mut played = Ok(());
+                for r in (self.point.row()..end.row() + 1).rev() {
+                    let c = cmp::min(self.point.col(), ed.buffer.line_len(r));
+                    played = play_macro(&mac, &Point::new(r, c), &self.client_id, ed,
+                                        &mut results);
+                    if played.is_err() {
+                        break;
+                    }
+                }
+                played
             }
-            Err(err) => {
-                Resp(Err(RespErr::InsertAtPtErr(err)))
+            None => {
+                let mut played = Ok(());
+                let mut start = self.point;
+                for _ in 0..self.count.unwrap_or(1) {
+                    played = play_macro(&mac, &start, &self.client_id, ed, &mut results);
+                    if played.is_err() {
+                        break;
+                    }
+                    start = mac.end_at(&start);
+                }
+                played
             }
+        };
+        if played.is_ok() {
+            ed.buffer.end_transaction();
+        } else {
+            // A macro that fails partway through changes nothing
+            ed.buffer.abort_transaction();
+            ed.sync_buffer(None);
+        }
+        match played {
+            Ok(()) => Resp(Ok(RespOk::MacroOk(results))),
+            Err(err) => Resp(Err(err))
+        }
+    }
+}
+
+impl Req for GetMacrosReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for GetMacrosReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        Resp(Ok(RespOk::MacrosOk(ed.macros.names())))
+    }
+}
+
+impl Req for SaveMacrosReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for SaveMacrosReq {:?}", self);
+        let ed = editor.lock().unwrap();
+        match ed.macros.save(&self.path) {
+            Ok(()) => Resp(Ok(RespOk::Ok)),
+            Err(err) => Resp(Err(RespErr::MacroErr(err)))
+        }
+    }
+}
+
+impl Req for LoadMacrosReq {
+    fn exec(&self, editor: &mut Arc<Mutex<Editor>>) -> Resp {
+        debug!("Calling Message exec() for LoadMacrosReq {:?}", self);
+        let mut ed = editor.lock().unwrap();
+        match ed.macros.load(&self.path) {
+            Ok(_) => Resp(Ok(RespOk::MacrosOk(ed.macros.names()))),
+            Err(err) => Resp(Err(RespErr::MacroErr(err)))
         }
     }
 }
@@ -90,16 +2134,101 @@ pub enum RespErr {
     TestError,
     DeserializationError,
     ClientAlreadyConnected,
-    InsertAtPtErr(BufErr)
+    InsertAtPtErr(BufErr),
+    SelectionErr(BufErr),
+    UndoErr(BufErr),
+    BlockErr(BufErr),
+    MoveErr(BufErr),
+    TextObjectErr(BufErr),
+    OperatorErr(BufErr),
+    DeleteRegionErr(BufErr),
+    RegisterErr(RegErr),
+    PasteErr(BufErr),
+    YankErr(BufErr),
+    MacroErr(MacroErr),
+    UnknownGrammar,
+    SyntaxErr(BufErr),
+    IndentErr(BufErr),
+    ColumnErr(BufErr),
+    WrapErr(BufErr),
+    LineErr(BufErr),
+    TransformErr(BufErr),
+    CompletionErr(BufErr),
+    SnippetErr(BufErr),
+    AbbrevErr(AbbrevErr),
+    FileErr(String),
+    PatchErr(BufErr),
+    NoFile,
+    ProtectErr(BufErr),
+    PropertyErr(BufErr),
+    ReloadErr(BufErr),
+    GrammarErr(GrammarErr),
+    OptionsErr(BufErr),
+    LoadErr(BufErr)
 }
 
 pub enum RespOk {
     ConnectResp(ConnRespStruct),
     InsertAtPtOk(Vec<Line>),
+    SelectionsOk(Vec<Selection>),
+    SelectionEditOk(SelEditRespStruct),
+    LinesOk(Vec<Line>),
+    BlockOk(Vec<String>),
+    PointOk(Point),
+    OptionsOk(Options),
+    RangeOk(RangeRespStruct),
+    OperatorOk(OpResult),
+    PasteOk(PasteRespStruct),
+    RegistersOk(RegistersRespStruct),
+    MacroOk(Vec<RespOk>),
+    MacrosOk(Vec<String>),
+    NodeOk(Node),
+    NodesOk(Vec<Node>),
+    FoldingRangesOk(Vec<FoldingRange>),
+    NewlineOk(NewlineRespStruct),
+    ColOk(usize),
+    DisplayLinesOk(Vec<DisplayLine>),
+    DisplayPointOk(DisplayPointRespStruct),
+    CompletionsOk(Completions),
+    SnippetOk(SnippetRespStruct),
+    TabStopOk(TabStop),
+    AbbreviationsOk(BTreeMap<String, String>),
+    DiffOk(DiffRespStruct),
+    ReloadOk(ReloadRespStruct),
+    RangesOk(Vec<RangeRespStruct>),
+    PropertyIdOk(usize),
+    PropertiesOk(Vec<TextProperty>),
+    GrammarOk(String),
     Ok
 }
 
+impl RespErr {
+    /// Returns the buffer error this wraps, if any.
+    fn buf_err(&self) -> Option<&BufErr> {
+        match self {
+            &RespErr::InsertAtPtErr(ref err) | &RespErr::SelectionErr(ref err) |
+            &RespErr::UndoErr(ref err) | &RespErr::BlockErr(ref err) |
+            &RespErr::MoveErr(ref err) | &RespErr::TextObjectErr(ref err) |
+            &RespErr::OperatorErr(ref err) | &RespErr::DeleteRegionErr(ref err) |
+            &RespErr::PasteErr(ref err) | &RespErr::YankErr(ref err) |
+            &RespErr::SyntaxErr(ref err) | &RespErr::IndentErr(ref err) |
+            &RespErr::ColumnErr(ref err) | &RespErr::WrapErr(ref err) |
+            &RespErr::LineErr(ref err) | &RespErr::TransformErr(ref err) |
+            &RespErr::CompletionErr(ref err) | &RespErr::SnippetErr(ref err) |
+            &RespErr::PatchErr(ref err) | &RespErr::ProtectErr(ref err) |
+            &RespErr::PropertyErr(ref err) | &RespErr::ReloadErr(ref err) |
+            &RespErr::OptionsErr(ref err) | &RespErr::LoadErr(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
 fn resp_err_code(resp_err: &RespErr) -> i32 {
+    // Whatever was being done, being refused because the text is read-only
+    // has its own code so clients can tell it apart from a bad request
+    if let Some(&BufErr::ReadOnly) = resp_err.buf_err() {
+        return 32;
+    }
     match resp_err {
         &RespErr::MalformedInput => 0,
         &RespErr::InvalidMethod => 1,
@@ -107,7 +2236,37 @@ fn resp_err_code(resp_err: &RespErr) -> i32 {
         &RespErr::TestError => 3,
         &RespErr::DeserializationError => 4,
         &RespErr::ClientAlreadyConnected => 5,
-        &RespErr::InsertAtPtErr(_) => 6
+        &RespErr::InsertAtPtErr(_) => 6,
+        &RespErr::SelectionErr(_) => 7,
+        &RespErr::UndoErr(_) => 8,
+        &RespErr::BlockErr(_) => 9,
+        &RespErr::MoveErr(_) => 10,
+        &RespErr::TextObjectErr(_) => 11,
+        &RespErr::OperatorErr(_) => 12,
+        &RespErr::DeleteRegionErr(_) => 13,
+        &RespErr::RegisterErr(_) => 14,
+        &RespErr::PasteErr(_) => 15,
+        &RespErr::YankErr(_) => 16,
+        &RespErr::MacroErr(_) => 17,
+        &RespErr::UnknownGrammar => 18,
+        &RespErr::SyntaxErr(_) => 19,
+        &RespErr::IndentErr(_) => 20,
+        &RespErr::ColumnErr(_) => 21,
+        &RespErr::WrapErr(_) => 22,
+        &RespErr::LineErr(_) => 23,
+        &RespErr::TransformErr(_) => 24,
+        &RespErr::CompletionErr(_) => 25,
+        &RespErr::SnippetErr(_) => 26,
+        &RespErr::AbbrevErr(_) => 27,
+        &RespErr::FileErr(_) => 28,
+        &RespErr::PatchErr(_) => 29,
+        &RespErr::NoFile => 30,
+        &RespErr::ProtectErr(_) => 31,
+        &RespErr::PropertyErr(_) => 33,
+        &RespErr::ReloadErr(_) => 34,
+        &RespErr::GrammarErr(_) => 35,
+        &RespErr::OptionsErr(_) => 36,
+        &RespErr::LoadErr(_) => 37
     }
 }
 
@@ -117,6 +2276,65 @@ pub struct ConnRespStruct {
     pub server_id: String
 }
 
+#[derive(Serialize)]
+pub struct RangeRespStruct {
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Serialize)]
+pub struct PasteRespStruct {
+    pub lines: Vec<Line>,
+    pub start: Point,
+    pub end: Point
+}
+
+#[derive(Serialize)]
+pub struct RegistersRespStruct {
+    #[serde(rename = "killRing")]
+    pub kill_ring: Vec<String>,
+    pub registers: BTreeMap<String, String>
+}
+
+#[derive(Serialize)]
+pub struct NewlineRespStruct {
+    pub lines: Vec<Line>,
+    pub point: Point
+}
+
+#[derive(Serialize)]
+pub struct DisplayPointRespStruct {
+    pub row: usize,
+    pub col: usize
+}
+
+#[derive(Serialize)]
+pub struct SnippetRespStruct {
+    pub lines: Vec<Line>,
+    pub stop: TabStop
+}
+
+/// The difference between a file and the buffer, as a unified diff and as
+/// character-level changes from the file to the buffer.
+#[derive(Serialize)]
+pub struct DiffRespStruct {
+    pub diff: String,
+    pub changes: Vec<Change>
+}
+
+#[derive(Serialize)]
+pub struct ReloadRespStruct {
+    pub lines: Vec<Line>,
+    /// The rows merge conflicts start on
+    pub conflicts: Vec<usize>
+}
+
+#[derive(Serialize)]
+pub struct SelEditRespStruct {
+    pub lines: Vec<Line>,
+    pub selections: Vec<Selection>
+}
+
 impl fmt::Display for RespErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -129,6 +2347,92 @@ impl fmt::Display for RespErr {
             &RespErr::InsertAtPtErr(ref buf_err) => {
                 write!(f, "insert at point error: {}", buf_err.to_string())
             }
+            &RespErr::SelectionErr(ref buf_err) => {
+                write!(f, "selection error: {}", buf_err.to_string())
+            }
+            &RespErr::UndoErr(ref buf_err) => {
+                write!(f, "undo error: {}", buf_err.to_string())
+            }
+            &RespErr::BlockErr(ref buf_err) => {
+                write!(f, "block error: {}", buf_err.to_string())
+            }
+            &RespErr::MoveErr(ref buf_err) => {
+                write!(f, "move error: {}", buf_err.to_string())
+            }
+            &RespErr::TextObjectErr(ref buf_err) => {
+                write!(f, "text object error: {}", buf_err.to_string())
+            }
+            &RespErr::OperatorErr(ref buf_err) => {
+                write!(f, "operator error: {}", buf_err.to_string())
+            }
+            &RespErr::DeleteRegionErr(ref buf_err) => {
+                write!(f, "delete region error: {}", buf_err.to_string())
+            }
+            &RespErr::RegisterErr(ref reg_err) => {
+                write!(f, "register error: {}", reg_err.to_string())
+            }
+            &RespErr::PasteErr(ref buf_err) => {
+                write!(f, "paste error: {}", buf_err.to_string())
+            }
+            &RespErr::YankErr(ref buf_err) => {
+                write!(f, "yank error: {}", buf_err.to_string())
+            }
+            &RespErr::MacroErr(ref macro_err) => {
+                write!(f, "macro error: {}", macro_err.to_string())
+            }
+            &RespErr::UnknownGrammar => { write!(f, "unknown grammar") }
+            &RespErr::SyntaxErr(ref buf_err) => {
+                write!(f, "syntax tree error: {}", buf_err.to_string())
+            }
+            &RespErr::IndentErr(ref buf_err) => {
+                write!(f, "indent error: {}", buf_err.to_string())
+            }
+            &RespErr::ColumnErr(ref buf_err) => {
+                write!(f, "column error: {}", buf_err.to_string())
+            }
+            &RespErr::WrapErr(ref buf_err) => {
+                write!(f, "wrap error: {}", buf_err.to_string())
+            }
+            &RespErr::LineErr(ref buf_err) => {
+                write!(f, "line command error: {}", buf_err.to_string())
+            }
+            &RespErr::TransformErr(ref buf_err) => {
+                write!(f, "transform error: {}", buf_err.to_string())
+            }
+            &RespErr::CompletionErr(ref buf_err) => {
+                write!(f, "completion error: {}", buf_err.to_string())
+            }
+            &RespErr::SnippetErr(ref buf_err) => {
+                write!(f, "snippet error: {}", buf_err.to_string())
+            }
+            &RespErr::AbbrevErr(ref abbrev_err) => {
+                write!(f, "abbreviation error: {}", abbrev_err.to_string())
+            }
+            &RespErr::FileErr(ref err) => {
+                write!(f, "file error: {}", err)
+            }
+            &RespErr::PatchErr(ref buf_err) => {
+                write!(f, "patch error: {}", buf_err.to_string())
+            }
+            &RespErr::NoFile => { write!(f, "buffer has no file") }
+            &RespErr::ProtectErr(ref buf_err) => {
+                write!(f, "protect error: {}", buf_err.to_string())
+            }
+            &RespErr::PropertyErr(ref buf_err) => {
+                write!(f, "property error: {}", buf_err.to_string())
+            }
+            &RespErr::ReloadErr(ref buf_err) => {
+                write!(f, "reload error: {}", buf_err.to_string())
+            }
+            &RespErr::GrammarErr(ref grammar_err) => {
+                write!(f, "grammar error: {}", grammar_err.to_string())
+            }
+            &RespErr::OptionsErr(ref buf_err) => {
+                write!(f, "options error: {}", buf_err.to_string())
+            }
+            &RespErr::LoadErr(ref buf_err) => {
+                write!(f, "load error: {}", buf_err.to_string())
+            }
         }
     }
 }
@@ -163,6 +2467,93 @@ impl Serialize for RespOk {
             &RespOk::InsertAtPtOk(ref l) => {
                 l.serialize(serializer)
             }
+            &RespOk::SelectionsOk(ref s) => {
+                s.serialize(serializer)
+            }
+            &RespOk::SelectionEditOk(ref s) => {
+                s.serialize(serializer)
+            }
+            &RespOk::LinesOk(ref l) => {
+                l.serialize(serializer)
+            }
+            &RespOk::BlockOk(ref s) => {
+                s.serialize(serializer)
+            }
+            &RespOk::PointOk(ref p) => {
+                p.serialize(serializer)
+            }
+            &RespOk::OptionsOk(ref o) => {
+                o.serialize(serializer)
+            }
+            &RespOk::RangeOk(ref r) => {
+                r.serialize(serializer)
+            }
+            &RespOk::OperatorOk(ref r) => {
+                r.serialize(serializer)
+            }
+            &RespOk::PasteOk(ref p) => {
+                p.serialize(serializer)
+            }
+            &RespOk::RegistersOk(ref r) => {
+                r.serialize(serializer)
+            }
+            &RespOk::MacroOk(ref r) => {
+                r.serialize(serializer)
+            }
+            &RespOk::MacrosOk(ref m) => {
+                m.serialize(serializer)
+            }
+            &RespOk::NodeOk(ref n) => {
+                n.serialize(serializer)
+            }
+            &RespOk::NodesOk(ref n) => {
+                n.serialize(serializer)
+            }
+            &RespOk::FoldingRangesOk(ref f) => {
+                f.serialize(serializer)
+            }
+            &RespOk::NewlineOk(ref n) => {
+                n.serialize(serializer)
+            }
+            &RespOk::ColOk(ref c) => {
+                c.serialize(serializer)
+            }
+            &RespOk::DisplayLinesOk(ref l) => {
+                l.serialize(serializer)
+            }
+            &RespOk::DisplayPointOk(ref p) => {
+                p.serialize(serializer)
+            }
+            &RespOk::CompletionsOk(ref c) => {
+                c.serialize(serializer)
+            }
+            &RespOk::SnippetOk(ref s) => {
+                s.serialize(serializer)
+            }
+            &RespOk::TabStopOk(ref t) => {
+                t.serialize(serializer)
+            }
+            &RespOk::AbbreviationsOk(ref a) => {
+                a.serialize(serializer)
+            }
+            &RespOk::DiffOk(ref d) => {
+                d.serialize(serializer)
+            }
+            &RespOk::ReloadOk(ref r) => {
+                r.serialize(serializer)
+            }
+            &RespOk::RangesOk(ref r) => {
+                r.serialize(serializer)
+            }
+            &RespOk::PropertyIdOk(ref i) => {
+                i.serialize(serializer)
+            }
+            &RespOk::PropertiesOk(ref p) => {
+                p.serialize(serializer)
+            }
+            &RespOk::GrammarOk(ref g) => {
+                g.serialize(serializer)
+            }
         }
     }
 }
@@ -185,3 +2576,249 @@ impl Serialize for Resp {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use buffer::{Buffer, BufErr, IntoLine, Point, Span, Style};
+    use editor::Editor;
+    use files::{set_file, write_file};
+    use serde_json;
+    use std::env;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use super::{dispatch, resp_err_code, Resp, RespErr, RespOk};
+
+    fn editor_with(contents: &str) -> Arc<Mutex<Editor>> {
+        let editor = Arc::new(Mutex::new(Editor::new()));
+        editor.lock().unwrap().replace_buffer(Buffer::with_contents(contents));
+        editor
+    }
+
+    fn request(editor: &mut Arc<Mutex<Editor>>, json: &str) -> Resp {
+        dispatch(serde_json::from_str(json).unwrap(), editor)
+    }
+
+    fn insert(editor: &mut Arc<Mutex<Editor>>, string: &str, r: usize, c: usize) -> Resp {
+        request(editor, &format!(r#"{{"clientId": "a", "method": "insertAtPt",
+                                      "string": "{}", "point": {{"r": {}, "c": {}}}}}"#,
+                                 string, r, c))
+    }
+
+    fn record(editor: &mut Arc<Mutex<Editor>>, name: &str, steps: &[(&str, usize, usize)],
+              end: usize) {
+        assert!(request(editor, &format!(r#"{{"clientId": "a", "method": "startRecording",
+                                             "name": "{}", "point": {{"r": 0, "c": 0}}}}"#,
+                                         name)).0.is_ok());
+        for &(string, r, c) in steps {
+            if string == "undo" {
+                assert!(request(editor, r#"{"clientId": "a", "method": "undo"}"#).0.is_ok());
+            } else {
+                assert!(insert(editor, string, r, c).0.is_ok());
+            }
+        }
+        assert!(request(editor, &format!(r#"{{"clientId": "a", "method": "stopRecording",
+                                             "point": {{"r": 0, "c": {}}}}}"#,
+                                         end)).0.is_ok());
+    }
+
+    fn play(editor: &mut Arc<Mutex<Editor>>, name: &str, r: usize, c: usize) -> Resp {
+        request(editor, &format!(r#"{{"clientId": "a", "method": "playMacro", "name": "{}",
+                                      "point": {{"r": {}, "c": {}}}}}"#, name, r, c))
+    }
+
+    fn contents(editor: &Arc<Mutex<Editor>>) -> String {
+        editor.lock().unwrap().buffer.to_str()
+    }
+
+    #[test]
+    fn test_play_macro() {
+        let mut editor = editor_with("abc\ndef\n");
+        record(&mut editor, "m", &[("<", 0, 0), (">", 0, 4)], 5);
+        assert_eq!(contents(&editor), "<abc>\ndef\n");
+        // Requests that fail aren't recorded
+        assert!(insert(&mut editor, "!", 5, 0).0.is_err());
+        match play(&mut editor, "m", 1, 0).0 {
+            Ok(RespOk::MacroOk(results)) => assert_eq!(results.len(), 2),
+            _ => panic!("macro failed")
+        }
+        assert_eq!(contents(&editor), "<abc>\n<def>\n");
+        // The whole playback is one undo step
+        assert!(request(&mut editor, r#"{"clientId": "a", "method": "undo"}"#).0.is_ok());
+        assert_eq!(contents(&editor), "<abc>\ndef\n");
+        assert!(play(&mut editor, "n", 1, 0).0.is_err());
+    }
+
+    #[test]
+    fn test_play_macro_with_undo() {
+        let mut editor = editor_with("abc\ndef\n");
+        record(&mut editor, "m", &[("x", 0, 0), ("y", 0, 1), ("undo", 0, 0)], 1);
+        assert_eq!(contents(&editor), "xabc\ndef\n");
+        // The undo takes back the macro's own insertion rather than an
+        // earlier change
+        assert!(play(&mut editor, "m", 1, 0).0.is_ok());
+        assert_eq!(contents(&editor), "xabc\nxdef\n");
+        assert!(request(&mut editor, r#"{"clientId": "a", "method": "undo"}"#).0.is_ok());
+        assert_eq!(contents(&editor), "xabc\ndef\n");
+    }
+
+    #[test]
+    fn test_play_macro_failing() {
+        let mut editor = editor_with("abcdef\nde\n");
+        record(&mut editor, "m", &[("x", 0, 0), ("!", 0, 7)], 8);
+        assert_eq!(contents(&editor), "xabcdef!\nde\n");
+        match play(&mut editor, "m", 1, 0).0 {
+            Err(RespErr::InsertAtPtErr(BufErr::InvalidPoint)) => {}
+            _ => panic!("macro didn't fail")
+        }
+        assert_eq!(contents(&editor), "xabcdef!\nde\n");
+        assert!(request(&mut editor, r#"{"clientId": "a", "method": "undo"}"#).0.is_ok());
+        assert_eq!(contents(&editor), "xabcdef\nde\n");
+    }
+
+    #[test]
+    fn test_macro_leaves_out_settings() {
+        let mut editor = editor_with("abc\n");
+        assert!(request(&mut editor, r#"{"clientId": "a", "method": "startRecording",
+                                        "name": "m", "point": {"r": 0, "c": 0}}"#).0.is_ok());
+        assert!(insert(&mut editor, "x", 0, 0).0.is_ok());
+        assert!(request(&mut editor, r#"{"clientId": "a", "method": "setOptions",
+                                        "options": {"wordChars": "_", "tabWidth": 2}}"#).0.is_ok());
+        assert!(request(&mut editor, r#"{"clientId": "a", "method": "stopRecording",
+                                        "point": {"r": 0, "c": 1}}"#).0.is_ok());
+        match play(&mut editor, "m", 0, 0).0 {
+            Ok(RespOk::MacroOk(results)) => assert_eq!(results.len(), 1),
+            _ => panic!("macro failed")
+        }
+    }
+
+    #[test]
+    fn test_reload_read_only() {
+        let mut editor = editor_with("abc\n");
+        let path = env::temp_dir().join("reload_read_only.txt");
+        let path = path.to_str().unwrap();
+        write_file(path, "abc\n").unwrap();
+        set_file(&editor, path, Some("abc\n".to_string()));
+        write_file(path, "def\n").unwrap();
+        editor.lock().unwrap().buffer.options.read_only = true;
+        let resp = request(&mut editor, r#"{"clientId": "a", "method": "reloadFile",
+                                           "strategy": "discard"}"#);
+        match resp.0 {
+            Err(ref err) => assert_eq!(resp_err_code(err), 32),
+            Ok(_) => panic!("read-only buffer was reloaded")
+        }
+        assert_eq!(contents(&editor), "abc\n");
+        editor.lock().unwrap().file = None;
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_only() {
+        let mut editor = editor_with("abc\n");
+        assert!(insert(&mut editor, "x", 0, 0).0.is_ok());
+        assert!(request(&mut editor, r#"{"clientId": "a", "method": "setOptions",
+                                        "options": {"wordChars": "_", "readOnly": true}}"#)
+                .0.is_ok());
+        for resp in vec![insert(&mut editor, "y", 0, 0),
+                         request(&mut editor, r#"{"clientId": "a", "method": "undo"}"#)] {
+            match resp.0 {
+                Err(ref err) => assert_eq!(resp_err_code(err), 32),
+                Ok(_) => panic!("read-only buffer was changed")
+            }
+        }
+        assert_eq!(contents(&editor), "xabc\n");
+    }
+
+    #[test]
+    fn test_load_grammar() {
+        let mut editor = editor_with("let x = 1; // y\n");
+        let path = env::temp_dir().join("load_grammar.json");
+        let path = path.to_str().unwrap();
+        write_file(path, r##"{
+            "scopeName": "source.toy",
+            "patterns": [
+                {"include": "#comment"},
+                {"name": "keyword.toy", "match": "\\blet\\b"}
+            ],
+            "repository": {
+                "comment": {"name": "comment.line.toy", "match": "//.*$"}
+            }
+        }"##).unwrap();
+        match request(&mut editor, &format!(r#"{{"clientId": "a", "method": "loadGrammar",
+                                               "path": "{}"}}"#, path)).0 {
+            Ok(RespOk::GrammarOk(name)) => assert_eq!(name, "source.toy"),
+            _ => panic!("loadGrammar failed")
+        }
+        assert!(request(&mut editor, r#"{"clientId": "a", "method": "setGrammar",
+                                        "name": "source.toy"}"#).0.is_ok());
+        assert_eq!(editor.lock().unwrap().buffer.get_lines(0, 1)[0].spans, vec![
+            Span { start: 0, end: 3, style: Style::Keyword },
+            Span { start: 11, end: 15, style: Style::Comment }
+        ]);
+        fs::remove_file(path).unwrap();
+        let resp = request(&mut editor, &format!(r#"{{"clientId": "a", "method": "loadGrammar",
+                                                    "path": "{}"}}"#, path));
+        match resp.0 {
+            Err(ref err) => assert_eq!(resp_err_code(err), 35),
+            Ok(_) => panic!("missing grammar was loaded")
+        }
+    }
+
+    #[test]
+    fn test_set_options_zero_tab_width() {
+        let mut editor = editor_with("\tabc\n");
+        let resp = request(&mut editor, r#"{"clientId": "a", "method": "setOptions",
+                                           "options": {"wordChars": "_", "tabWidth": 0}}"#);
+        match resp.0 {
+            Err(ref err) => assert_eq!(resp_err_code(err), 36),
+            Ok(_) => panic!("tab width of zero was accepted")
+        }
+        let ed = editor.lock().unwrap();
+        assert_eq!(ed.buffer.options.tab_width, 8);
+        assert_eq!(ed.buffer.visual_col_at(&Point::new(0, 1)), 8);
+    }
+
+    #[test]
+    fn test_lazy_file() {
+        let mut editor = editor_with("");
+        let path = env::temp_dir().join("lazy_file.txt");
+        let path = path.to_str().unwrap();
+        write_file(path, "one\ntwo\nthree\n").unwrap();
+        assert!(request(&mut editor, &format!(r#"{{"clientId": "a", "method": "openFile",
+                                                 "path": "{}", "lazy": true}}"#, path))
+                .0.is_ok());
+        let resp = request(&mut editor, r#"{"clientId": "a", "method": "deleteRegion",
+                                           "start": {"r": 1, "c": 0},
+                                           "end": {"r": 1, "c": 2}}"#);
+        match resp.0 {
+            Ok(RespOk::LinesOk(lines)) => assert_eq!(lines, vec!["o".into_line(1)]),
+            _ => panic!("deleteRegion failed")
+        }
+        assert!(editor.lock().unwrap().lazy.is_some());
+        editor.lock().unwrap().load_lazy().unwrap();
+        assert_eq!(contents(&editor), "one\no\nthree\n");
+        editor.lock().unwrap().file = None;
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lazy_file_changed() {
+        let mut editor = editor_with("");
+        let path = env::temp_dir().join("lazy_file_changed.txt");
+        let path = path.to_str().unwrap();
+        write_file(path, "one\ntwo\n").unwrap();
+        assert!(request(&mut editor, &format!(r#"{{"clientId": "a", "method": "openFile",
+                                                 "path": "{}", "lazy": true}}"#, path))
+                .0.is_ok());
+        assert!(insert(&mut editor, "x", 1, 3).0.is_ok());
+        write_file(path, "one\n").unwrap();
+        // The edit can't be made again on rows that have gone
+        let resp = request(&mut editor, r#"{"clientId": "a", "method": "undo"}"#);
+        match resp.0 {
+            Err(ref err) => assert_eq!(resp_err_code(err), 37),
+            Ok(_) => panic!("changed file was read")
+        }
+        assert!(editor.lock().unwrap().lazy.is_some());
+        editor.lock().unwrap().file = None;
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,9 +1,227 @@
 extern crate buffer;
+extern crate serde_json;
 extern crate uuid;
-use buffer::Buffer;
+use abbrevs::Abbreviations;
+use buffer::{Buffer, BufErr, ChangeEvent, Edit, FoldingRange, LazyBuffer, SelectionSet};
+use files::OpenFile;
+use grammars::Grammars;
+use indexed::IndexedFile;
+use macros::Macros;
+use registers::Registers;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{Receiver, Sender};
 
 pub struct Editor {
     pub client_id: Option<String>,
     pub server_id: uuid::Uuid,
-    pub buffer: Buffer
+    pub buffer: Buffer,
+    // A file too big to read up front, which stands in for the buffer until
+    // a request needs all of it
+    pub lazy: Option<LazyBuffer<IndexedFile>>,
+    // Selections in the buffer, keyed by client id
+    pub selections: HashMap<String, SelectionSet>,
+    pub registers: Registers,
+    pub macros: Macros,
+    pub abbreviations: Abbreviations,
+    pub grammars: Grammars,
+    // The file the buffer was opened from or last saved to
+    pub file: Option<OpenFile>,
+    // Notifications for clients connected to the output port, keyed by
+    // client id, along with which connection each came from
+    pub outboxes: HashMap<String, (u64, Sender<Value>)>,
+    // Number of connections made to the output port so far
+    pub connections: u64,
+    // Folding ranges last sent to each client that asked for them
+    pub folding_ranges: HashMap<String, Vec<FoldingRange>>,
+    // Changes to the buffer not yet sent to the clients watching for them
+    pub changes: Receiver<ChangeEvent>,
+    pub change_watchers: HashSet<String>,
+    // Number of requests executed so far, used to tell whether two commands
+    // were consecutive
+    pub command: u64
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        let mut buffer = Buffer::new();
+        let changes = buffer.observe();
+        Editor {
+            client_id: None,
+            server_id: uuid::Uuid::new_v4(),
+            buffer: buffer,
+            lazy: None,
+            selections: HashMap::new(),
+            registers: Registers::new(),
+            macros: Macros::new(),
+            abbreviations: Abbreviations::new(),
+            grammars: Grammars::new(),
+            file: None,
+            outboxes: HashMap::new(),
+            connections: 0,
+            folding_ranges: HashMap::new(),
+            changes: changes,
+            change_watchers: HashSet::new(),
+            command: 0
+        }
+    }
+
+    /// Moves the selections of every client except `except` to follow
+    /// `edits`.
+    pub fn shift_selections(&mut self, edits: &[Edit], except: Option<&str>) {
+        for (client_id, sels) in self.selections.iter_mut() {
+            if Some(client_id.as_str()) != except {
+                sels.shift(edits);
+            }
+        }
+    }
+
+    /// Catches everything tracking positions in the buffer up with the edits
+    /// made since the last call. `client_id`'s selections are left alone if
+    /// it has already placed them.
+    pub fn sync_buffer(&mut self, client_id: Option<&str>) {
+        self.buffer.update_snippet_mirrors();
+        let edits = self.buffer.take_changes();
+        self.shift_selections(&edits, client_id);
+        if edits.len() > 0 {
+            self.update_folding_ranges();
+        }
+        self.send_changes();
+    }
+
+    /// Replaces the buffer with `buffer`, keeping the old one's options and
+    /// grammar. Clients' selections are dropped, since they were in the old
+    /// buffer.
+    pub fn replace_buffer(&mut self, mut buffer: Buffer) {
+        // Changes still to be sent come from the old buffer's observer
+        self.send_changes();
+        buffer.options = self.buffer.options.clone();
+        buffer.set_grammar(self.buffer.grammar());
+        buffer.abbreviations = self.buffer.abbreviations.clone();
+        self.changes = buffer.observe();
+        self.buffer = buffer;
+        self.selections.clear();
+        self.update_folding_ranges();
+    }
+
+    /// Reads the whole of a lazily opened file into the buffer, keeping the
+    /// edits made to it so far. Fails, leaving the file as it was, if it has
+    /// changed on disk since it was opened.
+    pub fn load_lazy(&mut self) -> Result<(), BufErr> {
+        let mut buffer = match self.lazy {
+            Some(ref lazy) => {
+                debug!("Reading the rest of the file");
+                lazy.to_buffer()?
+            }
+            None => return Ok(())
+        };
+        let lazy = self.lazy.take().unwrap();
+        if let Some(ref mut file) = self.file {
+            if file.base.is_none() {
+                file.base = Some(String::from_utf8_lossy(&lazy.source().contents()).into_owned());
+            }
+        }
+        // Nothing was tracking positions in the rows these were made to, and
+        // observers were told about them as they were made
+        buffer.take_changes();
+        self.replace_buffer(buffer);
+        Ok(())
+    }
+
+    /// Gives the buffer the abbreviations for its grammar, after either has
+    /// changed.
+    pub fn update_abbreviations(&mut self) {
+        let language = self.buffer.grammar().map(|grammar| grammar.name);
+        self.buffer.abbreviations = self.abbreviations.table_for(language.as_ref().map(|n| &n[..]));
+    }
+
+    /// Sends `method` with `params` to `client_id` if it's connected to the
+    /// output port.
+    pub fn notify(&mut self, client_id: &str, method: &str, params: Value) {
+        let mut notification = Map::new();
+        notification.insert("method".to_string(), Value::String(method.to_string()));
+        notification.insert("params".to_string(), params);
+        let sent = match self.outboxes.get(client_id) {
+            Some(&(_, ref outbox)) => outbox.send(Value::Object(notification)).is_ok(),
+            None => return
+        };
+        if !sent {
+            // The client has disconnected
+            self.outboxes.remove(client_id);
+        }
+    }
+
+    /// Sends notifications for `client_id` to `outbox` from now on, in place
+    /// of any connection it made before. Returns the new connection's id.
+    pub fn add_outbox(&mut self, client_id: &str, outbox: Sender<Value>) -> u64 {
+        self.connections += 1;
+        self.outboxes.insert(client_id.to_string(), (self.connections, outbox));
+        self.connections
+    }
+
+    /// Stops sending notifications for `client_id` if they still go to
+    /// connection `id`, rather than one the client has made since.
+    pub fn remove_outbox(&mut self, client_id: &str, id: u64) {
+        if self.outboxes.get(client_id).map_or(false, |&(other, _)| other == id) {
+            self.outboxes.remove(client_id);
+        }
+    }
+
+    /// Notifies every client that has asked for folding ranges if they've
+    /// changed.
+    fn update_folding_ranges(&mut self) {
+        if self.folding_ranges.len() == 0 {
+            return;
+        }
+        let ranges = self.buffer.folding_ranges();
+        let changed = self.folding_ranges.iter()
+            .filter(|&(_, sent)| *sent != ranges)
+            .map(|(client_id, _)| client_id.clone())
+            .collect::<Vec<_>>();
+        for client_id in changed {
+            if let Ok(params) = serde_json::to_value(&ranges) {
+                self.notify(&client_id, "foldingRanges", params);
+            }
+            self.folding_ranges.insert(client_id, ranges.clone());
+        }
+    }
+
+    /// Sends every change made to the buffer since the last call to the
+    /// clients watching for them.
+    fn send_changes(&mut self) {
+        let events = self.changes.try_iter().collect::<Vec<_>>();
+        let outboxes = &self.outboxes;
+        self.change_watchers.retain(|client_id| outboxes.contains_key(client_id));
+        if events.len() == 0 || self.change_watchers.len() == 0 {
+            return;
+        }
+        let client_ids = self.change_watchers.iter().cloned().collect::<Vec<_>>();
+        for client_id in client_ids {
+            for event in &events {
+                if let Ok(params) = serde_json::to_value(event) {
+                    self.notify(&client_id, "bufferChanged", params);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use std::sync::mpsc;
+    use super::Editor;
+
+    #[test]
+    fn test_reconnect_outbox() {
+        let mut ed = Editor::new();
+        let (old, _old_receiver) = mpsc::channel();
+        let old_connection = ed.add_outbox("a", old);
+        let (new, new_receiver) = mpsc::channel();
+        ed.add_outbox("a", new);
+        // The old connection closing doesn't take the new one's outbox
+        ed.remove_outbox("a", old_connection);
+        ed.notify("a", "ping", Value::Null);
+        assert!(new_receiver.try_recv().is_ok());
+    }
 }
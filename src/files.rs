@@ -0,0 +1,236 @@
+extern crate serde_json;
+
+use editor::Editor;
+use serde_json::{Map, Value};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// How often the watcher looks for changes
+const WATCH_INTERVAL_MS: u64 = 250;
+
+/// What to do with the buffer after its file has changed on disk.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ReloadStrategy {
+    /// Replace the buffer with the file, losing unsaved edits
+    #[serde(rename = "discard")]
+    Discard,
+    /// Keep the buffer as it is, so that saving it overwrites the file
+    #[serde(rename = "keep")]
+    Keep,
+    /// Merge the changes on disk with the unsaved edits
+    #[serde(rename = "merge")]
+    Merge
+}
+
+/// The file backing the buffer.
+pub struct OpenFile {
+    pub path: String,
+    /// What the file held when it was last opened, saved or reloaded, which
+    /// is what merges take both sides' changes from. None until a lazily
+    /// opened file has been read.
+    pub base: Option<String>,
+    // Tells the file's watcher to stop once another file is opened
+    stop: Arc<AtomicBool>
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+pub fn read_file(path: &str) -> io::Result<String> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+pub fn write_file(path: &str, contents: &str) -> io::Result<()> {
+    File::create(path)?.write_all(contents.as_bytes())
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::ffi::CString;
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    const IN_NONBLOCK: c_int = 0o4000;
+    const IN_CLOEXEC: c_int = 0o2000000;
+    const IN_MODIFY: u32 = 0x2;
+    const IN_ATTRIB: u32 = 0x4;
+    const IN_CLOSE_WRITE: u32 = 0x8;
+    const IN_MOVED_FROM: u32 = 0x40;
+    const IN_MOVED_TO: u32 = 0x80;
+    const IN_CREATE: u32 = 0x100;
+    const IN_DELETE: u32 = 0x200;
+    // The size of struct inotify_event without its name
+    const EVENT_SIZE: usize = 16;
+
+    extern "C" {
+        fn inotify_init1(flags: c_int) -> c_int;
+        fn inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> c_int;
+        fn read(fd: c_int, buf: *mut c_void, count: usize) -> isize;
+        fn close(fd: c_int) -> c_int;
+    }
+
+    /// Watches a file's directory rather than the file, since most editors
+    /// save by replacing the file with a new one.
+    pub struct Inotify {
+        fd: c_int,
+        name: Vec<u8>
+    }
+
+    impl Inotify {
+        pub fn new(path: &str) -> Option<Inotify> {
+            let path = Path::new(path);
+            let dir = match path.parent() {
+                Some(dir) if dir.as_os_str().len() > 0 => dir,
+                _ => Path::new(".")
+            };
+            let name = match path.file_name() {
+                Some(name) => name.as_bytes().to_vec(),
+                None => return None
+            };
+            let dir = match CString::new(dir.as_os_str().as_bytes()) {
+                Ok(dir) => dir,
+                Err(_) => return None
+            };
+            let mask = IN_MODIFY | IN_ATTRIB | IN_CLOSE_WRITE | IN_MOVED_FROM | IN_MOVED_TO |
+                IN_CREATE | IN_DELETE;
+            unsafe {
+                let fd = inotify_init1(IN_NONBLOCK | IN_CLOEXEC);
+                if fd < 0 {
+                    return None;
+                }
+                if inotify_add_watch(fd, dir.as_ptr(), mask) < 0 {
+                    close(fd);
+                    return None;
+                }
+                Some(Inotify { fd: fd, name: name })
+            }
+        }
+
+        /// Reads the events queued since the last call and returns whether
+        /// any were about the file.
+        pub fn changed(&mut self) -> bool {
+            let mut changed = false;
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = unsafe { read(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+                if n <= 0 {
+                    return changed;
+                }
+                let mut i = 0;
+                while i + EVENT_SIZE <= n as usize {
+                    let len = buf[i + 12] as usize | (buf[i + 13] as usize) << 8 |
+                        (buf[i + 14] as usize) << 16 | (buf[i + 15] as usize) << 24;
+                    let name = &buf[i + EVENT_SIZE..i + EVENT_SIZE + len];
+                    let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(len)];
+                    changed |= name == &self.name[..];
+                    i += EVENT_SIZE + len;
+                }
+            }
+        }
+    }
+
+    impl Drop for Inotify {
+        fn drop(&mut self) {
+            unsafe {
+                close(self.fd);
+            }
+        }
+    }
+}
+
+enum Watcher {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::Inotify),
+    /// Compares the file's modification time and size with the last ones seen
+    Poll(String, Option<(SystemTime, u64)>)
+}
+
+fn stat(path: &str) -> Option<(SystemTime, u64)> {
+    fs::metadata(path).ok().and_then(|meta| meta.modified().ok().map(|time| (time, meta.len())))
+}
+
+impl Watcher {
+    #[cfg(target_os = "linux")]
+    fn new(path: &str) -> Watcher {
+        match inotify::Inotify::new(path) {
+            Some(inotify) => Watcher::Inotify(inotify),
+            None => Watcher::Poll(path.to_string(), stat(path))
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new(path: &str) -> Watcher {
+        Watcher::Poll(path.to_string(), stat(path))
+    }
+
+    fn changed(&mut self) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            &mut Watcher::Inotify(ref mut inotify) => inotify.changed(),
+            &mut Watcher::Poll(ref path, ref mut last) => {
+                let now = stat(path);
+                let changed = now != *last;
+                *last = now;
+                changed
+            }
+        }
+    }
+}
+
+/// Makes `path` the buffer's file, with `contents` as what it holds, and
+/// starts watching it for changes. `editor` mustn't be locked.
+pub fn set_file(editor: &Arc<Mutex<Editor>>, path: &str, contents: Option<String>) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut watcher = Watcher::new(path);
+    editor.lock().unwrap().file = Some(OpenFile {
+        path: path.to_string(),
+        base: contents,
+        stop: stop.clone()
+    });
+    let editor = editor.clone();
+    thread::spawn(move || {
+        while !stop.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(WATCH_INTERVAL_MS));
+            if watcher.changed() {
+                let mut ed = editor.lock().unwrap();
+                if !stop.load(Ordering::SeqCst) {
+                    ed.check_file();
+                }
+            }
+        }
+        debug!("File watcher exiting");
+    });
+}
+
+impl Editor {
+    /// Notifies every client if the file no longer holds what the buffer was
+    /// last synced with, which isn't the case after the buffer saves it.
+    fn check_file(&mut self) {
+        let (path, deleted) = match self.file {
+            Some(ref file) => match read_file(&file.path) {
+                Ok(ref contents) if Some(contents) == file.base.as_ref() => return,
+                Ok(_) => (file.path.clone(), false),
+                Err(_) => (file.path.clone(), true)
+            },
+            None => return
+        };
+        debug!("{} changed on disk", path);
+        let mut params = Map::new();
+        params.insert("path".to_string(), Value::String(path));
+        params.insert("deleted".to_string(), Value::Bool(deleted));
+        let client_ids = self.outboxes.keys().cloned().collect::<Vec<_>>();
+        for client_id in client_ids {
+            self.notify(&client_id, "fileChanged", Value::Object(params.clone()));
+        }
+    }
+}
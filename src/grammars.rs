@@ -0,0 +1,55 @@
+extern crate serde_json;
+
+use buffer::{Grammar, TextMateGrammar};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+
+#[derive(PartialEq, Debug)]
+pub enum GrammarErr {
+    Io(String)
+}
+
+impl fmt::Display for GrammarErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &GrammarErr::Io(ref err) => { write!(f, "i/o error: {}", err) }
+        }
+    }
+}
+
+/// Grammars loaded from files, keyed by name. They're offered alongside the
+/// builtin ones, which a loaded grammar of the same name replaces.
+pub struct Grammars {
+    loaded: BTreeMap<String, Grammar>
+}
+
+impl Grammars {
+    pub fn new() -> Grammars {
+        Grammars {
+            loaded: BTreeMap::new()
+        }
+    }
+
+    /// Returns the grammar called `name`.
+    pub fn get(&self, name: &str) -> Option<Grammar> {
+        self.loaded.get(name).cloned().or_else(|| Grammar::builtin(name))
+    }
+
+    /// Reads a TextMate grammar in its JSON form from `path`. Returns the
+    /// name it's loaded under, which is the grammar's own name if it has one
+    /// and its scope name otherwise.
+    pub fn load(&mut self, path: &str) -> Result<String, GrammarErr> {
+        let mut json = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut json))
+            .map_err(|err| GrammarErr::Io(err.to_string()))?;
+        let textmate: TextMateGrammar = serde_json::from_str(&json)
+            .map_err(|err| GrammarErr::Io(err.to_string()))?;
+        let grammar = Grammar::from_textmate(textmate);
+        let name = grammar.name.clone();
+        self.loaded.insert(name.clone(), grammar);
+        Ok(name)
+    }
+}
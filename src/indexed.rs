@@ -0,0 +1,132 @@
+extern crate buffer;
+
+use buffer::LineSource;
+use memmap::{Mmap, MmapViewSync, Protection};
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::time::SystemTime;
+
+/// A file mapped into memory with where each row starts, so that any row can
+/// be read without reading the ones before it. Rows are only read when
+/// they're asked for, so if something else changes the file meanwhile they
+/// come out as whatever is there now, cut short where the file ends.
+pub struct IndexedFile {
+    file: File,
+    // None for an empty file, which can't be mapped
+    map: Option<MmapViewSync>,
+    len: usize,
+    modified: Option<SystemTime>,
+    rows: Vec<usize>
+}
+
+impl IndexedFile {
+    pub fn open(path: &str) -> io::Result<IndexedFile> {
+        let file = File::open(path)?;
+        let meta = file.metadata()?;
+        let map = if meta.len() == 0 {
+            None
+        } else {
+            Some(Mmap::open(&file, Protection::Read)?.into_view_sync())
+        };
+        let len = map.as_ref().map_or(0, |map| map.len());
+        let mut rows = vec![0];
+        rows.extend(IndexedFile::bytes_of(&map, 0, len).iter().enumerate()
+            .filter(|&(_, &b)| b == b'\n')
+            .map(|(i, _)| i + 1));
+        // A newline at the end of the file doesn't start another row
+        if rows.len() > 1 && rows[rows.len() - 1] == len {
+            rows.pop();
+        }
+        Ok(IndexedFile {
+            file: file,
+            map: map,
+            len: len,
+            modified: meta.modified().ok(),
+            rows: rows
+        })
+    }
+
+    fn bytes_of(map: &Option<MmapViewSync>, start: usize, end: usize) -> &[u8] {
+        match map {
+            // The map is only read, and never past where the file ends
+            &Some(ref map) => unsafe { &map.as_slice()[start..end] },
+            &None => &[]
+        }
+    }
+
+    /// Returns the bytes from `start` up to `end`, or as many of them as the
+    /// file still has. Reading mapped memory past the end of a file that's
+    /// been cut short would crash, so its length is checked first.
+    fn bytes(&self, start: usize, end: usize) -> &[u8] {
+        let len = self.file.metadata().map(|meta| meta.len()).unwrap_or(0);
+        let len = cmp::min(len, self.len as u64) as usize;
+        IndexedFile::bytes_of(&self.map, cmp::min(start, len), cmp::min(end, len))
+    }
+
+    /// Reads the whole file as it was indexed.
+    pub fn contents(&self) -> Vec<u8> {
+        self.bytes(0, self.len).to_vec()
+    }
+}
+
+impl LineSource for IndexedFile {
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn line(&self, r: usize) -> Vec<char> {
+        let end = self.rows.get(r + 1).cloned().unwrap_or(self.len);
+        let mut bytes = self.bytes(self.rows[r], end);
+        if bytes.last() == Some(&b'\n') {
+            bytes = &bytes[..bytes.len() - 1];
+        }
+        String::from_utf8_lossy(bytes).chars().collect()
+    }
+
+    fn changed(&self) -> bool {
+        match self.file.metadata() {
+            Ok(meta) => meta.len() != self.len as u64 || meta.modified().ok() != self.modified,
+            Err(_) => true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::LineSource;
+    use files::write_file;
+    use std::env;
+    use std::fs;
+    use super::IndexedFile;
+
+    #[test]
+    fn test_indexed_file() {
+        let path = env::temp_dir().join("indexed_file.txt");
+        let path = path.to_str().unwrap();
+        write_file(path, "one\ntwo\nthree").unwrap();
+        let file = IndexedFile::open(path).unwrap();
+        assert_eq!(file.len(), 3);
+        assert_eq!(file.line(1), vec!['t', 'w', 'o']);
+        assert_eq!(file.line(2), vec!['t', 'h', 'r', 'e', 'e']);
+        assert_eq!(file.contents(), b"one\ntwo\nthree".to_vec());
+        assert!(!file.changed());
+        // Rows that have gone since come out short rather than failing
+        write_file(path, "on").unwrap();
+        assert!(file.changed());
+        assert_eq!(file.line(0), vec!['o', 'n']);
+        assert_eq!(file.line(2), vec![]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_indexed_empty_file() {
+        let path = env::temp_dir().join("indexed_empty_file.txt");
+        let path = path.to_str().unwrap();
+        write_file(path, "").unwrap();
+        let file = IndexedFile::open(path).unwrap();
+        assert_eq!(file.len(), 1);
+        assert_eq!(file.line(0), vec![]);
+        fs::remove_file(path).unwrap();
+    }
+}
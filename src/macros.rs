@@ -0,0 +1,235 @@
+extern crate buffer;
+extern crate serde_json;
+
+use buffer::Point;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+
+// Methods that edit the buffer or move selections, which are the only ones
+// worth replaying. Ones that read files or change settings are left out,
+// since undoing a macro couldn't undo them.
+const RECORDABLE_METHODS: &'static [&'static str] = &[
+    "insertAtPt",
+    "deleteRegion",
+    "setSelections",
+    "insertAtSelections",
+    "deleteSelections",
+    "deleteBlock",
+    "insertBlock",
+    "insertAtBlock",
+    "move",
+    "operator",
+    "yank",
+    "paste",
+    "pastePop",
+    "undo",
+    "newline",
+    "reindentRegion",
+    "joinLines",
+    "duplicateLines",
+    "duplicateRegion",
+    "moveLines",
+    "sortLines",
+    "uniqueLines",
+    "reverseLines",
+    "trimTrailingWhitespace",
+    "changeCase",
+    "transpose",
+    "toggleLineComment",
+    "toggleBlockComment",
+    "insertSnippet",
+    "nextTabStop",
+    "prevTabStop",
+    "cancelSnippet",
+    "applyPatch",
+    "protectRegion",
+    "unprotectRegion",
+    "addProperty",
+    "removeProperty"
+];
+
+pub fn is_recordable(method: &str) -> bool {
+    RECORDABLE_METHODS.contains(&method)
+}
+
+#[derive(PartialEq, Debug)]
+pub enum MacroErr {
+    AlreadyRecording,
+    NotRecording,
+    NoSuchMacro,
+    Io(String)
+}
+
+impl fmt::Display for MacroErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &MacroErr::AlreadyRecording => { write!(f, "already recording a macro") }
+            &MacroErr::NotRecording => { write!(f, "not recording a macro") }
+            &MacroErr::NoSuchMacro => { write!(f, "no such macro") }
+            &MacroErr::Io(ref err) => { write!(f, "i/o error: {}", err) }
+        }
+    }
+}
+
+/// A recorded sequence of requests. Points in the requests are replayed
+/// relative to where the client's cursor was when recording started.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Macro {
+    pub start: Point,
+    pub end: Point,
+    pub requests: Vec<Value>
+}
+
+/// Returns where `pt` ends up if `from` is moved to `to`. Columns only move
+/// on the same row as `from`.
+fn translate_point(pt: &Point, from: &Point, to: &Point) -> Point {
+    let r = (to.row() + pt.row()).saturating_sub(from.row());
+    if pt.row() == from.row() {
+        Point::new(r, (to.col() + pt.col()).saturating_sub(from.col()))
+    } else {
+        Point::new(r, pt.col())
+    }
+}
+
+/// Translates every point in `value`, which is any object with just an `r`
+/// and a `c` field.
+fn translate(value: &mut Value, from: &Point, to: &Point) {
+    match value {
+        &mut Value::Object(ref mut map) => {
+            if map.len() == 2 && map.contains_key("r") && map.contains_key("c") {
+                let pt = serde_json::from_value::<Point>(Value::Object(map.clone()));
+                if let Ok(pt) = pt {
+                    if let Ok(Value::Object(translated)) =
+                        serde_json::to_value(translate_point(&pt, from, to)) {
+                        *map = translated;
+                    }
+                    return;
+                }
+            }
+            for (_, v) in map.iter_mut() {
+                translate(v, from, to);
+            }
+        }
+        &mut Value::Array(ref mut values) => {
+            for v in values.iter_mut() {
+                translate(v, from, to);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Macro {
+    /// Returns the requests to play the macro starting at `start` on behalf
+    /// of `client_id`.
+    pub fn requests_at(&self, start: &Point, client_id: &str) -> Vec<Value> {
+        self.requests.iter().map(|req| {
+            let mut req = req.clone();
+            translate(&mut req, &self.start, start);
+            if let Value::Object(ref mut map) = req {
+                map.insert("clientId".to_string(), Value::String(client_id.to_string()));
+            }
+            req
+        }).collect()
+    }
+
+    /// Returns where the cursor ends up after playing the macro at `start`.
+    pub fn end_at(&self, start: &Point) -> Point {
+        translate_point(&self.end, &self.start, start)
+    }
+}
+
+struct Recording {
+    name: String,
+    start: Point,
+    requests: Vec<Value>
+}
+
+/// Macros being recorded, keyed by client id, and finished macros, keyed by
+/// name.
+pub struct Macros {
+    recordings: HashMap<String, Recording>,
+    macros: BTreeMap<String, Macro>
+}
+
+impl Macros {
+    pub fn new() -> Macros {
+        Macros {
+            recordings: HashMap::new(),
+            macros: BTreeMap::new()
+        }
+    }
+
+    pub fn start_recording(&mut self, client_id: &str, name: &str, start: Point)
+        -> Result<(), MacroErr> {
+        if self.recordings.contains_key(client_id) {
+            return Err(MacroErr::AlreadyRecording);
+        }
+        self.recordings.insert(client_id.to_string(), Recording {
+            name: name.to_string(),
+            start: start,
+            requests: vec![]
+        });
+        Ok(())
+    }
+
+    pub fn is_recording(&self, client_id: &str) -> bool {
+        self.recordings.contains_key(client_id)
+    }
+
+    /// Adds `req` to the macro `client_id` is recording, if any.
+    pub fn record(&mut self, client_id: &str, req: Value) {
+        if let Some(recording) = self.recordings.get_mut(client_id) {
+            recording.requests.push(req);
+        }
+    }
+
+    /// Finishes `client_id`'s recording with the cursor at `end` and returns
+    /// the macro's name.
+    pub fn stop_recording(&mut self, client_id: &str, end: Point) -> Result<String, MacroErr> {
+        let recording = match self.recordings.remove(client_id) {
+            Some(recording) => recording,
+            None => return Err(MacroErr::NotRecording)
+        };
+        self.macros.insert(recording.name.clone(), Macro {
+            start: recording.start,
+            end: end,
+            requests: recording.requests
+        });
+        Ok(recording.name)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Macro, MacroErr> {
+        self.macros.get(name).cloned().ok_or(MacroErr::NoSuchMacro)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.macros.keys().cloned().collect()
+    }
+
+    /// Writes every macro to `path` as JSON.
+    pub fn save(&self, path: &str) -> Result<(), MacroErr> {
+        let json = serde_json::to_string_pretty(&self.macros)
+            .map_err(|err| MacroErr::Io(err.to_string()))?;
+        File::create(path)
+            .and_then(|mut file| file.write_all(json.as_bytes()))
+            .map_err(|err| MacroErr::Io(err.to_string()))
+    }
+
+    /// Reads macros saved by save(), replacing any with the same names, and
+    /// returns how many were read.
+    pub fn load(&mut self, path: &str) -> Result<usize, MacroErr> {
+        let mut json = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut json))
+            .map_err(|err| MacroErr::Io(err.to_string()))?;
+        let macros: BTreeMap<String, Macro> = serde_json::from_str(&json)
+            .map_err(|err| MacroErr::Io(err.to_string()))?;
+        let n = macros.len();
+        self.macros.extend(macros);
+        Ok(n)
+    }
+}
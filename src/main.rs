@@ -4,6 +4,7 @@ extern crate serde_json;
 extern crate buffer;
 extern crate byteorder;
 extern crate color_logger;
+extern crate memmap;
 extern crate uuid;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
@@ -11,18 +12,26 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::net::{TcpListener, TcpStream};
 use std::thread;
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
 use std::io::{Read, Write, ErrorKind, Cursor};
 use log::LogLevelFilter;
 
-use buffer::{Buffer, IntoLine};
+use buffer::IntoLine;
 use serde_json::Value;
 
+mod abbrevs;
 mod actions;
 use actions::*;
 
 mod editor;
 use editor::Editor;
 
+mod files;
+mod grammars;
+mod indexed;
+mod macros;
+mod registers;
+
 const IN_PORT: i16 = 8765;
 const OUT_PORT: i16 = 8766;
 const PACKET_SIZE_BYTES: usize = 4;
@@ -75,32 +84,7 @@ fn in_handle_client(mut editor: Arc<Mutex<Editor>>, mut stream: TcpStream) {
         let resp = match input {
             Ok(input) => {
                 debug!("{:?}", input);
-                match input["method"].as_str() {
-                    Some("connect") => {
-                        let connect_input: Result<ConnectReq, serde_json::error::Error> =
-                            serde_json::from_value(input);
-                        match connect_input {
-                            Ok(inp) => inp.exec(&mut editor),
-                            Err(_) => Resp(Err(RespErr::DeserializationError))
-                        }
-                    }
-                    Some("insertAtPt") => {
-                        let insert_input: Result<InsertAtPtReq, serde_json::error::Error> =
-                            serde_json::from_value(input);
-                        match insert_input {
-                            Ok(inp) => inp.exec(&mut editor),
-                            Err(_) => Resp(Err(RespErr::DeserializationError))
-                        }
-                    }
-                    Some(&_) => {
-                        warn!("Invalid method: {}", input);
-                        Resp(Err(RespErr::InvalidMethod))
-                    }
-                    None => {
-                        error!("Missing method: {}", input);
-                        Resp(Err(RespErr::MissingMethod))
-                    }
-                }
+                dispatch(input, &mut editor)
             }
             Err(err) => {
                 error!("Illegal input: {}", err);
@@ -140,18 +124,64 @@ fn in_handle_client(mut editor: Arc<Mutex<Editor>>, mut stream: TcpStream) {
     }
 }
 
-fn out_handle_client(stream: TcpStream) {
+/// Sends notifications to a client, which first identifies itself with a
+/// packet containing its `clientId`.
+fn out_handle_client(editor: Arc<Mutex<Editor>>, mut stream: TcpStream) {
     debug!("Outbound connection to {}", stream.peer_addr().unwrap().ip());
+    let mut recv_size_buf = [0u8; PACKET_SIZE_BYTES];
+    if let Err(e) = stream.read_exact(&mut recv_size_buf) {
+        error!("Stream read error: {}", e);
+        return;
+    }
+    let recv_size = match Cursor::new(&recv_size_buf).read_u32::<LittleEndian>() {
+        Ok(size) => size,
+        Err(e) => {
+            panic!("Size conversion error: {}", e);
+        }
+    };
+    let mut recv_buf = vec![0; recv_size as usize];
+    if let Err(e) = stream.read_exact(&mut recv_buf[..]) {
+        error!("Stream read error: {}", e);
+        return;
+    }
+    let input: serde_json::Result<Value> = serde_json::from_slice(&recv_buf[..]);
+    let client_id = match input {
+        Ok(ref input) if input["clientId"].is_string() => {
+            input["clientId"].as_str().unwrap().to_string()
+        }
+        _ => {
+            error!("Illegal output port handshake");
+            return;
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let connection = editor.lock().unwrap().add_outbox(&client_id, sender);
+    for notification in receiver {
+        let s = serde_json::to_string(&notification).unwrap();
+        let sb = s.as_bytes();
+        let mut send_buf = vec![];
+        match send_buf.write_u32::<LittleEndian>(sb.len() as u32) {
+            Ok(_) => {}
+            Err(err) => {
+                panic!("Size conversion error: {}", err);
+            }
+        }
+        send_buf.extend_from_slice(sb);
+        if let Err(err) = stream.write_all(&send_buf[..]) {
+            info!("Client {} disconnected from output port: {}", client_id, err);
+            break;
+        }
+        debug!("Sent notification to client {}: {}", client_id, s);
+    }
+    editor.lock().unwrap().remove_outbox(&client_id, connection);
 }
 
 fn main() {
     color_logger::init(LogLevelFilter::Debug).unwrap();
 
-    let editor = Arc::new(Mutex::new(Editor {
-        client_id: None,
-        server_id: uuid::Uuid::new_v4(),
-        buffer: Buffer::new()
-    }));
+    let editor = Arc::new(Mutex::new(Editor::new()));
+    let out_editor = editor.clone();
 
     {
         let mut ed = editor.lock().unwrap();
@@ -187,7 +217,11 @@ fn main() {
         for stream in out_listener.incoming() {
             match stream {
                 Ok(stream) => {
-
+                    let editor = out_editor.clone();
+                    thread::spawn(move || {
+                        out_handle_client(editor, stream);
+                        debug!("Client output thread exiting");
+                    });
                 }
                 Err(e) => {
                     error!("Out listener error: {}", e);
@@ -0,0 +1,298 @@
+extern crate buffer;
+
+use buffer::Point;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+// Same as Emacs's default kill-ring-max
+const KILL_RING_SIZE: usize = 60;
+
+#[derive(PartialEq, Debug)]
+pub enum RegErr {
+    InvalidRegister,
+    EmptyRegister,
+    ReadOnlyRegister,
+    NoPreviousPaste,
+    NotFromKillRing
+}
+
+impl fmt::Display for RegErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &RegErr::InvalidRegister => { write!(f, "invalid register") }
+            &RegErr::EmptyRegister => { write!(f, "register is empty") }
+            &RegErr::ReadOnlyRegister => { write!(f, "register is read-only") }
+            &RegErr::NoPreviousPaste => { write!(f, "previous command was not a paste") }
+            &RegErr::NotFromKillRing => { write!(f, "last paste was not from the kill ring") }
+        }
+    }
+}
+
+/// Killed and copied text, most recent first.
+pub struct KillRing {
+    entries: VecDeque<String>,
+    // Entry that the last paste or paste pop inserted
+    yank_index: usize
+}
+
+impl KillRing {
+    pub fn new() -> KillRing {
+        KillRing {
+            entries: VecDeque::new(),
+            yank_index: 0
+        }
+    }
+
+    pub fn push(&mut self, text: String) {
+        self.entries.push_front(text);
+        self.entries.truncate(KILL_RING_SIZE);
+        self.yank_index = 0;
+    }
+
+    /// Adds `text` to the most recent entry, before it if `prepend` is set.
+    pub fn append(&mut self, text: &str, prepend: bool) {
+        if self.entries.len() == 0 {
+            self.push(text.to_string());
+            return;
+        }
+        let entry = self.entries.front_mut().unwrap();
+        if prepend {
+            entry.insert_str(0, text);
+        } else {
+            entry.push_str(text);
+        }
+        self.yank_index = 0;
+    }
+
+    /// Returns the `n`th most recent entry and starts cycling from it.
+    pub fn get(&mut self, n: usize) -> Option<&String> {
+        if n < self.entries.len() {
+            self.yank_index = n;
+        }
+        self.entries.get(n)
+    }
+
+    /// Returns the most recent entry and starts cycling from it.
+    pub fn current(&mut self) -> Option<&String> {
+        self.yank_index = 0;
+        self.entries.front()
+    }
+
+    /// Returns the entry before the one last returned, wrapping around to
+    /// the most recent one.
+    pub fn rotate(&mut self) -> Option<&String> {
+        if self.entries.len() == 0 {
+            return None;
+        }
+        self.yank_index = (self.yank_index + 1) % self.entries.len();
+        self.entries.get(self.yank_index)
+    }
+
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+/// A register name as sent by a client: `a` to `z` are named registers
+/// (upper case appends to them), `0` to `9` read from the kill ring, `_`
+/// discards text and `"` or no name at all is the kill ring.
+pub fn parse_register(name: &Option<String>) -> Result<Option<char>, RegErr> {
+    let name = match name {
+        &Some(ref name) => name,
+        &None => return Ok(None)
+    };
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some('"'), None) => Ok(None),
+        (Some(ch), None) if ch.is_ascii_alphanumeric() || ch == '_' => Ok(Some(ch)),
+        _ => Err(RegErr::InvalidRegister)
+    }
+}
+
+/// The kill ring and named registers, shared by every buffer.
+pub struct Registers {
+    pub kill_ring: KillRing,
+    named: BTreeMap<char, String>,
+    // Command number and resulting point of the last kill, so that the next
+    // command can add to it if it kills next to the same point
+    last_kill: Option<(u64, Point)>,
+    // Command number, inserted region and register of the last paste or
+    // paste pop
+    last_paste: Option<(u64, Point, Point, Option<char>)>
+}
+
+impl Registers {
+    pub fn new() -> Registers {
+        Registers {
+            kill_ring: KillRing::new(),
+            named: BTreeMap::new(),
+            last_kill: None,
+            last_paste: None
+        }
+    }
+
+    /// Stores text deleted between `start` and `end` by command number
+    /// `command`. Like Emacs, consecutive kills at the same point are merged
+    /// into one kill ring entry.
+    pub fn kill(&mut self, register: Option<char>, text: String, start: Point, end: Point,
+                command: u64) -> Result<(), RegErr> {
+        if register.is_some() {
+            return self.store(register, text);
+        }
+        match self.last_kill {
+            Some((last_command, pt)) if last_command + 1 == command && pt == start => {
+                self.kill_ring.append(&text, false);
+            }
+            Some((last_command, pt)) if last_command + 1 == command && pt == end => {
+                self.kill_ring.append(&text, true);
+            }
+            _ => self.kill_ring.push(text)
+        }
+        self.last_kill = Some((command, start));
+        Ok(())
+    }
+
+    /// Returns an error if text can't be stored in `register`, so that it
+    /// can be checked before the text is deleted.
+    pub fn check_writable(&self, register: Option<char>) -> Result<(), RegErr> {
+        match register {
+            Some(ch) if ch.is_ascii_digit() => Err(RegErr::ReadOnlyRegister),
+            _ => Ok(())
+        }
+    }
+
+    /// Stores `text` in `register`, or as a new kill ring entry.
+    pub fn store(&mut self, register: Option<char>, text: String) -> Result<(), RegErr> {
+        self.check_writable(register)?;
+        match register {
+            None => self.kill_ring.push(text),
+            Some('_') => {}
+            Some(ch) if ch.is_ascii_uppercase() => {
+                self.named.entry(ch.to_ascii_lowercase()).or_insert(String::new()).push_str(&text);
+            }
+            Some(ch) => {
+                self.named.insert(ch, text);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the text to paste from `register`.
+    pub fn get(&mut self, register: Option<char>) -> Result<String, RegErr> {
+        let text = match register {
+            None => self.kill_ring.current().cloned(),
+            Some(ch) if ch.is_ascii_digit() => {
+                self.kill_ring.get(ch.to_digit(10).unwrap() as usize).cloned()
+            }
+            Some(ch) => self.named.get(&ch.to_ascii_lowercase()).cloned()
+        };
+        text.ok_or(RegErr::EmptyRegister)
+    }
+
+    /// Remembers that command number `command` pasted text from `register`
+    /// between `start` and `end`.
+    pub fn pasted(&mut self, register: Option<char>, start: Point, end: Point, command: u64) {
+        self.last_paste = Some((command, start, end, register));
+    }
+
+    /// Returns the region inserted by the last paste and the kill ring entry
+    /// to replace it with, if the last paste was command number `command - 1`
+    /// and came from the kill ring.
+    pub fn paste_pop(&mut self, command: u64) -> Result<(Point, Point, String), RegErr> {
+        let (start, end) = match self.last_paste {
+            Some((last_command, start, end, register)) if last_command + 1 == command => {
+                match register {
+                    Some(ch) if !ch.is_ascii_digit() => return Err(RegErr::NotFromKillRing),
+                    _ => (start, end)
+                }
+            }
+            _ => return Err(RegErr::NoPreviousPaste)
+        };
+        match self.kill_ring.rotate() {
+            Some(text) => Ok((start, end, text.clone())),
+            None => Err(RegErr::EmptyRegister)
+        }
+    }
+
+    /// Returns the contents of the named registers.
+    pub fn named(&self) -> BTreeMap<String, String> {
+        self.named.iter().map(|(name, text)| (name.to_string(), text.clone())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::Point;
+    use super::{KILL_RING_SIZE, KillRing, RegErr, Registers};
+
+    #[test]
+    fn test_consecutive_kills() {
+        let mut regs = Registers::new();
+        // Deleting forwards from the same point appends
+        regs.kill(None, "foo".to_string(), Point::new(0, 2), Point::new(0, 5), 1).unwrap();
+        regs.kill(None, " bar".to_string(), Point::new(0, 2), Point::new(0, 6), 2).unwrap();
+        assert_eq!(regs.kill_ring.entries(), vec!["foo bar"]);
+        // and backwards up to it prepends
+        regs.kill(None, "x".to_string(), Point::new(0, 1), Point::new(0, 2), 3).unwrap();
+        assert_eq!(regs.kill_ring.entries(), vec!["xfoo bar"]);
+        // but not once another command has run in between
+        regs.kill(None, "y".to_string(), Point::new(0, 1), Point::new(0, 2), 5).unwrap();
+        assert_eq!(regs.kill_ring.entries(), vec!["y", "xfoo bar"]);
+        // and a named register starts over
+        regs.kill(Some('a'), "z".to_string(), Point::new(0, 1), Point::new(0, 2), 6).unwrap();
+        assert_eq!(regs.get(Some('a')), Ok("z".to_string()));
+    }
+
+    #[test]
+    fn test_kill_ring_size() {
+        let mut ring = KillRing::new();
+        for i in 0..KILL_RING_SIZE + 5 {
+            ring.push(i.to_string());
+        }
+        let entries = ring.entries();
+        assert_eq!(entries.len(), KILL_RING_SIZE);
+        assert_eq!(entries[0], (KILL_RING_SIZE + 4).to_string());
+        assert_eq!(entries[KILL_RING_SIZE - 1], "5");
+    }
+
+    #[test]
+    fn test_paste_pop() {
+        let mut regs = Registers::new();
+        for text in &["a", "b", "c"] {
+            regs.store(None, text.to_string()).unwrap();
+        }
+        assert_eq!(regs.get(None), Ok("c".to_string()));
+        regs.pasted(None, Point::new(0, 0), Point::new(0, 1), 1);
+        assert_eq!(regs.paste_pop(2), Ok((Point::new(0, 0), Point::new(0, 1), "b".to_string())));
+        regs.pasted(None, Point::new(0, 0), Point::new(0, 1), 2);
+        assert_eq!(regs.paste_pop(3), Ok((Point::new(0, 0), Point::new(0, 1), "a".to_string())));
+        regs.pasted(None, Point::new(0, 0), Point::new(0, 1), 3);
+        // Wraps around to the most recent
+        assert_eq!(regs.paste_pop(4), Ok((Point::new(0, 0), Point::new(0, 1), "c".to_string())));
+        assert_eq!(regs.paste_pop(9), Err(RegErr::NoPreviousPaste));
+
+        // Digit registers carry on through the ring from where they are
+        assert_eq!(regs.get(Some('1')), Ok("b".to_string()));
+        regs.pasted(Some('1'), Point::new(0, 0), Point::new(0, 1), 10);
+        assert_eq!(regs.paste_pop(11), Ok((Point::new(0, 0), Point::new(0, 1), "a".to_string())));
+
+        regs.store(Some('q'), "named".to_string()).unwrap();
+        assert_eq!(regs.get(Some('q')), Ok("named".to_string()));
+        regs.pasted(Some('q'), Point::new(0, 0), Point::new(0, 5), 12);
+        assert_eq!(regs.paste_pop(13), Err(RegErr::NotFromKillRing));
+    }
+
+    #[test]
+    fn test_read_only_registers() {
+        let mut regs = Registers::new();
+        assert_eq!(regs.check_writable(Some('3')), Err(RegErr::ReadOnlyRegister));
+        assert_eq!(regs.store(Some('3'), "x".to_string()), Err(RegErr::ReadOnlyRegister));
+        assert_eq!(regs.kill(Some('0'), "x".to_string(), Point::new(0, 0), Point::new(0, 1), 1),
+                   Err(RegErr::ReadOnlyRegister));
+        assert_eq!(regs.kill_ring.entries(), Vec::<String>::new());
+        assert_eq!(regs.check_writable(Some('_')), Ok(()));
+        regs.store(Some('a'), "x".to_string()).unwrap();
+        regs.store(Some('A'), "y".to_string()).unwrap();
+        assert_eq!(regs.get(Some('a')), Ok("xy".to_string()));
+    }
+}